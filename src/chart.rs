@@ -0,0 +1,95 @@
+// standard
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// internal
+use crate::definitions::{MoistureReading, RunOutcome, RunRecord};
+
+// external
+use chrono::{DateTime, Utc};
+use image::{ColorType, ImageEncoder};
+use image::codecs::png::PngEncoder;
+use plotters::prelude::*;
+
+
+const WIDTH: u32 = 960;
+const HEIGHT: u32 = 480;
+const GRID: RGBColor = RGBColor(220, 220, 220);
+/// One line per zone, in the order of their names.
+const MOISTURE: [(RGBColor, &str); 4] = [
+    (RGBColor(30, 110, 200), "blue"),
+    (RGBColor(240, 140, 20), "orange"),
+    (RGBColor(140, 60, 180), "purple"),
+    (RGBColor(120, 80, 40), "brown")
+];
+const RUN_OK: RGBColor = RGBColor(60, 170, 60);
+const RUN_FAILED: RGBColor = RGBColor(210, 50, 50);
+/// Runs that were stopped or skipped, neither done nor broken.
+const RUN_INTERRUPTED: RGBColor = RGBColor(130, 130, 130);
+
+/// Readings of each zone, with the colour of its line.
+fn zone_lines(readings: &[MoistureReading]) -> Vec<(&str, Vec<&MoistureReading>, (RGBColor, &'static str))> {
+    let mut zones: BTreeMap<&str, Vec<&MoistureReading>> = BTreeMap::new();
+    for reading in readings {
+        zones.entry(&reading.zone).or_default().push(reading);
+    }
+
+    zones.into_iter().zip(MOISTURE.iter().cycle()).map(|((zone, readings), color)| (zone, readings, *color)).collect()
+}
+
+/// Which colour each zone's line has, for the caption.
+pub fn zone_colors(readings: &[MoistureReading]) -> Vec<(String, &'static str)> {
+    zone_lines(readings).into_iter().map(|(zone, _, (_, name))| (zone.to_string(), name)).collect()
+}
+
+/// Renders moisture over time as one line per zone, blue for the first, with watering runs
+/// overlaid as vertical green (succeeded), grey (stopped or skipped) or red (failed) lines, and returns a PNG.
+///
+/// The chart has no text on purpose, so it doesn't need any fonts on the Pi.
+/// Gridlines mark 25%, 50% and 75% moisture; everything else goes into the caption.
+pub fn render_moisture_chart(
+    readings: &[MoistureReading],
+    runs: &[RunRecord],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let (from, to) = (from.timestamp(), to.timestamp());
+    let mut pixels = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(16)
+            .build_cartesian_2d(from..to, 0f64..100f64)?;
+
+        chart.plotting_area().draw(&Rectangle::new([(from, 0.0), (to, 100.0)], GRID.stroke_width(1)))?;
+        chart.draw_series([25.0, 50.0, 75.0].iter().map(|level| {
+            PathElement::new(vec![(from, *level), (to, *level)], GRID.stroke_width(1))
+        }))?;
+
+        chart.draw_series(runs.iter().map(|record| {
+            let x = record.run.started_at.timestamp();
+            let color = match record.outcome {
+                RunOutcome::Ok => RUN_OK,
+                RunOutcome::Stopped | RunOutcome::Skipped(_) => RUN_INTERRUPTED,
+                RunOutcome::Failed(_) => RUN_FAILED
+            };
+            PathElement::new(vec![(x, 0.0), (x, 100.0)], color.mix(0.6).stroke_width(2))
+        }))?;
+
+        for (_, readings, (color, _)) in zone_lines(readings) {
+            chart.draw_series(LineSeries::new(
+                readings.iter().map(|reading| (reading.at.timestamp(), reading.moisture)),
+                color.stroke_width(2)
+            ))?;
+        }
+
+        root.present()?;
+    }
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&pixels, WIDTH, HEIGHT, ColorType::Rgb8)?;
+    Ok(png)
+}
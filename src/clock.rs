@@ -0,0 +1,97 @@
+// standard
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// external
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::watch;
+
+
+/// Where the garden gets its time from, so tests can step through days of schedules in milliseconds.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Resolves once the clock reached `deadline`.
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Wall-clock time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        let duration = (deadline - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// Runs `speed` times faster than wall-clock time, starting now. Used by `--simulate`.
+#[derive(Debug, Clone, Copy)]
+pub struct AcceleratedClock {
+    started_at: DateTime<Utc>,
+    started: Instant,
+    speed: f64
+}
+
+impl AcceleratedClock {
+    pub fn new(speed: f64) -> Self {
+        AcceleratedClock { started_at: Utc::now(), started: Instant::now(), speed: speed.max(f64::MIN_POSITIVE) }
+    }
+}
+
+impl Clock for AcceleratedClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = self.started.elapsed().mul_f64(self.speed);
+        self.started_at + chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::max_value())
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        let duration = (deadline - self.now()).to_std().unwrap_or(Duration::ZERO).div_f64(self.speed);
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// Only moves when told to, sleepers wake up as soon as it passes their deadline.
+pub struct ManualClock {
+    now: watch::Sender<DateTime<Utc>>
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        ManualClock { now: watch::channel(start).0 }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.send_replace(now);
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        self.now.send_modify(|now| *now = *now + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        let mut now = self.now.subscribe();
+        async move {
+            while *now.borrow_and_update() < deadline {
+                if now.changed().await.is_err() {
+                    // the clock is gone and will never get there
+                    futures::future::pending::<()>().await;
+                }
+            }
+        }.boxed()
+    }
+}
@@ -1,23 +1,132 @@
-use crate::definitions::{PeriodicTask, PeriodicTaskType};
+use crate::definitions::{CatchUp, PeriodicTask, PeriodicTaskType, Zone};
+use crate::logging::{LogFormat, LogOutput};
+use crate::weather::{WeatherSource};
+
+use chrono_tz::Tz;
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub scheduler_tick_ms: u64,
+    pub log_filter: String,
+    pub log_format: LogFormat,
+    pub log_output: LogOutput,
     pub water_pump_working_cycle_seconds: u64,
     pub messages_in_flight_limit: usize,
+    pub events_in_flight_limit: usize,
+    pub notifier_tick_ms: u64,
+    pub history_db_path: String,
+    pub history_maintenance_interval_seconds: u64,
+    pub history_raw_retention_days: i64,
+    pub history_hourly_retention_days: i64,
+    pub history_runs_retention_days: i64,
+    pub http_api_address: String,
+    pub mqtt_client_id: String,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_keep_alive_seconds: u64,
+    pub mqtt_reconnect_delay_seconds: u64,
+    pub home_assistant_discovery_prefix: String,
+    pub ctl_socket_path: String,
+    pub ctl_request_timeout_seconds: u64,
+    pub simulation_speed: f64,
+    pub simulation_initial_moisture_percent: f64,
+    pub simulation_evaporation_percent_per_hour: f64,
+    pub simulation_pump_percent_per_second: f64,
+    /// Where the garden is, for sunrise and sunset schedules. Longitude is positive east.
+    pub latitude: f64,
+    pub longitude: f64,
+    /// IANA timezone the task schedules run in and the bot shows times in.
+    pub timezone: Tz,
+    /// Skips or scales scheduled watering, nothing changes without it.
+    pub weather: Option<WeatherSource>,
+    pub weather_skip_rain_mm: f64,
+    /// Watering takes `weather_scale_percent_per_degree` longer per degree above the base temperature
+    /// and shorter per degree below, between the min and max scale.
+    pub weather_base_temperature_celsius: f64,
+    pub weather_scale_percent_per_degree: f64,
+    pub weather_min_scale: f64,
+    pub weather_max_scale: f64,
+    /// Input pin of a rain switch, scheduled watering waits while it is wet and for
+    /// `rain_sensor_dry_delay_minutes` after it dried.
+    pub rain_sensor_pin: Option<u64>,
+    /// Most rain switches close when wet and pull the pin low.
+    pub rain_sensor_wet_when_high: bool,
+    pub rain_sensor_poll_ms: u64,
+    /// How long a new level has to hold before it counts.
+    pub rain_sensor_debounce_ms: u64,
+    pub rain_sensor_dry_delay_minutes: u64,
+    pub zones: Vec<Zone>,
     pub periodic_tasks: Vec<PeriodicTask>
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
+
 impl Config {
     pub fn new () -> Self {
         Config {
-            scheduler_tick_ms: 1000,
+            log_filter: "info".to_string(),
+            log_format: LogFormat::Text,
+            log_output: LogOutput::Stdout,
             water_pump_working_cycle_seconds: 60,
             messages_in_flight_limit: 8,
+            events_in_flight_limit: 64,
+            notifier_tick_ms: 1000,
+            history_db_path: "smart_garden.db".to_string(),
+            history_maintenance_interval_seconds: 60 * 60,
+            history_raw_retention_days: 7,
+            history_hourly_retention_days: 90,
+            history_runs_retention_days: 365,
+            http_api_address: "0.0.0.0:8080".to_string(),
+            mqtt_client_id: "smart_garden".to_string(),
+            mqtt_topic_prefix: "smart_garden".to_string(),
+            mqtt_keep_alive_seconds: 30,
+            mqtt_reconnect_delay_seconds: 5,
+            home_assistant_discovery_prefix: "homeassistant".to_string(),
+            ctl_socket_path: "/run/smart_garden/ctl.sock".to_string(),
+            ctl_request_timeout_seconds: 5,
+            simulation_speed: 60.0,
+            simulation_initial_moisture_percent: 60.0,
+            simulation_evaporation_percent_per_hour: 1.5,
+            simulation_pump_percent_per_second: 0.5,
+            latitude: 51.48,
+            longitude: 0.0,
+            timezone: chrono_tz::Europe::London,
+            weather: None,
+            weather_skip_rain_mm: 5.0,
+            weather_base_temperature_celsius: 20.0,
+            weather_scale_percent_per_degree: 3.0,
+            weather_min_scale: 0.5,
+            weather_max_scale: 1.5,
+            rain_sensor_pin: None,
+            rain_sensor_wet_when_high: false,
+            rain_sensor_poll_ms: 1000,
+            rain_sensor_debounce_ms: 5000,
+            rain_sensor_dry_delay_minutes: 6 * 60,
+            zones: vec![
+                Zone { name: "garden".to_string(), pump_pin: 4, moisture_sensor_pin: Some(17) }
+            ],
             periodic_tasks: vec![
-                PeriodicTask { disabled: false, task_type: PeriodicTaskType::WaterPlants, cron: "1/10 * * * * *".to_string() },
-                PeriodicTask { disabled: true, task_type: PeriodicTaskType::ReadMoistureSensorsData, cron: "* * * * * *".to_string() }
+                PeriodicTask { disabled: false, task_type: PeriodicTaskType::WaterPlants, cron: "1/10 * * * * *".to_string(), catch_up: CatchUp::Once { within_hours: 12 } },
+                PeriodicTask { disabled: true, task_type: PeriodicTaskType::ReadMoistureSensorsData, cron: "* * * * * *".to_string(), catch_up: CatchUp::Skip }
             ]
         }
     }
-}
\ No newline at end of file
+
+    /// Used by `--simulate`, keeps the simulated history apart and reads the sensors regularly.
+    pub fn simulation() -> Self {
+        Config {
+            history_db_path: "smart_garden_simulation.db".to_string(),
+            periodic_tasks: vec![
+                PeriodicTask { disabled: false, task_type: PeriodicTaskType::WaterPlants, cron: "0 0 6 * * *".to_string(), catch_up: CatchUp::Once { within_hours: 12 } },
+                PeriodicTask { disabled: false, task_type: PeriodicTaskType::ReadMoistureSensorsData, cron: "0 */10 * * * *".to_string(), catch_up: CatchUp::Skip }
+            ],
+            ..Config::new()
+        }
+    }
+
+    pub fn zone(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.name == name)
+    }
+}
@@ -0,0 +1,232 @@
+// standard
+use std::sync::Arc;
+
+// internal
+use crate::clock::{SharedClock};
+use crate::config::{Config};
+use crate::definitions::{WorkerChannelMessage, SchedulerChannelMessage, SchedulerReply, NotifierChannelMessage, GardenEvent, CatchUp, PeriodicTask, PeriodicTaskType, TaskMetaData, WateringRequest, CorrelationId, Traced};
+use crate::error::{GardenError, GardenResult, MIN_WATERING_SECONDS, MAX_WATERING_SECONDS, send_error};
+use crate::history::{History};
+use crate::schedule::{TaskSchedule};
+use crate::status::{GardenStatus, SharedStatus};
+
+// external
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Sender};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug};
+
+
+/// How many runs a schedule preview shows.
+pub const PREVIEW_RUNS: usize = 5;
+
+/// Single entry point for every control surface (Telegram bot, HTTP API, ...),
+/// so they all validate input the same way and send the same channel messages.
+#[derive(Clone)]
+pub struct Controller {
+    config: &'static Config,
+    clock: SharedClock,
+    worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+    scheduler_channel_sender: mpsc::Sender<Traced<SchedulerChannelMessage>>,
+    notifier_channel_sender: Sender<NotifierChannelMessage>,
+    event_sender: broadcast::Sender<GardenEvent>,
+    status: SharedStatus,
+    correlation_id: Option<CorrelationId>,
+    shutdown: Arc<dyn Fn() + Send + Sync>
+}
+
+impl Controller {
+    pub fn new(
+        config: &'static Config,
+        clock: SharedClock,
+        worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+        scheduler_channel_sender: mpsc::Sender<Traced<SchedulerChannelMessage>>,
+        notifier_channel_sender: Sender<NotifierChannelMessage>,
+        event_sender: broadcast::Sender<GardenEvent>,
+        status: SharedStatus
+    ) -> Self {
+        Controller {
+            config,
+            clock,
+            worker_channel_sender,
+            scheduler_channel_sender,
+            notifier_channel_sender,
+            event_sender,
+            status,
+            correlation_id: None,
+            shutdown: Arc::new(|| std::process::exit(1))
+        }
+    }
+
+    /// Replaces what `shutdown` does, which is exiting the process.
+        pub fn with_shutdown(self, shutdown: impl Fn() + Send + Sync + 'static) -> Controller {
+        Controller { shutdown: Arc::new(shutdown), ..self }
+    }
+
+    /// Tags every message sent through the returned controller with `correlation_id`,
+    /// otherwise each message gets a fresh one.
+    pub fn traced(&self, correlation_id: CorrelationId) -> Controller {
+        Controller { correlation_id: Some(correlation_id), ..self.clone() }
+    }
+
+    pub fn config(&self) -> &'static Config {
+        self.config
+    }
+
+    /// The garden's clock, which runs faster than the wall clock in a simulation.
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub fn water(&self, request: WateringRequest) -> GardenResult<()> {
+        if let Some(zone) = &request.zone {
+            if self.config.zone(zone).is_none() {
+                return Err(GardenError::UnknownZone(zone.clone()));
+            }
+        }
+
+        if request.seconds < MIN_WATERING_SECONDS || request.seconds > MAX_WATERING_SECONDS {
+            return Err(GardenError::InvalidDuration(request.seconds));
+        }
+
+        self.send_to_worker(WorkerChannelMessage::WaterPlants(request))
+    }
+
+    pub fn stop(&self) -> GardenResult<()> {
+        self.send_to_worker(WorkerChannelMessage::Stop)
+    }
+
+    /// Stops watering `zone` if the worker is watering it, other zones and queued runs carry on.
+    pub fn stop_zone(&self, zone: &str) -> GardenResult<()> {
+        if self.config.zone(zone).is_none() {
+            return Err(GardenError::UnknownZone(zone.to_string()));
+        }
+
+        self.send_to_worker(WorkerChannelMessage::StopZone(zone.to_string()))
+    }
+
+    pub async fn list_tasks(&self) -> GardenResult<Vec<TaskMetaData>> {
+        let (reply, response) = oneshot::channel();
+        self.send_to_scheduler(SchedulerChannelMessage::ListPeriodicTasks(reply))?;
+        response.await.map_err(|_| GardenError::ChannelClosed("scheduler"))
+    }
+
+    pub async fn add_task(&self, task: PeriodicTask) -> GardenResult<()> {
+        validate_schedule(&task)?;
+        self.ask_scheduler(|reply| SchedulerChannelMessage::AddPeriodicTask(task, reply)).await
+    }
+
+    pub async fn update_task(&self, task: PeriodicTask) -> GardenResult<()> {
+        validate_schedule(&task)?;
+        self.ask_scheduler(|reply| SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task, reply)).await
+    }
+
+    /// Changes the schedule of a task, and whether it is disabled only if `disabled` is given,
+    /// so a new schedule doesn't re-enable a task or reset its catch-up policy.
+    pub async fn update_task_schedule(&self, task_type: PeriodicTaskType, cron: String, disabled: Option<bool>) -> GardenResult<()> {
+        // a bad schedule doesn't need to bother the scheduler
+        validate_schedule(&PeriodicTask { task_type: task_type.clone(), cron: cron.clone(), disabled: false, catch_up: CatchUp::default() })?;
+
+        let current = self.list_tasks().await?.into_iter()
+            .find(|meta| meta.task.task_type == task_type)
+            .ok_or(GardenError::UnknownTask(task_type))?;
+        self.update_task(PeriodicTask { cron, disabled: disabled.unwrap_or(current.task.disabled), ..current.task }).await
+    }
+
+    /// The next `count` runs of a schedule, without changing any task.
+    pub fn preview_schedule(&self, schedule: &str, count: usize) -> GardenResult<Vec<DateTime<Utc>>> {
+        Ok(schedule.parse::<TaskSchedule>()?.upcoming(&self.clock.now(), self.config, count))
+    }
+
+    pub async fn remove_task(&self, task_type: PeriodicTaskType) -> GardenResult<()> {
+        self.ask_scheduler(|reply| SchedulerChannelMessage::RemovePeriodicTask(task_type, reply)).await
+    }
+
+    pub async fn reset_tasks(&self) -> GardenResult<()> {
+        self.ask_scheduler(SchedulerChannelMessage::ResetTasks).await
+    }
+
+    /// Pausing holds back scheduled tasks, manual watering still works.
+    pub fn set_paused(&self, paused: bool) -> GardenResult<()> {
+        self.send_to_scheduler(SchedulerChannelMessage::SetPaused(paused))
+    }
+
+    pub async fn is_paused(&self) -> GardenResult<bool> {
+        let (reply, response) = oneshot::channel();
+        self.send_to_scheduler(SchedulerChannelMessage::IsPaused(reply))?;
+        response.await.map_err(|_| GardenError::ChannelClosed("scheduler"))
+    }
+
+    pub fn notify(&self, message: NotifierChannelMessage) -> GardenResult<()> {
+        self.notifier_channel_sender.try_send(message).map_err(send_error("notifier"))
+    }
+
+    pub fn shutdown(&self) {
+        (self.shutdown)()
+    }
+
+    /// Live stream of everything happening in the garden.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<GardenEvent> {
+        self.event_sender.subscribe()
+    }
+
+    pub fn status(&self) -> GardenStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn history(&self) -> GardenResult<History> {
+        Ok(History::open(&self.config.history_db_path)?)
+    }
+
+    /// Runs `read` on the history off the async runtime, queries and rendering block.
+    pub async fn read_history<T, F>(&self, read: F) -> GardenResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(History) -> GardenResult<T> + Send + 'static
+    {
+        let controller = self.clone();
+        tokio::task::spawn_blocking(move || read(controller.history()?)).await
+            .map_err(|_| GardenError::ChannelClosed("history"))?
+    }
+
+    /// Messages waiting for the scheduler and worker threads.
+    pub fn queue_depths(&self) -> [(&'static str, usize); 2] {
+        [("scheduler", queue_depth(&self.scheduler_channel_sender)), ("worker", queue_depth(&self.worker_channel_sender))]
+    }
+
+    fn correlation_id(&self) -> CorrelationId {
+        self.correlation_id.unwrap_or_default()
+    }
+
+    fn send_to_worker(&self, message: WorkerChannelMessage) -> GardenResult<()> {
+        let correlation_id = self.correlation_id();
+        debug!(%correlation_id, ?message, "Sending to the worker");
+        self.worker_channel_sender.try_send(Traced::new(correlation_id, message)).map_err(send_error("worker"))
+    }
+
+    fn send_to_scheduler(&self, message: SchedulerChannelMessage) -> GardenResult<()> {
+        let correlation_id = self.correlation_id();
+        debug!(%correlation_id, ?message, "Sending to the scheduler");
+        self.scheduler_channel_sender.try_send(Traced::new(correlation_id, message)).map_err(send_error("scheduler"))
+    }
+
+    /// Sends a command and waits until the scheduler applied it.
+    async fn ask_scheduler(&self, message: impl FnOnce(SchedulerReply) -> SchedulerChannelMessage) -> GardenResult<()> {
+        let (reply, response) = oneshot::channel();
+        self.send_to_scheduler(message(reply))?;
+        response.await.map_err(|_| GardenError::ChannelClosed("scheduler"))?
+    }
+}
+
+fn queue_depth<T>(sender: &mpsc::Sender<T>) -> usize {
+    sender.max_capacity() - sender.capacity()
+}
+
+pub fn parse_task_type(input: &str) -> GardenResult<PeriodicTaskType> {
+    input.trim().parse()
+}
+
+/// Cron expressions, sunrise or sunset relative schedules, intervals and duty cycles are all fine.
+pub fn validate_schedule(task: &PeriodicTask) -> GardenResult<()> {
+    TaskSchedule::for_task(task).map(|_| ())
+}
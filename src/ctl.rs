@@ -0,0 +1,257 @@
+// standard
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+// internal
+use crate::config::{Config};
+use crate::control::{Controller, PREVIEW_RUNS, parse_task_type};
+use crate::definitions::{CorrelationId, Trigger, WateringRequest};
+use crate::error::{GardenResult};
+use crate::schedule::{describe_preview};
+
+// external
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::{UnixListener, UnixStream as AsyncUnixStream};
+use tracing::{error, info, info_span, warn, Instrument};
+
+
+/// Moves the socket for the daemon and `smart_garden ctl` alike.
+const SOCKET_PATH_VARIABLE: &str = "SMART_GARDEN_SOCKET";
+
+/// Requests are a short JSON line, anything longer is cut off and rejected.
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+pub const USAGE: &str = "Usage: smart_garden ctl [--socket <path>] <command>
+  water [zone] [seconds]         water every zone or one zone
+  stop                           stop watering and drop the queued watering cycles
+  pause | resume                 hold back or resume the periodic tasks
+  status                         what the garden is doing right now
+  tasks list                     list periodic tasks
+  tasks update <task> <cron>     update a task schedule, e.g. tasks update WaterPlants sunrise - 30min
+  tasks preview <schedule>       show the next runs of a schedule, e.g. tasks preview every 36h
+  tasks remove <task>            remove a periodic task
+  tasks reset                    reset all tasks to the default config";
+
+/// One request per connection, sent as a JSON line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum CtlRequest {
+    Water { zone: Option<String>, seconds: Option<u64> },
+    Stop,
+    Pause,
+    Resume,
+    Status,
+    ListTasks,
+    UpdateTask { task_type: String, cron: String },
+    PreviewSchedule { schedule: String },
+    RemoveTask { task_type: String },
+    ResetTasks
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CtlResponse {
+    pub ok: bool,
+    pub output: String
+}
+
+impl CtlResponse {
+    fn done() -> Self {
+        CtlResponse { ok: true, output: "Done".to_string() }
+    }
+}
+
+impl From<GardenResult<()>> for CtlResponse {
+    fn from(result: GardenResult<()>) -> Self {
+        match result {
+            Ok(_) => CtlResponse::done(),
+            Err(error) => CtlResponse { ok: false, output: error.to_string() },
+        }
+    }
+}
+
+/// Parses the arguments after `ctl`, `None` when they do not make up a command.
+pub fn parse_args(args: &[String]) -> Option<CtlRequest> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["water", rest @ ..] if rest.len() <= 2 => {
+            let (zone, seconds) = match rest {
+                [] => (None, None),
+                [seconds] if seconds.parse::<u64>().is_ok() => (None, seconds.parse().ok()),
+                [zone] => (Some(zone.to_string()), None),
+                [zone, seconds] => (Some(zone.to_string()), Some(seconds.parse().ok()?)),
+                _ => return None,
+            };
+            Some(CtlRequest::Water { zone, seconds })
+        },
+        ["stop"] => Some(CtlRequest::Stop),
+        ["pause"] => Some(CtlRequest::Pause),
+        ["resume"] => Some(CtlRequest::Resume),
+        ["status"] => Some(CtlRequest::Status),
+        ["tasks", "list"] => Some(CtlRequest::ListTasks),
+        ["tasks", "update", task_type, cron @ ..] if !cron.is_empty() => Some(CtlRequest::UpdateTask { task_type: task_type.to_string(), cron: cron.join(" ") }),
+        ["tasks", "preview", schedule @ ..] if !schedule.is_empty() => Some(CtlRequest::PreviewSchedule { schedule: schedule.join(" ") }),
+        ["tasks", "remove", task_type] => Some(CtlRequest::RemoveTask { task_type: task_type.to_string() }),
+        ["tasks", "reset"] => Some(CtlRequest::ResetTasks),
+        _ => None,
+    }
+}
+
+/// Runs a request through the same controller the bot and the HTTP API use.
+pub async fn execute(controller: &Controller, request: CtlRequest) -> CtlResponse {
+    match request {
+        CtlRequest::Water { zone, seconds } => {
+            let seconds = seconds.unwrap_or(controller.config().water_pump_working_cycle_seconds);
+            controller.water(WateringRequest { zone, seconds, trigger: Trigger::Cli }).into()
+        },
+        CtlRequest::Stop => controller.stop().into(),
+        CtlRequest::Pause => controller.set_paused(true).into(),
+        CtlRequest::Resume => controller.set_paused(false).into(),
+        CtlRequest::Status => CtlResponse { ok: true, output: controller.status().describe(controller.config().timezone) },
+        CtlRequest::ListTasks => match controller.list_tasks().await {
+            Ok(tasks) => {
+                let output = tasks.iter().map(|meta| meta.describe(controller.config().timezone)).collect::<Vec<_>>().join("\n\n");
+                CtlResponse { ok: true, output }
+            },
+            Err(error) => CtlResponse { ok: false, output: error.to_string() },
+        },
+        CtlRequest::UpdateTask { task_type, cron } => match parse_task_type(&task_type) {
+            Ok(task_type) => controller.update_task_schedule(task_type, cron, None).await.into(),
+            Err(error) => Err(error).into(),
+        },
+        CtlRequest::PreviewSchedule { schedule } => match controller.preview_schedule(&schedule, PREVIEW_RUNS) {
+            Ok(runs) => CtlResponse { ok: true, output: describe_preview(&schedule, &runs, controller.config().timezone) },
+            Err(error) => CtlResponse { ok: false, output: error.to_string() },
+        },
+        CtlRequest::RemoveTask { task_type } => match parse_task_type(&task_type) {
+            Ok(task_type) => controller.remove_task(task_type).await.into(),
+            Err(error) => Err(error).into(),
+        },
+        CtlRequest::ResetTasks => controller.reset_tasks().await.into(),
+    }
+}
+
+async fn handle_connection(controller: Controller, stream: AsyncUnixStream) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = AsyncBufReader::new(reader.take(MAX_REQUEST_BYTES));
+    let mut line = String::new();
+
+    // a client that doesn't finish its request in time is dropped, so it can't hold a task forever
+    let timeout = Duration::from_secs(controller.config().ctl_request_timeout_seconds);
+    tokio::time::timeout(timeout, reader.read_line(&mut line)).await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "no request in time"))??;
+
+    let response = match serde_json::from_str::<CtlRequest>(&line) {
+        Ok(request) => {
+            let correlation_id = CorrelationId::new();
+            let span = info_span!("ctl", %correlation_id);
+            span.in_scope(|| info!(?request, "Handling a local command"));
+            execute(&controller.traced(correlation_id), request).instrument(span).await
+        },
+        Err(error) => CtlResponse { ok: false, output: format!("Invalid request: {}", error) },
+    };
+
+    let mut json = serde_json::to_string(&response)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await
+}
+
+/// `SMART_GARDEN_SOCKET` wins over the config, so both sides can be moved the same way.
+pub fn socket_path(config: &Config) -> String {
+    match env::var(SOCKET_PATH_VARIABLE) {
+        Ok(path) if !path.is_empty() => path,
+        _ => config.ctl_socket_path.clone()
+    }
+}
+
+pub struct CtlServer {} impl CtlServer {
+    pub async fn run_async(controller: Controller) {
+        let path = socket_path(controller.config());
+
+        // a socket left behind by a previous run would make bind fail
+        if Path::new(&path).exists() {
+            fs::remove_file(&path).unwrap_or_default();
+        }
+        if let Some(dir) = Path::new(&path).parent().filter(|dir| !dir.exists()) {
+            if let Err(error) = fs::DirBuilder::new().recursive(true).mode(0o750).create(dir) {
+                warn!(%path, %error, "Could not create the socket's directory");
+            }
+        }
+
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(error) => {
+                error!(%path, %error, "Could not listen for local commands");
+                return;
+            }
+        };
+
+        // only the garden's user and its group may control it
+        if let Err(error) = fs::set_permissions(&path, fs::Permissions::from_mode(0o660)) {
+            warn!(%path, %error, "Could not restrict access to the socket");
+        }
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let controller = controller.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = handle_connection(controller, stream).await {
+                            warn!(%error, "Local command failed");
+                        }
+                    });
+                },
+                Err(error) => warn!(%error, "Could not accept a local command"),
+            }
+        }
+    }
+}
+
+/// Sends one request to a running daemon and waits for its response.
+pub fn send(socket_path: &str, request: &CtlRequest) -> std::io::Result<CtlResponse> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    let mut json = serde_json::to_string(request)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Entry point of `smart_garden ctl ...`, returns the process exit code.
+pub fn run_cli(config: &Config, args: &[String]) -> i32 {
+    let (path, args) = match args {
+        [flag, path, args @ ..] if flag == "--socket" => (path.clone(), args),
+        _ => (socket_path(config), args)
+    };
+
+    let request = match parse_args(args) {
+        Some(request) => request,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match send(&path, &request) {
+        Ok(response) if response.ok => {
+            println!("{}", response.output);
+            0
+        },
+        Ok(response) => {
+            eprintln!("{}", response.output);
+            1
+        },
+        Err(error) => {
+            eprintln!("Could not reach the smart garden at {}: {}", path, error);
+            1
+        }
+    }
+}
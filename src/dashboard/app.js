@@ -0,0 +1,232 @@
+"use strict";
+
+const state = {
+    token: localStorage.getItem("smart_garden_token") || "",
+    zones: [],
+    cards: new Map()
+};
+
+async function api(method, path, body) {
+    const response = await fetch(path, {
+        method,
+        headers: {
+            "Authorization": `Bearer ${state.token}`,
+            "Content-Type": "application/json"
+        },
+        body: body === undefined ? undefined : JSON.stringify(body)
+    });
+
+    if (response.status === 401) {
+        showLogin();
+        throw new Error("unauthorized");
+    }
+
+    if (!response.ok) {
+        const error = await response.json().catch(() => ({ error: response.statusText }));
+        alert(error.error);
+        throw new Error(error.error);
+    }
+
+    return response;
+}
+
+function showLogin() {
+    document.getElementById("login").hidden = false;
+    document.getElementById("app").hidden = true;
+}
+
+function setMoisture(card, reading) {
+    if (!reading) {
+        return;
+    }
+    card.querySelector(".level").style.width = `${reading.moisture}%`;
+    card.querySelector(".moisture").textContent =
+        `${reading.moisture.toFixed(1)}% at ${new Date(reading.timestamp).toLocaleString()}`;
+}
+
+function setWatering(run) {
+    document.getElementById("pump").textContent = run
+        ? `Watering ${run.zone} for ${run.seconds} seconds`
+        : "Pump is idle";
+
+    for (const [name, card] of state.cards) {
+        card.classList.toggle("watering", run !== null && run.zone === name);
+    }
+}
+
+async function loadZones() {
+    state.zones = await (await api("GET", "/zones")).json();
+
+    const container = document.getElementById("zones");
+    const select = document.getElementById("chart-zone");
+    const template = document.getElementById("zone-card");
+    container.replaceChildren();
+    select.replaceChildren(new Option("all zones", ""));
+    state.cards.clear();
+
+    for (const zone of state.zones) {
+        const card = template.content.firstElementChild.cloneNode(true);
+        card.querySelector(".name").textContent = zone.name;
+        card.querySelector(".water").addEventListener("click", () => {
+            const seconds = Number(card.querySelector(".seconds").value);
+            api("POST", `/zones/${encodeURIComponent(zone.name)}/water`, { seconds });
+        });
+        card.querySelector(".stop").addEventListener("click", () => {
+            api("POST", `/zones/${encodeURIComponent(zone.name)}/stop`);
+        });
+        setMoisture(card, zone.moisture);
+        card.classList.toggle("watering", zone.watering);
+
+        state.cards.set(zone.name, card);
+        container.appendChild(card);
+        select.appendChild(new Option(zone.name, zone.name));
+    }
+}
+
+function cell(...children) {
+    const td = document.createElement("td");
+    td.append(...children);
+    return td;
+}
+
+// the server knows every schedule format (cron, sun, interval, duty cycle), so it gets to judge
+async function previewSchedule(schedule) {
+    const response = await fetch(`/schedules/preview?${new URLSearchParams({ schedule })}`, {
+        headers: { "Authorization": `Bearer ${state.token}` }
+    });
+    return response.ok ? response.json() : null;
+}
+
+function validateSchedule(input) {
+    clearTimeout(input.validation);
+    input.validation = setTimeout(async () => {
+        const schedule = input.value;
+        const preview = await previewSchedule(schedule).catch(() => null);
+        // the input may have changed while waiting
+        if (input.value === schedule) {
+            input.classList.toggle("invalid", preview === null);
+            input.title = preview ? preview.description : "";
+        }
+    }, 300);
+}
+
+async function loadTasks() {
+    const tasks = await (await api("GET", "/tasks")).json();
+    const body = document.querySelector("#tasks tbody");
+    body.replaceChildren();
+
+    tasks.sort((a, b) => a.task_type.localeCompare(b.task_type));
+    for (const task of tasks) {
+        const row = document.createElement("tr");
+        const cron = document.createElement("input");
+        const disabled = document.createElement("input");
+        const save = document.createElement("button");
+        const remove = document.createElement("button");
+
+        cron.type = "text";
+        cron.value = task.cron;
+        cron.addEventListener("input", () => validateSchedule(cron));
+        disabled.type = "checkbox";
+        disabled.checked = task.disabled;
+        save.textContent = "Save";
+        save.addEventListener("click", async () => {
+            await api("PUT", `/tasks/${task.task_type}`, { cron: cron.value, disabled: disabled.checked });
+            await loadTasks();
+        });
+        remove.textContent = "Remove";
+        remove.className = "danger";
+        remove.addEventListener("click", async () => {
+            await api("DELETE", `/tasks/${task.task_type}`);
+            await loadTasks();
+        });
+
+        row.append(cell(task.task_type), cell(cron), cell(disabled), cell(save, remove));
+        body.appendChild(row);
+    }
+}
+
+async function loadChart() {
+    const zone = document.getElementById("chart-zone").value;
+    const period = document.getElementById("chart-period").value;
+    const query = new URLSearchParams({ period });
+    if (zone) {
+        query.set("zone", zone);
+    }
+
+    const image = await (await api("GET", `/chart?${query}`)).blob();
+    const chart = document.getElementById("chart");
+    URL.revokeObjectURL(chart.src);
+    chart.src = URL.createObjectURL(image);
+}
+
+function logEvent(event) {
+    const list = document.getElementById("events");
+    const item = document.createElement("li");
+    const subject = event.run ? event.run.zone : event.reading ? event.reading.zone : event.zone;
+    item.textContent = `${new Date().toLocaleTimeString()} ${event.type} ${subject}${event.reason ? `: ${event.reason}` : ""}`;
+    list.prepend(item);
+
+    while (list.children.length > 100) {
+        list.lastChild.remove();
+    }
+}
+
+function listen() {
+    const events = new EventSource(`/events?access_token=${encodeURIComponent(state.token)}`);
+
+    events.onmessage = (message) => {
+        const event = JSON.parse(message.data);
+        logEvent(event);
+
+        switch (event.type) {
+            case "watering_started":
+                setWatering(event.run);
+                break;
+            case "watering_finished":
+            case "watering_stopped":
+            case "watering_failed":
+                setWatering(null);
+                break;
+            case "moisture_read": {
+                const card = state.cards.get(event.reading.zone);
+                if (card) {
+                    setMoisture(card, event.reading);
+                }
+                break;
+            }
+        }
+    };
+}
+
+async function start() {
+    document.getElementById("login").hidden = true;
+    await loadZones();
+    document.getElementById("app").hidden = false;
+
+    const status = await (await api("GET", "/status")).json();
+    setWatering(status.current_run);
+
+    await Promise.all([loadTasks(), loadChart()]);
+    listen();
+}
+
+document.getElementById("login").addEventListener("submit", (event) => {
+    event.preventDefault();
+    state.token = document.getElementById("token").value;
+    localStorage.setItem("smart_garden_token", state.token);
+    start();
+});
+
+document.getElementById("stop").addEventListener("click", () => api("POST", "/stop"));
+document.getElementById("reset-tasks").addEventListener("click", async () => {
+    await api("POST", "/tasks/reset");
+    await loadTasks();
+});
+document.getElementById("chart-zone").addEventListener("change", loadChart);
+document.getElementById("chart-period").addEventListener("change", loadChart);
+
+if (state.token) {
+    start().catch(showLogin);
+} else {
+    showLogin();
+}
@@ -1,26 +1,319 @@
 use std::fmt;
 use std::str::FromStr;
 
-use teloxide_core::types::ChatId;
+use crate::error::{GardenError, GardenResult};
+use crate::schedule;
 
-#[derive(Debug, Clone)]
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use job_scheduler::Uuid;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PeriodicTaskType {
     WaterPlants,
     ReadMoistureSensorsData
 }
 
-#[derive(Debug, Clone)]
+/// Where the scheduler reports whether a command went through.
+pub type SchedulerReply = oneshot::Sender<GardenResult<()>>;
+
+#[derive(Debug)]
 pub enum SchedulerChannelMessage {
-    UpdatePeriodicTaskScheduleCommand(PeriodicTask),
-    ListPeriodicTasks(ChatId),
-    RemovePeriodicTask(PeriodicTaskType),
-    ResetTasks
+    AddPeriodicTask(PeriodicTask, SchedulerReply),
+    /// The task keeps its catch-up policy.
+    UpdatePeriodicTaskScheduleCommand(PeriodicTask, SchedulerReply),
+    ListPeriodicTasks(oneshot::Sender<Vec<TaskMetaData>>),
+    RemovePeriodicTask(PeriodicTaskType, SchedulerReply),
+    ResetTasks(SchedulerReply),
+    /// Paused tasks keep their schedule but are not sent to the worker.
+    SetPaused(bool),
+    IsPaused(oneshot::Sender<bool>)
 }
 
 #[derive(Debug, Clone)]
 pub enum WorkerChannelMessage {
     PeriodicTask(PeriodicTask),
-    WaterPlants(u64),
+    WaterPlants(WateringRequest),
+    /// Stops the running watering cycle and drops the queued ones.
+    Stop,
+    /// Stops watering the zone if it is being watered, the other zones and queued runs carry on.
+    StopZone(String)
+}
+
+/// Follows a command from the bot, API or scheduler to the worker run it causes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorrelationId(Uuid);
+
+impl CorrelationId {
+    pub fn new() -> Self {
+        CorrelationId(Uuid::new_v4())
+    }
+}
+
+impl Default for CorrelationId {
+    fn default() -> Self {
+        CorrelationId::new()
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // short enough to read in a log line, unique enough for a garden
+        write!(f, "{}", &self.0.to_simple().to_string()[..8])
+    }
+}
+
+/// A channel message together with the correlation ID of the command that caused it.
+#[derive(Debug, Clone)]
+pub struct Traced<T> {
+    pub correlation_id: CorrelationId,
+    pub message: T
+}
+
+impl<T> Traced<T> {
+    pub fn new(correlation_id: CorrelationId, message: T) -> Self {
+        Traced { correlation_id, message }
+    }
+}
+
+/// A Telegram chat, kept apart from teloxide so the garden builds without the bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChatId(pub i64);
+
+impl fmt::Display for ChatId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NotifierChannelMessage {
+    Subscribe(ChatId),
+    Unsubscribe(ChatId),
+    SetQuietHours(ChatId, Option<QuietHours>),
+    SetDigest(ChatId, Option<NaiveTime>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSeverity {
+    /// Only recorded, never notified.
+    Telemetry,
+    /// Batched into digests and held back during quiet hours.
+    Routine,
+    /// Always delivered right away.
+    Critical
+}
+
+#[derive(Debug, Clone)]
+pub enum GardenEvent {
+    WateringStarted(WateringRun),
+    WateringFinished(WateringRun),
+    WateringStopped(WateringRun),
+    WateringFailed(WateringRun, String),
+    /// A scheduled watering run didn't happen, e.g. because it rained.
+    WateringSkipped(SkippedRun),
+    MoistureRead(MoistureReading),
+    /// The debounced rain sensor turned wet (`true`) or dry.
+    RainSensorChanged(bool),
+    SensorFailed(String, String),
+    /// The scheduler fired a periodic task or learned how it went, the history keeps it across restarts.
+    TaskRan(PeriodicTaskType, TaskRun),
+    /// A periodic task was added, changed or removed, or the scheduler was paused or resumed.
+    TasksChanged,
+    /// Something in the background went wrong, e.g. the history database is unavailable.
+    Failure(String)
+}
+
+impl GardenEvent {
+    pub fn severity(&self) -> EventSeverity {
+        match self {
+            GardenEvent::WateringStarted(_) => EventSeverity::Telemetry,
+            GardenEvent::MoistureRead(_) => EventSeverity::Telemetry,
+            GardenEvent::WateringFinished(_) => EventSeverity::Routine,
+            GardenEvent::WateringStopped(_) => EventSeverity::Routine,
+            GardenEvent::WateringSkipped(_) => EventSeverity::Routine,
+            GardenEvent::RainSensorChanged(_) => EventSeverity::Telemetry,
+            GardenEvent::WateringFailed(_, _) => EventSeverity::Critical,
+            GardenEvent::SensorFailed(_, _) => EventSeverity::Critical,
+            GardenEvent::TaskRan(_, _) => EventSeverity::Telemetry,
+            GardenEvent::TasksChanged => EventSeverity::Telemetry,
+            GardenEvent::Failure(_) => EventSeverity::Critical,
+        }
+    }
+}
+
+impl fmt::Display for GardenEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GardenEvent::WateringStarted(run) => write!(f, "Watering {} for {} seconds", run.zone, run.seconds),
+            GardenEvent::WateringFinished(run) => write!(f, "Watered {} for {} seconds", run.zone, run.seconds),
+            GardenEvent::WateringStopped(run) => write!(f, "Watering {} was stopped", run.zone),
+            GardenEvent::WateringFailed(run, reason) => write!(f, "Watering {} failed: {}", run.zone, reason),
+            GardenEvent::WateringSkipped(skipped) => write!(f, "Skipped watering, {}", skipped.reason),
+            GardenEvent::MoistureRead(reading) => write!(f, "Moisture in {} is {:.1}%", reading.zone, reading.moisture),
+            GardenEvent::RainSensorChanged(true) => write!(f, "Rain sensor is wet"),
+            GardenEvent::RainSensorChanged(false) => write!(f, "Rain sensor is dry"),
+            GardenEvent::TaskRan(task_type, TaskRun { outcome: Some(outcome), .. }) => write!(f, "{} ran, {}", task_type, outcome),
+            GardenEvent::TaskRan(task_type, TaskRun { outcome: None, .. }) => write!(f, "{} ran", task_type),
+            GardenEvent::TasksChanged => write!(f, "Periodic tasks changed"),
+            GardenEvent::SensorFailed(zone, reason) => write!(f, "Moisture sensor in {} failed: {}", zone, reason),
+            GardenEvent::Failure(reason) => write!(f, "Something went wrong: {}", reason),
+        }
+    }
+}
+
+/// Irrigated area with its own water pump and, optionally, a moisture sensor.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub name: String,
+    pub pump_pin: u64,
+    pub moisture_sensor_pin: Option<u64>
+}
+
+/// What started a watering run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Schedule,
+    Manual,
+    Api,
+    Mqtt,
+    Cli
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Schedule => write!(f, "schedule"),
+            Trigger::Manual => write!(f, "manual"),
+            Trigger::Api => write!(f, "api"),
+            Trigger::Mqtt => write!(f, "mqtt"),
+            Trigger::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Trigger, Self::Err> {
+        match input {
+            "schedule" => Ok(Trigger::Schedule),
+            "manual" => Ok(Trigger::Manual),
+            "api" => Ok(Trigger::Api),
+            "mqtt" => Ok(Trigger::Mqtt),
+            "cli" => Ok(Trigger::Cli),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Ask the worker to water one zone, or every zone when `zone` is `None`.
+#[derive(Debug, Clone)]
+pub struct WateringRequest {
+    pub zone: Option<String>,
+    pub seconds: u64,
+    pub trigger: Trigger
+}
+
+#[derive(Debug, Clone)]
+pub struct WateringRun {
+    pub started_at: DateTime<Utc>,
+    pub zone: String,
+    pub seconds: u64,
+    pub trigger: Trigger
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Ok,
+    Stopped,
+    Skipped(String),
+    Failed(String)
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunOutcome::Ok => write!(f, "ok"),
+            RunOutcome::Stopped => write!(f, "stopped"),
+            RunOutcome::Skipped(reason) => write!(f, "skipped, {}", reason),
+            RunOutcome::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<&str> for RunOutcome {
+    fn from(input: &str) -> RunOutcome {
+        match input {
+            "ok" => RunOutcome::Ok,
+            "stopped" => RunOutcome::Stopped,
+            input => match input.strip_prefix("skipped, ") {
+                Some(reason) => RunOutcome::Skipped(reason.to_string()),
+                None => RunOutcome::Failed(input.to_string())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunRecord {
+    pub run: WateringRun,
+    pub outcome: RunOutcome
+}
+
+/// A scheduled watering run that didn't happen and why.
+#[derive(Debug, Clone)]
+pub struct SkippedRun {
+    pub at: DateTime<Utc>,
+    pub reason: String
+}
+
+#[derive(Debug, Clone)]
+pub struct MoistureReading {
+    pub at: DateTime<Utc>,
+    pub zone: String,
+    pub moisture: f64
+}
+
+/// Daily time window, possibly wrapping over midnight (e.g. `22:00-07:00`),
+/// during which routine notifications are held back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<QuietHours, Self::Err> {
+        let (start, end) = input.trim().split_once('-').ok_or(())?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| ())?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| ())?;
+
+        if start == end {
+            return Err(());
+        }
+
+        Ok(QuietHours { start, end })
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
 }
 
 impl fmt::Display for PeriodicTaskType {
@@ -33,13 +326,52 @@ impl fmt::Display for PeriodicTaskType {
 }
 
 impl FromStr for PeriodicTaskType {
-    type Err = ();
+    type Err = GardenError;
 
     fn from_str(input: &str) -> Result<PeriodicTaskType, Self::Err> {
         match input {
             "WaterPlants"  => Ok(PeriodicTaskType::WaterPlants),
             "ReadMoistureSensorsData"  => Ok(PeriodicTaskType::ReadMoistureSensorsData),
-            _ => Err(()),
+            _ => Err(GardenError::InvalidTask(input.to_string())),
+        }
+    }
+}
+
+/// What happens on startup to runs that were due while the garden was off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CatchUp {
+    /// Missed runs are lost.
+    #[default]
+    Skip,
+    /// One run right away if the latest missed run was due less than `within_hours` ago.
+    Once { within_hours: u32 },
+    /// Every missed run, one after another.
+    All
+}
+
+impl fmt::Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatchUp::Skip => write!(f, "skip"),
+            CatchUp::Once { within_hours } => write!(f, "once {}h", within_hours),
+            CatchUp::All => write!(f, "all"),
+        }
+    }
+}
+
+impl FromStr for CatchUp {
+    type Err = ();
+
+    /// `skip`, `all` or `once 12h`.
+    fn from_str(input: &str) -> Result<CatchUp, Self::Err> {
+        match input.trim() {
+            "skip" => Ok(CatchUp::Skip),
+            "all" => Ok(CatchUp::All),
+            input => {
+                let hours = input.strip_prefix("once").and_then(|hours| hours.trim().strip_suffix('h')).ok_or(())?;
+                let within_hours = hours.trim().parse::<u32>().ok().filter(|hours| *hours > 0).ok_or(())?;
+                Ok(CatchUp::Once { within_hours })
+            }
         }
     }
 }
@@ -48,7 +380,8 @@ impl FromStr for PeriodicTaskType {
 pub struct PeriodicTask {
     pub task_type: PeriodicTaskType,
     pub cron: String,
-    pub disabled: bool
+    pub disabled: bool,
+    pub catch_up: CatchUp
 }
 
 impl fmt::Display for PeriodicTask {
@@ -56,3 +389,58 @@ impl fmt::Display for PeriodicTask {
         write!(f, "Task: '{}' \nSchedule: '{}' \nDisabled: {}", self.task_type, self.cron, self.disabled)
     }
 }
+
+/// When a periodic task last fired, `outcome` is `None` until the worker reports how it went.
+#[derive(Debug, Clone)]
+pub struct TaskRun {
+    pub at: DateTime<Utc>,
+    pub outcome: Option<RunOutcome>
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskMetaData {
+    pub job_id: Uuid,
+    pub task: PeriodicTask,
+    /// The next few runs, empty when the task never runs again, is disabled or the scheduler is paused.
+    pub next_runs: Vec<DateTime<Utc>>,
+    pub last_run: Option<TaskRun>,
+    /// The whole scheduler is paused, not just this task.
+    pub paused: bool
+}
+
+impl TaskMetaData {
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.next_runs.first().copied()
+    }
+
+    /// Plain English overview of the task, with times in `timezone`.
+    pub fn describe(&self, timezone: Tz) -> String {
+        let disabled = if self.task.disabled { ", disabled" } else { "" };
+        let catch_up = match self.task.catch_up {
+            CatchUp::Skip => String::new(),
+            CatchUp::Once { within_hours } => format!("\nMissed runs: once, if missed within {} hours", within_hours),
+            CatchUp::All => "\nMissed runs: all".to_string()
+        };
+        let next_runs = if self.task.disabled {
+            "none while disabled".to_string()
+        } else if self.paused {
+            "none while the scheduler is paused".to_string()
+        } else if self.next_runs.is_empty() {
+            "never".to_string()
+        } else {
+            self.next_runs.iter().map(|at| local_time(*at, timezone)).collect::<Vec<_>>().join(", ")
+        };
+        let last_run = match &self.last_run {
+            Some(TaskRun { at, outcome: Some(outcome) }) => format!("{}, {}", local_time(*at, timezone), outcome),
+            Some(TaskRun { at, outcome: None }) => format!("{}, still running", local_time(*at, timezone)),
+            None => "not yet".to_string()
+        };
+
+        format!("{}: {}{}\nNext runs: {}\nLast run: {}{}", self.task.task_type, schedule::describe(&self.task.cron), disabled, next_runs, last_run, catch_up)
+    }
+}
+
+/// How times are shown to people, in the garden's timezone.
+pub fn local_time(at: DateTime<Utc>, timezone: Tz) -> String {
+    at.with_timezone(&timezone).format("%Y-%m-%d %H:%M %Z").to_string()
+}
@@ -0,0 +1,142 @@
+// standard
+use std::fmt;
+
+// internal
+use crate::definitions::{PeriodicTaskType};
+
+// external
+use tokio::sync::mpsc;
+
+
+pub const MIN_WATERING_SECONDS: u64 = 10;
+pub const MAX_WATERING_SECONDS: u64 = 60 * 5;
+
+/// Everything that can go wrong in the garden, with enough context to tell the user what happened.
+#[derive(Debug)]
+pub enum GardenError {
+    /// Driving a GPIO pin failed, e.g. the pin is not exported or the process lacks permissions.
+    #[cfg(feature = "gpio")]
+    Gpio { pin: u64, action: &'static str, source: sysfs_gpio::Error },
+    /// The moisture sensor returned something other than wet or dry.
+    Sensor { zone: String, reason: String },
+    UnknownZone(String),
+    InvalidDuration(u64),
+    InvalidTask(String),
+    UnknownTask(PeriodicTaskType),
+    InvalidSchedule { cron: String, reason: String },
+    InvalidInput(String),
+    /// The thread behind a channel stopped.
+    ChannelClosed(&'static str),
+    /// The thread behind a channel is alive but its queue is full.
+    Busy(&'static str),
+    Persistence(rusqlite::Error),
+    #[cfg(feature = "telegram")]
+    Telegram(teloxide::RequestError),
+    Render(String),
+    /// The weather provider could not be read or returned something unexpected.
+    Weather { source: String, reason: String }
+}
+
+pub type GardenResult<T> = Result<T, GardenError>;
+
+impl GardenError {
+    #[cfg(feature = "gpio")]
+    pub fn gpio(pin: u64, action: &'static str) -> impl FnOnce(sysfs_gpio::Error) -> GardenError {
+        move |source| GardenError::Gpio { pin, action, source }
+    }
+
+    /// Failures on our side rather than wrong input, worth trying again later.
+    pub fn is_temporary(&self) -> bool {
+        matches!(self, GardenError::ChannelClosed(_) | GardenError::Busy(_))
+    }
+
+    /// The request itself was wrong, sending it again won't help.
+    pub fn is_invalid_input(&self) -> bool {
+        matches!(self,
+            GardenError::UnknownZone(_) | GardenError::InvalidDuration(_) | GardenError::InvalidTask(_) |
+            GardenError::UnknownTask(_) | GardenError::InvalidSchedule { .. } | GardenError::InvalidInput(_)
+        )
+    }
+
+    /// What the user gets to read when their command failed.
+    pub fn reply(&self) -> String {
+        if self.is_temporary() {
+            format!("Sorry... {}. Please try again later.", self)
+        } else if self.is_invalid_input() {
+            format!("Wrong input... {}", self)
+        } else {
+            format!("Sorry... {}", self)
+        }
+    }
+}
+
+impl fmt::Display for GardenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "gpio")]
+            GardenError::Gpio { pin, action, source } => write!(f, "failed to {} on GPIO pin {}: {}", action, pin, source),
+            GardenError::Sensor { zone, reason } => write!(f, "the moisture sensor in {} failed: {}", zone, reason),
+            GardenError::UnknownZone(zone) => write!(f, "{} is not a known zone", zone),
+            GardenError::InvalidDuration(seconds) => write!(f, "cycle to water plants is {} but should be a number between {} and {} seconds", seconds, MIN_WATERING_SECONDS, MAX_WATERING_SECONDS),
+            GardenError::InvalidTask(task_type) => write!(f, "{} is not a valid task", task_type),
+            GardenError::UnknownTask(task_type) => write!(f, "{} is not scheduled", task_type),
+            GardenError::InvalidSchedule { cron, reason } => write!(f, "{} is not a valid schedule: {}", cron, reason),
+            GardenError::InvalidInput(message) => write!(f, "{}", message),
+            GardenError::ChannelClosed(thread) => write!(f, "the {} has stopped", thread),
+            GardenError::Busy(thread) => write!(f, "the {} is not responding", thread),
+            GardenError::Persistence(error) => write!(f, "failed to access the history: {}", error),
+            #[cfg(feature = "telegram")]
+            GardenError::Telegram(error) => write!(f, "failed to talk to Telegram: {}", error),
+            GardenError::Render(message) => write!(f, "failed to render the chart: {}", message),
+            GardenError::Weather { source, reason } => write!(f, "failed to get the weather from {}: {}", source, reason),
+        }
+    }
+}
+
+impl std::error::Error for GardenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "gpio")]
+            GardenError::Gpio { source, .. } => Some(source),
+            GardenError::Persistence(error) => Some(error),
+            #[cfg(feature = "telegram")]
+            GardenError::Telegram(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for GardenError {
+    fn from(error: rusqlite::Error) -> Self {
+        GardenError::Persistence(error)
+    }
+}
+
+#[cfg(feature = "telegram")]
+impl From<teloxide::RequestError> for GardenError {
+    fn from(error: teloxide::RequestError) -> Self {
+        GardenError::Telegram(error)
+    }
+}
+
+/// A failed `try_send`, the notifier still uses a crossbeam channel.
+pub trait TrySendFailure {
+    fn is_full(&self) -> bool;
+}
+
+impl<T> TrySendFailure for mpsc::error::TrySendError<T> {
+    fn is_full(&self) -> bool {
+        matches!(self, mpsc::error::TrySendError::Full(_))
+    }
+}
+
+impl<T> TrySendFailure for crossbeam_channel::TrySendError<T> {
+    fn is_full(&self) -> bool {
+        self.is_full()
+    }
+}
+
+/// Maps a failed `try_send` to the thread that did not take the message.
+pub fn send_error<E: TrySendFailure>(thread: &'static str) -> impl FnOnce(E) -> GardenError {
+    move |error| if error.is_full() { GardenError::Busy(thread) } else { GardenError::ChannelClosed(thread) }
+}
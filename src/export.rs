@@ -0,0 +1,129 @@
+// standard
+use std::fmt;
+use std::str::FromStr;
+
+// internal
+use crate::definitions::{GardenEvent, MoistureReading, RunRecord, WateringRun};
+
+// external
+use serde_json::{json, Value};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ExportFormat, Self::Err> {
+        match input {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per reading or run, oldest first, with a `kind` column telling them apart.
+fn to_csv(readings: &[MoistureReading], runs: &[RunRecord]) -> String {
+    let mut rows: Vec<(i64, String)> = Vec::new();
+
+    for reading in readings {
+        rows.push((reading.at.timestamp(), format!(
+            "reading,{},{},{},,,",
+            reading.at.to_rfc3339(), csv_field(&reading.zone), reading.moisture
+        )));
+    }
+
+    for record in runs {
+        rows.push((record.run.started_at.timestamp(), format!(
+            "run,{},{},,{},{},{}",
+            record.run.started_at.to_rfc3339(), csv_field(&record.run.zone), record.run.seconds, record.run.trigger, csv_field(&record.outcome.to_string())
+        )));
+    }
+
+    rows.sort_by_key(|(at, _)| *at);
+
+    let mut csv = String::from("kind,timestamp,zone,moisture,seconds,trigger,outcome\n");
+    for (_, row) in rows {
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn reading_json(reading: &MoistureReading) -> Value {
+    json!({ "timestamp": reading.at.to_rfc3339(), "zone": reading.zone, "moisture": reading.moisture })
+}
+
+pub fn run_json(run: &WateringRun) -> Value {
+    json!({ "timestamp": run.started_at.to_rfc3339(), "zone": run.zone, "seconds": run.seconds, "trigger": run.trigger.to_string() })
+}
+
+pub fn run_record_json(record: &RunRecord) -> Value {
+    let mut value = run_json(&record.run);
+    value["outcome"] = json!(record.outcome.to_string());
+    value
+}
+
+/// Shared by the HTTP API's event stream and the MQTT bridge.
+pub fn event_json(event: &GardenEvent) -> Value {
+    match event {
+        GardenEvent::WateringStarted(run) => json!({ "type": "watering_started", "run": run_json(run) }),
+        GardenEvent::WateringFinished(run) => json!({ "type": "watering_finished", "run": run_json(run) }),
+        GardenEvent::WateringStopped(run) => json!({ "type": "watering_stopped", "run": run_json(run) }),
+        GardenEvent::WateringFailed(run, reason) => json!({ "type": "watering_failed", "run": run_json(run), "reason": reason }),
+        GardenEvent::WateringSkipped(skipped) => json!({ "type": "watering_skipped", "timestamp": skipped.at.to_rfc3339(), "reason": skipped.reason }),
+        GardenEvent::RainSensorChanged(wet) => json!({ "type": "rain_sensor_changed", "wet": wet }),
+        GardenEvent::MoistureRead(reading) => json!({ "type": "moisture_read", "reading": reading_json(reading) }),
+        GardenEvent::SensorFailed(zone, reason) => json!({ "type": "sensor_failed", "zone": zone, "reason": reason }),
+        GardenEvent::TasksChanged => json!({ "type": "tasks_changed" }),
+        GardenEvent::TaskRan(task_type, run) => json!({
+            "type": "task_ran",
+            "task_type": task_type.to_string(),
+            "timestamp": run.at.to_rfc3339(),
+            "outcome": run.outcome.as_ref().map(|outcome| outcome.to_string())
+        }),
+        GardenEvent::Failure(reason) => json!({ "type": "failure", "reason": reason }),
+    }
+}
+
+fn to_json(readings: &[MoistureReading], runs: &[RunRecord]) -> String {
+    let readings: Vec<_> = readings.iter().map(reading_json).collect();
+    let runs: Vec<_> = runs.iter().map(run_record_json).collect();
+
+    json!({ "readings": readings, "runs": runs }).to_string()
+}
+
+pub fn export(readings: &[MoistureReading], runs: &[RunRecord], format: ExportFormat) -> Vec<u8> {
+    match format {
+        ExportFormat::Csv => to_csv(readings, runs).into_bytes(),
+        ExportFormat::Json => to_json(readings, runs).into_bytes(),
+    }
+}
@@ -0,0 +1,112 @@
+// standard
+use std::sync::Arc;
+
+// internal
+use crate::clock::{SharedClock, SystemClock};
+use crate::config::{Config};
+use crate::control::{Controller};
+use crate::definitions::{GardenEvent, NotifierChannelMessage};
+use crate::hardware::{Hardware};
+use crate::history::{History};
+use crate::metrics::{Metrics, METRICS};
+use crate::rain::{RainSensor, SharedRain};
+use crate::scheduler::{Scheduler};
+#[cfg(not(feature = "gpio"))]
+use crate::simulation::{SimulatedGarden};
+use crate::status::{GardenStatus, SharedStatus};
+use crate::weather::{SharedWeather};
+use crate::worker::{Worker};
+
+// external
+use crossbeam_channel::{bounded, Receiver};
+use tokio::sync::{broadcast, mpsc};
+
+
+/// Sets up a garden, by default on the wall clock and the Pi's GPIO pins, or a simulated garden without the gpio feature.
+pub struct GardenBuilder {
+    config: &'static Config,
+    clock: Option<SharedClock>,
+    hardware: Option<Hardware>,
+    weather: Option<SharedWeather>
+}
+
+impl GardenBuilder {
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn hardware(mut self, hardware: Hardware) -> Self {
+        self.hardware = Some(hardware);
+        self
+    }
+
+    /// Overrides `config.weather`, e.g. with a provider that is not one of the built in sources.
+    pub fn weather(mut self, weather: SharedWeather) -> Self {
+        self.weather = Some(weather);
+        self
+    }
+
+    /// Spawns the scheduler and worker on the current tokio runtime and starts the
+    /// history, status and metrics threads. Control surfaces are up to the caller.
+    pub fn start(self) -> Garden {
+        let config = self.config;
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        #[cfg(feature = "gpio")]
+        let hardware = self.hardware.unwrap_or_else(Hardware::gpio);
+        #[cfg(not(feature = "gpio"))]
+        let hardware = self.hardware.unwrap_or_else(|| SimulatedGarden::new(config, clock.clone()).hardware());
+        let weather = self.weather.or_else(|| config.weather.as_ref().map(|source| source.provider()));
+
+        // setup communication channels between the scheduler and worker tasks and whoever sends notifications
+        let (scheduler_channel_sender, scheduler_channel_receiver) = mpsc::channel(config.messages_in_flight_limit);
+        let (worker_channel_sender, worker_channel_receiver) = mpsc::channel(config.messages_in_flight_limit);
+        let (notifier_channel_sender, notifier_channel_receiver) = bounded(config.messages_in_flight_limit);
+
+        // setup event stream, every interested thread subscribes to it
+        let (event_sender, event_receiver) = broadcast::channel(config.events_in_flight_limit);
+
+        // setup shared snapshot of what the garden is doing right now
+        let status = SharedStatus::default();
+
+        // setup scheduler and worker, both only wake up when there is something to do
+        tokio::spawn(Scheduler::run_async(config, clock.clone(), scheduler_channel_receiver, worker_channel_sender.clone(), event_sender.clone()));
+        let mut worker = Worker::new(config, clock.clone(), hardware.clone(), worker_channel_receiver, event_sender.clone());
+        if let Some(weather) = weather {
+            worker = worker.with_weather(weather);
+        }
+        if let Some(pin) = config.rain_sensor_pin {
+            let rain = SharedRain::default();
+            let mut rain_sensor = RainSensor::new(config, clock.clone(), hardware.digital_input, pin, rain.clone(), event_sender.clone());
+            tokio::spawn(async move { rain_sensor.run().await });
+            worker = worker.with_rain(rain);
+        }
+        tokio::spawn(async move { worker.run().await });
+
+        // setup threads
+        History::run_threaded(config, clock.clone(), event_sender.clone());
+        GardenStatus::run_threaded(status.clone(), event_sender.subscribe());
+        Metrics::run_threaded(&METRICS, clock.clone(), event_sender.subscribe());
+
+        Garden {
+            controller: Controller::new(config, clock.clone(), worker_channel_sender, scheduler_channel_sender, notifier_channel_sender, event_sender, status),
+            notifier_channel_receiver,
+            event_receiver
+        }
+    }
+}
+
+/// A running garden, every control surface drives it through `controller`.
+pub struct Garden {
+    pub controller: Controller,
+    /// Subscription changes for whoever delivers notifications.
+    pub notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+    /// Every event since the garden started, including those from before any control surface was up.
+    pub event_receiver: broadcast::Receiver<GardenEvent>
+}
+
+impl Garden {
+    pub fn builder(config: &'static Config) -> GardenBuilder {
+        GardenBuilder { config, clock: None, hardware: None, weather: None }
+    }
+}
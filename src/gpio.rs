@@ -0,0 +1,81 @@
+// internal
+use crate::definitions::{Zone};
+use crate::error::{GardenError, GardenResult};
+use crate::hardware::{Actuator, DigitalInput, MoistureSensor};
+
+// external
+use sysfs_gpio::{Direction, Pin};
+
+
+fn export(pin: &Pin) -> GardenResult<()> {
+    pin.export().map_err(GardenError::gpio(pin.get_pin_num(), "export"))
+}
+
+/// Unexports the pin even when using it failed, the first error wins.
+fn unexport<T>(pin: &Pin, result: GardenResult<T>) -> GardenResult<T> {
+    let unexported = pin.unexport().map_err(GardenError::gpio(pin.get_pin_num(), "unexport"));
+    let value = result?;
+    unexported?;
+    Ok(value)
+}
+
+/// Like `Pin::with_exported`, but keeps track of which step failed on which pin.
+fn with_exported<T>(pin: &Pin, closure: impl FnOnce() -> GardenResult<T>) -> GardenResult<T> {
+    export(pin)?;
+    unexport(pin, closure())
+}
+
+/// Pumps and probes wired to the Pi's GPIO header through sysfs.
+pub struct Gpio;
+
+impl Actuator for Gpio {
+    /// The pin stays exported while the pump runs.
+    fn set_pump(&self, zone: &Zone, on: bool) -> GardenResult<()> {
+        let pin = zone.pump_pin;
+        let water_pump = Pin::new(pin);
+
+        if on {
+            export(&water_pump)?;
+            let result = water_pump.set_direction(Direction::Out).map_err(GardenError::gpio(pin, "set the direction"))
+                .and_then(|_| water_pump.set_value(1).map_err(GardenError::gpio(pin, "turn on the pump")));
+            // don't leave a half set up pin behind
+            if result.is_err() {
+                return unexport(&water_pump, result);
+            }
+            result
+        } else {
+            let result = water_pump.set_value(0).map_err(GardenError::gpio(pin, "turn off the pump"));
+            unexport(&water_pump, result)
+        }
+    }
+}
+
+impl MoistureSensor for Gpio {
+    /// Reads the digital output of a soil moisture probe module, which is pulled
+    /// low once the soil is wet enough, and reports it as 0% or 100%.
+    fn read_moisture(&self, zone: &Zone) -> GardenResult<f64> {
+        let pin = zone.moisture_sensor_pin.ok_or_else(|| GardenError::Sensor { zone: zone.name.clone(), reason: "no sensor pin configured".to_string() })?;
+        let sensor = Pin::new(pin);
+        let value = with_exported(&sensor, || {
+            sensor.set_direction(Direction::In).map_err(GardenError::gpio(pin, "set the direction"))?;
+            sensor.get_value().map_err(GardenError::gpio(pin, "read the value"))
+        })?;
+
+        match value {
+            0 => Ok(100.0),
+            1 => Ok(0.0),
+            value => Err(GardenError::Sensor { zone: zone.name.clone(), reason: format!("unexpected value {}", value) })
+        }
+    }
+}
+
+impl DigitalInput for Gpio {
+    fn read_input(&self, pin: u64) -> GardenResult<bool> {
+        let input = Pin::new(pin);
+        let value = with_exported(&input, || {
+            input.set_direction(Direction::In).map_err(GardenError::gpio(pin, "set the direction"))?;
+            input.get_value().map_err(GardenError::gpio(pin, "read the value"))
+        })?;
+        Ok(value != 0)
+    }
+}
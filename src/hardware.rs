@@ -0,0 +1,80 @@
+// standard
+use std::sync::Arc;
+
+// internal
+use crate::definitions::{Zone};
+use crate::error::{GardenResult};
+#[cfg(feature = "gpio")]
+use crate::gpio::{Gpio};
+
+// external
+use chrono::{DateTime, Duration, Utc};
+
+
+/// Turns a zone's water pump on and off.
+pub trait Actuator: Send + Sync {
+    fn set_pump(&self, zone: &Zone, on: bool) -> GardenResult<()>;
+}
+
+/// Reads a zone's soil moisture in percent.
+pub trait MoistureSensor: Send + Sync {
+    fn read_moisture(&self, zone: &Zone) -> GardenResult<f64>;
+}
+
+/// Reads whether an input pin is high, e.g. a rain switch.
+pub trait DigitalInput: Send + Sync {
+    fn read_input(&self, pin: u64) -> GardenResult<bool>;
+}
+
+/// What the worker drives, GPIO on the Pi or a simulated garden on a laptop.
+#[derive(Clone)]
+pub struct Hardware {
+    pub actuator: Arc<dyn Actuator>,
+    pub moisture_sensor: Arc<dyn MoistureSensor>,
+    pub digital_input: Arc<dyn DigitalInput>
+}
+
+impl Hardware {
+    #[cfg(feature = "gpio")]
+    pub fn gpio() -> Self {
+        Hardware { actuator: Arc::new(Gpio), moisture_sensor: Arc::new(Gpio), digital_input: Arc::new(Gpio) }
+    }
+}
+
+/// An input pin that only changes once a new level has held for `debounce`, so a bouncing switch changes once.
+pub struct DebouncedInput {
+    input: Arc<dyn DigitalInput>,
+    pin: u64,
+    debounce: Duration,
+    level: Option<bool>,
+    /// A level different from `level` and since when it has been read.
+    pending: Option<(bool, DateTime<Utc>)>
+}
+
+impl DebouncedInput {
+    pub fn new(input: Arc<dyn DigitalInput>, pin: u64, debounce: Duration) -> Self {
+        DebouncedInput { input, pin, debounce, level: None, pending: None }
+    }
+
+    /// Reads the pin, returns the new level once it settled, including the first one.
+    pub fn poll(&mut self, now: DateTime<Utc>) -> GardenResult<Option<bool>> {
+        let high = self.input.read_input(self.pin)?;
+        if self.level == Some(high) {
+            self.pending = None;
+            return Ok(None);
+        }
+
+        let since = match self.pending {
+            Some((level, since)) if level == high => since,
+            _ => now
+        };
+        if now - since >= self.debounce {
+            self.level = Some(high);
+            self.pending = None;
+            Ok(Some(high))
+        } else {
+            self.pending = Some((high, since));
+            Ok(None)
+        }
+    }
+}
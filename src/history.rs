@@ -0,0 +1,366 @@
+// standard
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration};
+
+// internal
+use crate::clock::{SharedClock};
+use crate::config::{Config};
+use crate::definitions::{ChatId, GardenEvent, MoistureReading, QuietHours, PeriodicTaskType, RunOutcome, RunRecord, TaskRun, Trigger, WateringRun, local_time};
+use crate::error::{GardenError, GardenResult};
+
+// external
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::{params, Connection};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS readings (
+        at INTEGER NOT NULL,
+        zone TEXT NOT NULL,
+        moisture REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS readings_zone_at ON readings (zone, at);
+
+    CREATE TABLE IF NOT EXISTS readings_hourly (
+        at INTEGER NOT NULL,
+        zone TEXT NOT NULL,
+        moisture_avg REAL NOT NULL,
+        moisture_min REAL NOT NULL,
+        moisture_max REAL NOT NULL,
+        samples INTEGER NOT NULL,
+        PRIMARY KEY (zone, at)
+    );
+
+    CREATE TABLE IF NOT EXISTS readings_daily (
+        at INTEGER NOT NULL,
+        zone TEXT NOT NULL,
+        moisture_avg REAL NOT NULL,
+        moisture_min REAL NOT NULL,
+        moisture_max REAL NOT NULL,
+        samples INTEGER NOT NULL,
+        PRIMARY KEY (zone, at)
+    );
+
+    CREATE TABLE IF NOT EXISTS runs (
+        started_at INTEGER NOT NULL,
+        zone TEXT NOT NULL,
+        seconds INTEGER NOT NULL,
+        trigger TEXT NOT NULL,
+        outcome TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS runs_zone_started_at ON runs (zone, started_at);
+
+    CREATE TABLE IF NOT EXISTS task_runs (
+        task_type TEXT PRIMARY KEY,
+        at INTEGER NOT NULL,
+        outcome TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS subscribers (
+        chat_id INTEGER PRIMARY KEY,
+        quiet_hours TEXT,
+        digest_at TEXT
+    );
+";
+
+const DIGEST_FORMAT: &str = "%H:%M:%S";
+
+/// A subscribed chat with its quiet hours and digest time.
+pub type SavedSubscriber = (ChatId, Option<QuietHours>, Option<NaiveTime>);
+
+/// Longer periods can't have any history and would overflow the dates.
+const MAX_PERIOD_HOURS: i64 = 100 * 366 * 24;
+
+/// Parses periods like `24h`, `7d` or `2w`, up to a hundred years.
+pub fn parse_period(input: &str) -> Option<chrono::Duration> {
+    let input = input.trim();
+    let unit = input.chars().last()?;
+    let amount: i64 = input[..input.len() - unit.len_utf8()].parse().ok().filter(|amount| *amount > 0)?;
+
+    let hours_per_unit = match unit {
+        'h' => 1,
+        'd' => 24,
+        'w' => 7 * 24,
+        _ => return None
+    };
+    let hours = amount.checked_mul(hours_per_unit).filter(|hours| *hours <= MAX_PERIOD_HOURS)?;
+    Some(chrono::Duration::hours(hours))
+}
+
+/// When a period ending `now` started, wrong input rather than a panic if that's before the earliest date.
+pub fn period_start(now: DateTime<Utc>, period: chrono::Duration) -> GardenResult<DateTime<Utc>> {
+    now.checked_sub_signed(period).ok_or_else(|| GardenError::InvalidInput(format!("{} hours is too long a period", period.num_hours())))
+}
+
+fn to_datetime(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_utc(NaiveDateTime::from_timestamp(timestamp, 0), Utc)
+}
+
+/// Sensor readings and watering runs stored in a local SQLite database.
+/// Old readings are downsampled into hourly and then daily aggregates.
+pub struct History {
+    connection: Connection
+}
+
+impl History {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        History::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        History::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> rusqlite::Result<Self> {
+        // the bot reads while the history thread writes
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.execute_batch("PRAGMA journal_mode = WAL;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(History { connection })
+    }
+
+    pub fn run_threaded(
+        config: &'static Config,
+        clock: SharedClock,
+        event_sender: broadcast::Sender<GardenEvent>
+    ) {
+        let mut event_receiver = event_sender.subscribe();
+
+        thread::spawn(move || {
+            let runtime = Runtime::new().unwrap();
+            let mut history = match History::open(&config.history_db_path) {
+                Ok(history) => history,
+                Err(error) => {
+                    let error = GardenError::from(error);
+                    error!(path = %config.history_db_path, %error, "Failed to open history database");
+                    event_sender.send(GardenEvent::Failure(format!("history is not recorded, {}", error))).unwrap_or_default();
+                    return;
+                }
+            };
+            let maintenance_interval = chrono::Duration::seconds(config.history_maintenance_interval_seconds as i64);
+
+            runtime.block_on(async move {
+                let mut next_maintenance = clock.now();
+
+                loop {
+                    let now = clock.now();
+                    if now >= next_maintenance {
+                        if let Err(error) = history.apply_retention(config, now) {
+                            let error = GardenError::from(error);
+                            error!(%error, "Failed to apply history retention");
+                            event_sender.send(GardenEvent::Failure(error.to_string())).unwrap_or_default();
+                        }
+                        next_maintenance = now + maintenance_interval;
+                    }
+
+                    tokio::select! {
+                        _ = clock.sleep_until(next_maintenance) => {},
+                        event = event_receiver.recv() => match event {
+                            Ok(event) => {
+                                if let Err(error) = history.record(&event) {
+                                    error!(?event, %error, "Failed to record an event");
+                                }
+                            },
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!(skipped, "History fell behind, events were dropped");
+                            },
+                            Err(broadcast::error::RecvError::Closed) => break
+                        }
+                    }
+                }
+            });
+        });
+    }
+
+    pub fn record(&self, event: &GardenEvent) -> rusqlite::Result<()> {
+        match event {
+            GardenEvent::WateringFinished(run) => self.insert_run(run, &RunOutcome::Ok),
+            GardenEvent::WateringStopped(run) => self.insert_run(run, &RunOutcome::Stopped),
+            GardenEvent::WateringFailed(run, reason) => self.insert_run(run, &RunOutcome::Failed(reason.clone())),
+            GardenEvent::MoistureRead(reading) => self.insert_reading(reading),
+            GardenEvent::TaskRan(task_type, run) => self.save_task_run(task_type, run),
+            GardenEvent::WateringStarted(_) | GardenEvent::WateringSkipped(_) | GardenEvent::RainSensorChanged(_) | GardenEvent::SensorFailed(_, _) | GardenEvent::TasksChanged | GardenEvent::Failure(_) => Ok(())
+        }
+    }
+
+    pub fn insert_reading(&self, reading: &MoistureReading) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO readings (at, zone, moisture) VALUES (?1, ?2, ?3)",
+            params![reading.at.timestamp(), reading.zone, reading.moisture]
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_run(&self, run: &WateringRun, outcome: &RunOutcome) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO runs (started_at, zone, seconds, trigger, outcome) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run.started_at.timestamp(), run.zone, run.seconds as i64, run.trigger.to_string(), outcome.to_string()]
+        )?;
+        Ok(())
+    }
+
+    /// Remembers the latest run of a periodic task, so runs missed while the garden was off can be caught up.
+    pub fn save_task_run(&self, task_type: &PeriodicTaskType, run: &TaskRun) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO task_runs (task_type, at, outcome) VALUES (?1, ?2, ?3)",
+            params![task_type.to_string(), run.at.timestamp(), run.outcome.as_ref().map(|outcome| outcome.to_string())]
+        )?;
+        Ok(())
+    }
+
+    /// The latest run of every periodic task, by task type.
+    pub fn task_runs(&self) -> rusqlite::Result<HashMap<String, TaskRun>> {
+        let mut statement = self.connection.prepare("SELECT task_type, at, outcome FROM task_runs")?;
+        let rows = statement.query_map([], |row| {
+            let run = TaskRun { at: to_datetime(row.get(1)?), outcome: row.get::<_, Option<String>>(2)?.as_deref().map(RunOutcome::from) };
+            Ok((row.get::<_, String>(0)?, run))
+        })?;
+        rows.collect()
+    }
+
+    /// Remembers who gets notified and when, so subscriptions survive a restart.
+    pub fn save_subscriber(&self, chat_id: ChatId, quiet_hours: Option<QuietHours>, digest_at: Option<NaiveTime>) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO subscribers (chat_id, quiet_hours, digest_at) VALUES (?1, ?2, ?3)",
+            params![chat_id.0, quiet_hours.map(|quiet_hours| quiet_hours.to_string()), digest_at.map(|digest_at| digest_at.format(DIGEST_FORMAT).to_string())]
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_subscriber(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        self.connection.execute("DELETE FROM subscribers WHERE chat_id = ?1", params![chat_id.0])?;
+        Ok(())
+    }
+
+    /// Every subscribed chat with its quiet hours and digest time.
+    pub fn subscribers(&self) -> rusqlite::Result<Vec<SavedSubscriber>> {
+        let mut statement = self.connection.prepare("SELECT chat_id, quiet_hours, digest_at FROM subscribers ORDER BY chat_id")?;
+        let rows = statement.query_map([], |row| {
+            let quiet_hours = row.get::<_, Option<String>>(1)?.and_then(|quiet_hours| quiet_hours.parse().ok());
+            let digest_at = row.get::<_, Option<String>>(2)?.and_then(|digest_at| NaiveTime::parse_from_str(&digest_at, DIGEST_FORMAT).ok());
+            Ok((ChatId(row.get(0)?), quiet_hours, digest_at))
+        })?;
+        rows.collect()
+    }
+
+    /// Moisture over time, oldest first. Downsampled periods contribute their averages.
+    pub fn readings(&self, zone: Option<&str>, since: DateTime<Utc>) -> rusqlite::Result<Vec<MoistureReading>> {
+        let mut statement = self.connection.prepare(
+            "SELECT at, zone, moisture FROM readings WHERE at >= ?1 AND (?2 IS NULL OR zone = ?2)
+             UNION ALL SELECT at, zone, moisture_avg FROM readings_hourly WHERE at >= ?1 AND (?2 IS NULL OR zone = ?2)
+             UNION ALL SELECT at, zone, moisture_avg FROM readings_daily WHERE at >= ?1 AND (?2 IS NULL OR zone = ?2)
+             ORDER BY at"
+        )?;
+
+        let rows = statement.query_map(params![since.timestamp(), zone], |row| {
+            Ok(MoistureReading { at: to_datetime(row.get(0)?), zone: row.get(1)?, moisture: row.get(2)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Watering runs, oldest first.
+    pub fn runs(&self, zone: Option<&str>, since: DateTime<Utc>) -> rusqlite::Result<Vec<RunRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT started_at, zone, seconds, trigger, outcome FROM runs
+             WHERE started_at >= ?1 AND (?2 IS NULL OR zone = ?2) ORDER BY started_at"
+        )?;
+
+        let rows = statement.query_map(params![since.timestamp(), zone], |row| {
+            let trigger: String = row.get(3)?;
+            let outcome: String = row.get(4)?;
+            let seconds: i64 = row.get(2)?;
+            Ok(RunRecord {
+                run: WateringRun {
+                    started_at: to_datetime(row.get(0)?),
+                    zone: row.get(1)?,
+                    seconds: seconds as u64,
+                    trigger: trigger.parse().unwrap_or(Trigger::Manual)
+                },
+                outcome: RunOutcome::from(outcome.as_str())
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Human readable overview of moisture and watering runs since `since`, with times in `timezone`.
+    pub fn summary(&self, zone: Option<&str>, since: DateTime<Utc>, timezone: Tz) -> rusqlite::Result<String> {
+        let readings = self.readings(zone, since)?;
+        let runs = self.runs(zone, since)?;
+        let mut lines = Vec::new();
+
+        if readings.is_empty() {
+            lines.push("Moisture: no readings".to_string());
+        } else {
+            let average = readings.iter().map(|r| r.moisture).sum::<f64>() / readings.len() as f64;
+            let min = readings.iter().map(|r| r.moisture).fold(f64::MAX, f64::min);
+            let max = readings.iter().map(|r| r.moisture).fold(f64::MIN, f64::max);
+            lines.push(format!("Moisture: avg {:.1}%, min {:.1}%, max {:.1}% ({} samples)", average, min, max, readings.len()));
+        }
+
+        let failed = runs.iter().filter(|r| matches!(r.outcome, RunOutcome::Failed(_))).count();
+        let stopped = runs.iter().filter(|r| r.outcome == RunOutcome::Stopped).count();
+        let seconds: u64 = runs.iter().filter(|r| r.outcome == RunOutcome::Ok).map(|r| r.run.seconds).sum();
+        lines.push(format!("Watering runs: {} ({} failed, {} stopped), {} seconds in total", runs.len(), failed, stopped, seconds));
+
+        if let Some(last) = runs.last() {
+            lines.push(format!(
+                "Last run: {} {} for {} seconds ({}), {}",
+                local_time(last.run.started_at, timezone), last.run.zone, last.run.seconds, last.run.trigger, last.outcome
+            ));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Downsamples raw readings older than the raw retention into hourly aggregates,
+    /// hourly aggregates older than the hourly retention into daily ones, and drops
+    /// watering runs older than the runs retention.
+    pub fn apply_retention(&mut self, config: &Config, now: DateTime<Utc>) -> rusqlite::Result<()> {
+        let now = now.timestamp();
+        let raw_cutoff = (now - config.history_raw_retention_days * DAY) / HOUR * HOUR;
+        let hourly_cutoff = (now - config.history_hourly_retention_days * DAY) / DAY * DAY;
+        let runs_cutoff = now - config.history_runs_retention_days * DAY;
+
+        // readings are either still raw or already downsampled, never both or neither
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute(
+            "INSERT INTO readings_hourly (zone, at, moisture_avg, moisture_min, moisture_max, samples)
+             SELECT zone, at / ?2 * ?2, AVG(moisture), MIN(moisture), MAX(moisture), COUNT(*)
+             FROM readings WHERE at < ?1 GROUP BY zone, at / ?2
+             ON CONFLICT (zone, at) DO UPDATE SET
+                moisture_avg = (moisture_avg * samples + excluded.moisture_avg * excluded.samples) / (samples + excluded.samples),
+                moisture_min = MIN(moisture_min, excluded.moisture_min),
+                moisture_max = MAX(moisture_max, excluded.moisture_max),
+                samples = samples + excluded.samples",
+            params![raw_cutoff, HOUR]
+        )?;
+        transaction.execute("DELETE FROM readings WHERE at < ?1", params![raw_cutoff])?;
+
+        transaction.execute(
+            "INSERT INTO readings_daily (zone, at, moisture_avg, moisture_min, moisture_max, samples)
+             SELECT zone, at / ?2 * ?2, SUM(moisture_avg * samples) / SUM(samples), MIN(moisture_min), MAX(moisture_max), SUM(samples)
+             FROM readings_hourly WHERE at < ?1 GROUP BY zone, at / ?2
+             ON CONFLICT (zone, at) DO UPDATE SET
+                moisture_avg = (moisture_avg * samples + excluded.moisture_avg * excluded.samples) / (samples + excluded.samples),
+                moisture_min = MIN(moisture_min, excluded.moisture_min),
+                moisture_max = MAX(moisture_max, excluded.moisture_max),
+                samples = samples + excluded.samples",
+            params![hourly_cutoff, DAY]
+        )?;
+        transaction.execute("DELETE FROM readings_hourly WHERE at < ?1", params![hourly_cutoff])?;
+
+        transaction.execute("DELETE FROM runs WHERE started_at < ?1", params![runs_cutoff])?;
+
+        transaction.commit()
+    }
+}
@@ -0,0 +1,86 @@
+// internal
+use crate::config::{Config};
+use crate::definitions::{PeriodicTaskType};
+use crate::mqtt::{availability_topic, MqttMessage, ONLINE, OFFLINE};
+
+// external
+use serde_json::{json, Value};
+
+
+/// Topic Home Assistant announces itself on after a restart, discovery has to be sent again then.
+pub fn birth_topic(config: &Config) -> String {
+    format!("{}/status", config.home_assistant_discovery_prefix)
+}
+
+fn object_id(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+fn device(config: &Config) -> Value {
+    json!({ "identifiers": [config.mqtt_client_id], "name": "Smart Garden", "manufacturer": "smart_garden" })
+}
+
+fn entity(config: &Config, component: &str, object: &str, mut payload: Value) -> MqttMessage {
+    let prefix = &config.mqtt_topic_prefix;
+    let unique_id = format!("{}_{}", config.mqtt_client_id, object);
+
+    payload["unique_id"] = json!(unique_id);
+    payload["object_id"] = json!(unique_id);
+    payload["availability_topic"] = json!(availability_topic(prefix));
+    payload["payload_available"] = json!(ONLINE);
+    payload["payload_not_available"] = json!(OFFLINE);
+    payload["device"] = device(config);
+
+    MqttMessage {
+        topic: format!("{}/{}/{}/{}/config", config.home_assistant_discovery_prefix, component, object_id(&config.mqtt_client_id), object),
+        payload: payload.to_string(),
+        retain: true
+    }
+}
+
+/// One switch per zone, one sensor per moisture probe, one text entity per schedule and a pause switch.
+pub fn discovery_messages(config: &Config) -> Vec<MqttMessage> {
+    let prefix = &config.mqtt_topic_prefix;
+    let mut messages = Vec::new();
+
+    for zone in &config.zones {
+        let object = object_id(&zone.name);
+
+        messages.push(entity(config, "switch", &format!("{}_pump", object), json!({
+            "name": format!("{} watering", zone.name),
+            "icon": "mdi:sprinkler-variant",
+            "state_topic": format!("{}/zones/{}/pump", prefix, zone.name),
+            "command_topic": format!("{}/zones/{}/pump/set", prefix, zone.name)
+        })));
+
+        if zone.moisture_sensor_pin.is_some() {
+            messages.push(entity(config, "sensor", &format!("{}_moisture", object), json!({
+                "name": format!("{} moisture", zone.name),
+                "device_class": "moisture",
+                "state_class": "measurement",
+                "unit_of_measurement": "%",
+                "state_topic": format!("{}/zones/{}/moisture", prefix, zone.name)
+            })));
+        }
+    }
+
+    for task_type in [PeriodicTaskType::WaterPlants, PeriodicTaskType::ReadMoistureSensorsData] {
+        messages.push(entity(config, "text", &format!("{}_schedule", object_id(&task_type.to_string())), json!({
+            "name": format!("{} schedule", task_type),
+            "icon": "mdi:calendar-clock",
+            "entity_category": "config",
+            "state_topic": format!("{}/tasks/{}/cron", prefix, task_type),
+            "command_topic": format!("{}/tasks/{}/set", prefix, task_type)
+        })));
+    }
+
+    messages.push(entity(config, "switch", "paused", json!({
+        "name": "Pause schedules",
+        "icon": "mdi:pause-circle",
+        "entity_category": "config",
+        "state_topic": format!("{}/paused", prefix),
+        "command_topic": format!("{}/pause", prefix)
+    })));
+
+    messages
+}
@@ -0,0 +1,347 @@
+// standard
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+
+// internal
+use crate::control::{Controller, PREVIEW_RUNS, parse_task_type};
+use crate::chart::{render_moisture_chart};
+use crate::definitions::{CatchUp, PeriodicTask, TaskMetaData, Trigger, WateringRequest, Zone};
+use crate::error::{GardenError, GardenResult};
+use crate::export::{event_json, reading_json, run_json, run_record_json};
+use crate::history::{parse_period, period_start};
+use crate::metrics::{METRICS};
+use crate::schedule;
+
+// external
+use axum::{Json, Router};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post, put};
+use chrono::Utc;
+use futures::stream::{self, Stream};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+
+const DASHBOARD_HTML: &str = include_str!("dashboard/index.html");
+const DASHBOARD_JS: &str = include_str!("dashboard/app.js");
+const DASHBOARD_CSS: &str = include_str!("dashboard/style.css");
+
+
+impl IntoResponse for GardenError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            GardenError::UnknownZone(_) | GardenError::UnknownTask(_) => StatusCode::NOT_FOUND,
+            GardenError::ChannelClosed(_) | GardenError::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+            GardenError::InvalidDuration(_) | GardenError::InvalidTask(_) | GardenError::InvalidSchedule { .. } | GardenError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR
+        };
+
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+type ApiResult = Result<Response, GardenError>;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WaterBody {
+    pub seconds: Option<u64>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TaskBody {
+    pub task_type: Option<String>,
+    pub cron: String,
+    #[serde(default)]
+    pub disabled: bool
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    pub schedule: String
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    pub zone: Option<String>,
+    pub period: Option<String>
+}
+
+fn accepted() -> Response {
+    (StatusCode::ACCEPTED, Json(json!({ "status": "accepted" }))).into_response()
+}
+
+/// Task changes are applied by the time the scheduler replies.
+fn done() -> Response {
+    (StatusCode::OK, Json(json!({ "status": "ok" }))).into_response()
+}
+
+fn zone_json(zone: &Zone) -> Value {
+    json!({ "name": zone.name, "pump_pin": zone.pump_pin, "moisture_sensor_pin": zone.moisture_sensor_pin })
+}
+
+fn task_json(meta: &TaskMetaData) -> Value {
+    json!({
+        "job_id": meta.job_id.to_string(), "task_type": meta.task.task_type.to_string(), "cron": meta.task.cron, "disabled": meta.task.disabled,
+        "paused": meta.paused, "catch_up": meta.task.catch_up.to_string(),
+        "description": schedule::describe(&meta.task.cron),
+        "next_run": meta.next_run().map(|next_run| next_run.to_rfc3339()),
+        "next_runs": meta.next_runs.iter().map(|at| at.to_rfc3339()).collect::<Vec<_>>(),
+        "last_run": meta.last_run.as_ref().map(|last_run| json!({
+            "at": last_run.at.to_rfc3339(), "outcome": last_run.outcome.as_ref().map(|outcome| outcome.to_string())
+        }))
+    })
+}
+
+fn parse_history_query(controller: &Controller, query: &HistoryQuery) -> GardenResult<chrono::DateTime<Utc>> {
+    if let Some(zone) = &query.zone {
+        if controller.config().zone(zone).is_none() {
+            return Err(GardenError::UnknownZone(zone.clone()));
+        }
+    }
+
+    let period = match &query.period {
+        Some(period) => parse_period(period).ok_or_else(|| GardenError::InvalidInput(format!("{} is not a period like 24h or 7d", period)))?,
+        None => chrono::Duration::hours(24)
+    };
+
+    period_start(controller.clock().now(), period)
+}
+
+async fn list_zones(State(controller): State<Controller>) -> ApiResult {
+    let status = controller.status();
+    let zones: Vec<_> = controller.config().zones.iter().map(|zone| {
+        let mut value = zone_json(zone);
+        value["moisture"] = json!(status.moisture.get(&zone.name).map(reading_json));
+        value["watering"] = json!(status.current_run.as_ref().map(|run| run.zone == zone.name).unwrap_or(false));
+        value
+    }).collect();
+
+    Ok(Json(zones).into_response())
+}
+
+async fn water(controller: Controller, zone: Option<String>, body: Option<Json<WaterBody>>) -> ApiResult {
+    let seconds = body.and_then(|Json(body)| body.seconds).unwrap_or(controller.config().water_pump_working_cycle_seconds);
+    controller.water(WateringRequest { zone, seconds, trigger: Trigger::Api })?;
+    Ok(accepted())
+}
+
+async fn water_zone(State(controller): State<Controller>, Path(zone): Path<String>, body: Option<Json<WaterBody>>) -> ApiResult {
+    water(controller, Some(zone), body).await
+}
+
+async fn water_all(State(controller): State<Controller>, body: Option<Json<WaterBody>>) -> ApiResult {
+    water(controller, None, body).await
+}
+
+async fn stop(State(controller): State<Controller>) -> ApiResult {
+    controller.stop()?;
+    Ok(accepted())
+}
+
+async fn stop_zone(State(controller): State<Controller>, Path(zone): Path<String>) -> ApiResult {
+    controller.stop_zone(&zone)?;
+    Ok(accepted())
+}
+
+async fn list_tasks(State(controller): State<Controller>) -> ApiResult {
+    let tasks: Vec<_> = controller.list_tasks().await?.iter().map(task_json).collect();
+    Ok(Json(tasks).into_response())
+}
+
+async fn add_task(State(controller): State<Controller>, Json(body): Json<TaskBody>) -> ApiResult {
+    let task_type = body.task_type.ok_or_else(|| GardenError::InvalidInput("task_type is required".to_string()))?;
+    let task = PeriodicTask { task_type: parse_task_type(&task_type)?, cron: body.cron, disabled: body.disabled, catch_up: CatchUp::default() };
+    controller.add_task(task).await?;
+    Ok(done())
+}
+
+async fn update_task(State(controller): State<Controller>, Path(task_type): Path<String>, Json(body): Json<TaskBody>) -> ApiResult {
+    let task = PeriodicTask { task_type: parse_task_type(&task_type)?, cron: body.cron, disabled: body.disabled, catch_up: CatchUp::default() };
+    controller.update_task(task).await?;
+    Ok(done())
+}
+
+async fn remove_task(State(controller): State<Controller>, Path(task_type): Path<String>) -> ApiResult {
+    controller.remove_task(parse_task_type(&task_type)?).await?;
+    Ok(done())
+}
+
+async fn reset_tasks(State(controller): State<Controller>) -> ApiResult {
+    controller.reset_tasks().await?;
+    Ok(done())
+}
+
+async fn preview_schedule(State(controller): State<Controller>, Query(query): Query<PreviewQuery>) -> ApiResult {
+    let runs = controller.preview_schedule(&query.schedule, PREVIEW_RUNS)?;
+    let next_runs: Vec<_> = runs.iter().map(|at| at.to_rfc3339()).collect();
+    Ok(Json(json!({ "description": schedule::describe(&query.schedule), "next_runs": next_runs })).into_response())
+}
+
+async fn readings(State(controller): State<Controller>, Query(query): Query<HistoryQuery>) -> ApiResult {
+    let since = parse_history_query(&controller, &query)?;
+    let readings = controller.read_history(move |history| Ok(history.readings(query.zone.as_deref(), since)?)).await?;
+    let readings: Vec<_> = readings.iter().map(reading_json).collect();
+    Ok(Json(readings).into_response())
+}
+
+async fn history(State(controller): State<Controller>, Query(query): Query<HistoryQuery>) -> ApiResult {
+    let since = parse_history_query(&controller, &query)?;
+    let (readings, runs) = controller.read_history(move |history| {
+        Ok((history.readings(query.zone.as_deref(), since)?, history.runs(query.zone.as_deref(), since)?))
+    }).await?;
+    let readings: Vec<_> = readings.iter().map(reading_json).collect();
+    let runs: Vec<_> = runs.iter().map(run_record_json).collect();
+    Ok(Json(json!({ "readings": readings, "runs": runs })).into_response())
+}
+
+async fn status(State(controller): State<Controller>) -> ApiResult {
+    let status = controller.status();
+    let zones: Vec<_> = controller.config().zones.iter().map(|zone| json!({
+        "name": zone.name,
+        "moisture": status.moisture.get(&zone.name).map(reading_json),
+        "sensor_failure": status.sensor_failures.get(&zone.name),
+        "last_run": status.last_runs.get(&zone.name).map(run_record_json)
+    })).collect();
+
+    let last_skipped = status.last_skipped.as_ref().map(|skipped| json!({ "timestamp": skipped.at.to_rfc3339(), "reason": skipped.reason }));
+
+    Ok(Json(json!({
+        "current_run": status.current_run.as_ref().map(run_json),
+        "rain_sensor_wet": status.rain_sensor_wet,
+        "last_skipped": last_skipped,
+        "zones": zones
+    })).into_response())
+}
+
+async fn chart(State(controller): State<Controller>, Query(query): Query<HistoryQuery>) -> ApiResult {
+    let since = parse_history_query(&controller, &query)?;
+    let to = controller.clock().now();
+    let png = controller.read_history(move |history| {
+        let readings = history.readings(query.zone.as_deref(), since)?;
+        let runs = history.runs(query.zone.as_deref(), since)?;
+        render_moisture_chart(&readings, &runs, since, to).map_err(|error| GardenError::Render(error.to_string()))
+    }).await?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Prometheus scrape target, queue depths are sampled on every scrape.
+async fn metrics(State(controller): State<Controller>) -> Response {
+    for (channel, depth) in controller.queue_depths() {
+        METRICS.set_queue_depth(channel, depth);
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render()).into_response()
+}
+
+/// Server-sent events with every garden event as JSON, for the dashboard's live updates.
+async fn events(State(controller): State<Controller>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(controller.subscribe_events(), |mut event_receiver| async move {
+        loop {
+            match event_receiver.recv().await {
+                Ok(event) => {
+                    let event = Event::default().data(event_json(&event).to_string());
+                    return Some((Ok(event), event_receiver));
+                },
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD_HTML)
+}
+
+async fn dashboard_js() -> Response {
+    ([(header::CONTENT_TYPE, "application/javascript")], DASHBOARD_JS).into_response()
+}
+
+async fn dashboard_css() -> Response {
+    ([(header::CONTENT_TYPE, "text/css")], DASHBOARD_CSS).into_response()
+}
+
+/// Takes as long for every wrong token of the right length, so it can't be guessed byte by byte.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+async fn authenticate<B>(State(token): State<String>, request: Request<B>, next: Next<B>) -> Response {
+    let header_token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // EventSource can't set headers, so only the event stream may pass the token in the query
+    let query_token = request.uri().query()
+        .filter(|_| request.uri().path() == "/events")
+        .and_then(|query| form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "access_token").map(|(_, value)| value));
+
+    let authorized = header_token.or(query_token.as_deref()).map(|value| same_token(value, &token)).unwrap_or(false);
+
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "missing or invalid token" }))).into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Every API route requires an `Authorization: Bearer <token>` header,
+/// the dashboard's static files are public.
+pub fn router(controller: Controller, token: String) -> Router {
+    Router::new()
+        .route("/zones", get(list_zones))
+        .route("/zones/:zone/water", post(water_zone))
+        .route("/zones/:zone/stop", post(stop_zone))
+        .route("/water", post(water_all))
+        .route("/stop", post(stop))
+        .route("/tasks", get(list_tasks).post(add_task))
+        .route("/tasks/reset", post(reset_tasks))
+        .route("/tasks/:task_type", put(update_task).delete(remove_task))
+        .route("/schedules/preview", get(preview_schedule))
+        .route("/readings", get(readings))
+        .route("/history", get(history))
+        .route("/status", get(status))
+        .route("/chart", get(chart))
+        .route("/events", get(events))
+        .route("/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(token, authenticate))
+        .route("/", get(dashboard))
+        .route("/app.js", get(dashboard_js))
+        .route("/style.css", get(dashboard_css))
+        .with_state(controller)
+}
+
+pub struct HttpApi {} impl HttpApi {
+    pub async fn run_async(controller: Controller) {
+        let token = match env::var("HTTP_API_TOKEN") {
+            Ok(token) if !token.is_empty() => token,
+            _ => {
+                info!("HTTP_API_TOKEN is not set, the HTTP API is disabled");
+                return;
+            }
+        };
+
+        let address: SocketAddr = match controller.config().http_api_address.parse() {
+            Ok(address) => address,
+            Err(error) => {
+                error!(address = %controller.config().http_api_address, %error, "Invalid HTTP API address");
+                return;
+            }
+        };
+
+        let app = router(controller, token);
+        if let Err(error) = axum::Server::bind(&address).serve(app.into_make_service()).await {
+            error!(%error, "HTTP API stopped");
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Waters a garden on a schedule. The scheduler, the worker and the hardware they drive
+//! can be reused on their own, `Garden` puts them together the way the `smart_garden` binary runs them.
+
+// modules
+pub mod scheduler;
+pub mod config;
+pub mod definitions;
+pub mod worker;
+pub mod history;
+pub mod chart;
+pub mod export;
+pub mod status;
+pub mod control;
+pub mod metrics;
+pub mod logging;
+pub mod error;
+pub mod clock;
+pub mod hardware;
+pub mod simulation;
+pub mod garden;
+pub mod schedule;
+pub mod sun;
+pub mod weather;
+pub mod rain;
+#[cfg(feature = "gpio")]
+pub mod gpio;
+#[cfg(feature = "http")]
+pub mod http_api;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "mqtt")]
+pub mod home_assistant;
+#[cfg(feature = "ctl")]
+pub mod ctl;
+#[cfg(feature = "telegram")]
+pub mod telegram_bot;
+#[cfg(feature = "telegram")]
+// kept as written upstream
+#[allow(clippy::redundant_static_lifetimes, clippy::redundant_slicing, clippy::len_zero)]
+pub mod reply_text;
+#[cfg(feature = "telegram")]
+pub mod notifier;
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(not(any(feature = "telegram", feature = "http", feature = "mqtt", feature = "ctl")))]
+compile_error!("nothing could control the garden, enable at least one of the telegram, http, mqtt or ctl features");
+
+pub use clock::{Clock, SharedClock, SystemClock};
+pub use config::{Config};
+pub use control::{Controller};
+pub use definitions::{GardenEvent, NotifierChannelMessage, PeriodicTask, PeriodicTaskType, SchedulerChannelMessage, Traced, WateringRequest, WorkerChannelMessage};
+pub use error::{GardenError, GardenResult};
+pub use garden::{Garden, GardenBuilder};
+pub use hardware::{Actuator, DigitalInput, Hardware, MoistureSensor};
+pub use scheduler::{Scheduler};
+pub use weather::{WeatherProvider};
+pub use worker::{Worker};
@@ -0,0 +1,98 @@
+// standard
+use std::env;
+use std::str::FromStr;
+
+// internal
+use crate::config::{Config};
+
+// external
+use tracing::{warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogOutput {
+    Stdout,
+    Journald,
+    /// A new file every day, e.g. `smart_garden.log.2022-06-01`.
+    RotatingFile { directory: String }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<LogFormat, Self::Err> {
+        match input {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for LogOutput {
+    type Err = ();
+
+    /// `stdout`, `journald` or `file:<directory>`.
+    fn from_str(input: &str) -> Result<LogOutput, Self::Err> {
+        match input.split_once(':') {
+            Some(("file", directory)) if !directory.is_empty() => Ok(LogOutput::RotatingFile { directory: directory.to_string() }),
+            None if input == "stdout" => Ok(LogOutput::Stdout),
+            None if input == "journald" => Ok(LogOutput::Journald),
+            _ => Err(()),
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).with_ansi(false).boxed(),
+    }
+}
+
+/// `RUST_LOG` overrides the configured filter, e.g. `RUST_LOG=info,smart_garden::scheduler=debug`.
+fn filter(config: &Config) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log_filter))
+}
+
+/// Installs the global subscriber, keep the returned guard alive or buffered file logs are lost.
+/// `LOG_FORMAT` and `LOG_OUTPUT` override the configured format and output.
+pub fn init(config: &Config) -> Option<WorkerGuard> {
+    let format = env::var("LOG_FORMAT").ok().and_then(|format| format.parse().ok()).unwrap_or(config.log_format);
+    let output = env::var("LOG_OUTPUT").ok().and_then(|output| output.parse().ok()).unwrap_or_else(|| config.log_output.clone());
+
+    let (layer, guard, journald_error) = match output {
+        LogOutput::Stdout => (fmt_layer(format, std::io::stdout, true), None, None),
+        LogOutput::Journald => match tracing_journald::layer() {
+            Ok(layer) => (layer.boxed(), None, None),
+            Err(error) => (fmt_layer(format, std::io::stdout, true), None, Some(error)),
+        },
+        LogOutput::RotatingFile { directory } => {
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::daily(directory, "smart_garden.log"));
+            (fmt_layer(format, writer, false), Some(guard), None)
+        }
+    };
+
+    tracing_subscriber::registry().with(layer.with_filter(filter(config))).init();
+
+    if let Some(error) = journald_error {
+        warn!(%error, "journald is not available, logging to stdout");
+    }
+
+    guard
+}
@@ -1,50 +1,94 @@
-// modules
-mod scheduler;
-mod config;
-mod definitions;
-mod telegram_bot;
-mod worker;
-mod reply_text;
-
-#[cfg(test)]
-mod tests;
+// standard
+use std::sync::Arc;
 
 // internal
-use config::{Config};
-use definitions::{SchedulerChannelMessage, WorkerChannelMessage};
-use scheduler::{Scheduler};
-use worker::{Worker};
-use telegram_bot::{TelegramBot};
+use smart_garden::clock::{AcceleratedClock, SharedClock};
+use smart_garden::config::{Config};
+#[cfg(feature = "ctl")]
+use smart_garden::ctl::{self, CtlServer};
+use smart_garden::garden::{Garden};
+#[cfg(feature = "http")]
+use smart_garden::http_api::{HttpApi};
+use smart_garden::logging;
+#[cfg(feature = "mqtt")]
+use smart_garden::mqtt::{Mqtt};
+use smart_garden::simulation::{SimulatedGarden};
+#[cfg(feature = "telegram")]
+use smart_garden::notifier::{Notifier};
+#[cfg(feature = "telegram")]
+use smart_garden::telegram_bot::{TelegramBot};
 
 //external
 use dotenv::dotenv;
-use crossbeam_channel::{bounded, Sender, Receiver};
 use lazy_static::lazy_static;
+use tracing::{info};
 
 lazy_static! {
-    static ref CONFIG: Config = Config::new();
+    static ref CONFIG: Config = if simulate() { Config::simulation() } else { Config::new() };
+}
+
+/// `smart_garden --simulate` runs the whole garden on a laptop, without GPIO and with time sped up.
+/// Builds without the gpio feature have nothing else to drive.
+fn simulate() -> bool {
+    !cfg!(feature = "gpio") || std::env::args().skip(1).any(|arg| arg == "--simulate")
 }
 
 #[tokio::main]
 async fn main() {
+    // setup env config, `ctl` reads the socket path from it too
+    dotenv().ok();
+
+    // `smart_garden ctl ...` talks to the running daemon instead of starting one
+    #[cfg(feature = "ctl")]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    #[cfg(feature = "ctl")]
+    if args.first().map(String::as_str) == Some("ctl") {
+        std::process::exit(ctl::run_cli(&CONFIG, &args[1..]));
+    }
+
     // setup system signal handler
     ctrlc::set_handler(move || {
         std::process::exit(1);
     })
     .expect("Error setting Ctrl-C handler");
 
-    // setup env config
-    dotenv().ok();
+    // setup logging, the guard flushes buffered file logs on exit
+    let _log_guard = logging::init(&CONFIG);
 
-    // setup communication channels between all threads (SchedulerThread, WorkerThread, TelegramBotThread (main thread))
-    let (scheduler_channel_sender, scheduler_channel_receiver): (Sender<SchedulerChannelMessage>, Receiver<SchedulerChannelMessage>) = bounded(CONFIG.messages_in_flight_limit);
-    let (worker_channel_sender, worker_channel_receiver): (Sender<WorkerChannelMessage>, Receiver<WorkerChannelMessage>) = bounded(CONFIG.messages_in_flight_limit);
+    // setup scheduler, worker, history, status and metrics on the wall clock and the GPIO pins,
+    // or on sped up time and simulated pumps and sensors
+    let mut builder = Garden::builder(&CONFIG);
+    if simulate() {
+        info!(speed = CONFIG.simulation_speed, "Simulating the garden");
+        let clock: SharedClock = Arc::new(AcceleratedClock::new(CONFIG.simulation_speed));
+        builder = builder.clock(clock.clone()).hardware(SimulatedGarden::new(&CONFIG, clock).hardware());
+    }
 
-    // setup threads
-    Scheduler::run_threaded(&CONFIG, scheduler_channel_receiver, &worker_channel_sender);
-    Worker::run_threaded(&CONFIG, worker_channel_receiver);
+    // every control surface goes through the same controller
+    let garden = builder.start();
+    let controller = garden.controller;
 
-    // setup telegram bot server and listen for incoming messages
-    TelegramBot::run_async(&CONFIG, worker_channel_sender, scheduler_channel_sender).await;
-}
+    // setup local http api
+    #[cfg(feature = "http")]
+    tokio::spawn(HttpApi::run_async(controller.clone()));
+
+    // setup local command line access over a unix socket
+    #[cfg(feature = "ctl")]
+    tokio::spawn(CtlServer::run_async(controller.clone()));
 
+    // setup mqtt bridge to the home-automation broker
+    #[cfg(feature = "mqtt")]
+    tokio::spawn(Mqtt::run_async(controller.clone()));
+
+    // setup telegram notifications, bot server and listen for incoming messages
+    #[cfg(feature = "telegram")]
+    if std::env::var("TELOXIDE_TOKEN").is_ok() {
+        Notifier::run_threaded(&CONFIG, controller.clock().clone(), garden.notifier_channel_receiver, garden.event_receiver);
+        TelegramBot::run_async(controller).await;
+        return;
+    }
+
+    // the other control surfaces drive the garden
+    info!("Running without the Telegram bot");
+    futures::future::pending::<()>().await
+}
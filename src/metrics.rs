@@ -0,0 +1,120 @@
+// standard
+use std::thread;
+use std::time::Duration;
+
+// internal
+use crate::clock::{SharedClock};
+use crate::definitions::{GardenEvent, WateringRun};
+
+// external
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use prometheus::{CounterVec, Encoder, Gauge, GaugeVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tracing::{warn};
+
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::new();
+}
+
+/// Prometheus metrics, counters only ever go up for the lifetime of the process.
+pub struct Metrics {
+    registry: Registry,
+    pump_on_seconds: CounterVec,
+    runs: IntCounterVec,
+    moisture: GaugeVec,
+    scheduler_tick_lag: Gauge,
+    queue_depth: GaugeVec,
+    telegram_send_failures: IntCounter
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("smart_garden".to_string()), None).unwrap();
+
+        // a float counter, pump time is not a whole number of seconds once a run is stopped
+        let pump_on_seconds = CounterVec::new(Opts::new("pump_on_seconds_total", "Seconds the pump was on, per zone"), &["zone"]).unwrap();
+        let runs = IntCounterVec::new(Opts::new("runs_total", "Watering runs by zone, trigger and outcome"), &["zone", "trigger", "outcome"]).unwrap();
+        let moisture = GaugeVec::new(Opts::new("moisture_percent", "Latest moisture reading, per zone"), &["zone"]).unwrap();
+        let scheduler_tick_lag = Gauge::new("scheduler_tick_lag_seconds", "How much later than planned the last periodic task ran").unwrap();
+        let queue_depth = GaugeVec::new(Opts::new("channel_queue_depth", "Messages waiting in a thread's channel"), &["channel"]).unwrap();
+        let telegram_send_failures = IntCounter::new("telegram_send_failures_total", "Telegram messages that could not be sent").unwrap();
+
+        registry.register(Box::new(pump_on_seconds.clone())).unwrap();
+        registry.register(Box::new(runs.clone())).unwrap();
+        registry.register(Box::new(moisture.clone())).unwrap();
+        registry.register(Box::new(scheduler_tick_lag.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(telegram_send_failures.clone())).unwrap();
+
+        Metrics { registry, pump_on_seconds, runs, moisture, scheduler_tick_lag, queue_depth, telegram_send_failures }
+    }
+
+    fn finish_run(&self, run: &WateringRun, outcome: &str, on_seconds: f64) {
+        self.pump_on_seconds.with_label_values(&[&run.zone]).inc_by(on_seconds);
+        self.runs.with_label_values(&[&run.zone, &run.trigger.to_string(), outcome]).inc();
+    }
+
+    /// `now` is when the event was received, on the garden's clock.
+    pub fn apply(&self, event: &GardenEvent, now: DateTime<Utc>) {
+        // interrupted runs only count the time the pump actually ran
+        let elapsed = |run: &WateringRun| {
+            let seconds = (now - run.started_at).num_milliseconds() as f64 / 1000.0;
+            seconds.clamp(0.0, run.seconds as f64)
+        };
+
+        match event {
+            GardenEvent::WateringStarted(_) => {},
+            GardenEvent::WateringFinished(run) => self.finish_run(run, "ok", run.seconds as f64),
+            GardenEvent::WateringStopped(run) => self.finish_run(run, "stopped", elapsed(run)),
+            GardenEvent::WateringFailed(run, _) => self.finish_run(run, "failed", elapsed(run)),
+            GardenEvent::MoistureRead(reading) => self.moisture.with_label_values(&[&reading.zone]).set(reading.moisture),
+            GardenEvent::WateringSkipped(_) | GardenEvent::RainSensorChanged(_) | GardenEvent::SensorFailed(_, _) | GardenEvent::TaskRan(_, _) | GardenEvent::TasksChanged | GardenEvent::Failure(_) => {},
+        }
+    }
+
+    pub fn set_scheduler_tick_lag(&self, lag: Duration) {
+        self.scheduler_tick_lag.set(lag.as_secs_f64());
+    }
+
+    pub fn set_queue_depth(&self, channel: &str, depth: usize) {
+        self.queue_depth.with_label_values(&[channel]).set(depth as f64);
+    }
+
+    pub fn telegram_send_failed(&self) {
+        self.telegram_send_failures.inc();
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    pub fn run_threaded(metrics: &'static Metrics, clock: SharedClock, mut event_receiver: broadcast::Receiver<GardenEvent>) {
+        thread::spawn(move || {
+            let runtime = Runtime::new().unwrap();
+
+            runtime.block_on(async move {
+                loop {
+                    match event_receiver.recv().await {
+                        Ok(event) => metrics.apply(&event, clock.now()),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!(skipped, "Metrics fell behind, events were dropped");
+                        },
+                        Err(broadcast::error::RecvError::Closed) => break
+                    }
+                }
+            });
+        });
+    }
+}
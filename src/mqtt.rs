@@ -0,0 +1,313 @@
+// standard
+use std::env;
+use std::time::Duration;
+
+// internal
+use crate::control::{Controller, parse_task_type};
+use crate::definitions::{CorrelationId, GardenEvent, PeriodicTaskType, TaskMetaData, Trigger, WateringRequest};
+use crate::error::{GardenError, GardenResult};
+use crate::home_assistant::{birth_topic, discovery_messages};
+use crate::export::{event_json};
+
+// external
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use serde_json::json;
+use tracing::{error, info, info_span, warn, Instrument};
+
+
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+/// A message to publish, state topics are retained so late subscribers see the current value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool
+}
+
+impl MqttMessage {
+    fn state(topic: String, payload: impl Into<String>) -> Self {
+        MqttMessage { topic, payload: payload.into(), retain: true }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MqttCommand {
+    Water(WateringRequest),
+    Stop,
+    /// Turning off one zone's pump switch.
+    StopZone(String),
+    Pause(bool),
+    /// A new schedule, and whether the task is disabled if the payload says so.
+    UpdateTask { task_type: PeriodicTaskType, cron: String, disabled: Option<bool> }
+}
+
+#[derive(Deserialize)]
+struct WaterPayload {
+    zone: Option<String>,
+    seconds: Option<u64>
+}
+
+#[derive(Deserialize)]
+struct TaskPayload {
+    cron: String,
+    #[serde(default)]
+    disabled: Option<bool>
+}
+
+/// Topics the garden listens on, relative to the configured prefix.
+pub fn command_topics(prefix: &str) -> Vec<String> {
+    ["water", "zones/+/water", "zones/+/pump/set", "stop", "pause", "tasks/+/set"]
+        .iter()
+        .map(|topic| format!("{}/{}", prefix, topic))
+        .collect()
+}
+
+pub fn availability_topic(prefix: &str) -> String {
+    format!("{}/availability", prefix)
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "ON" } else { "OFF" }
+}
+
+/// Anything but a clear on or off, an empty payload included, is rejected.
+fn parse_switch(payload: &str) -> Option<bool> {
+    match payload.trim().to_lowercase().as_str() {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Watering payloads are empty (the configured cycle), a number of seconds or
+/// `{"zone": "tomatoes", "seconds": 30}`.
+fn parse_water(zone: Option<String>, payload: &str, default_seconds: u64) -> Option<WateringRequest> {
+    let (zone, seconds) = if payload.is_empty() {
+        (zone, default_seconds)
+    } else if let Ok(seconds) = payload.parse() {
+        (zone, seconds)
+    } else {
+        let body: WaterPayload = serde_json::from_str(payload).ok()?;
+        (zone.or(body.zone), body.seconds.unwrap_or(default_seconds))
+    };
+
+    Some(WateringRequest { zone, seconds, trigger: Trigger::Mqtt })
+}
+
+/// Task updates are a plain cron expression or `{"cron": "...", "disabled": false}`,
+/// what the payload leaves out stays as it is.
+fn parse_task(task_type: &str, payload: &str) -> Option<MqttCommand> {
+    let task_type = parse_task_type(task_type).ok()?;
+    let body = match serde_json::from_str::<TaskPayload>(payload) {
+        Ok(body) => body,
+        Err(_) if !payload.trim().is_empty() => TaskPayload { cron: payload.trim().to_string(), disabled: None },
+        Err(_) => return None,
+    };
+
+    Some(MqttCommand::UpdateTask { task_type, cron: body.cron, disabled: body.disabled })
+}
+
+/// Maps an incoming publish to a command, `None` for unknown topics or malformed payloads.
+pub fn parse_command(prefix: &str, topic: &str, payload: &str, default_seconds: u64) -> Option<MqttCommand> {
+    let path = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let payload = payload.trim();
+
+    match path.split('/').collect::<Vec<_>>().as_slice() {
+        ["water"] => parse_water(None, payload, default_seconds).map(MqttCommand::Water),
+        ["zones", zone, "water"] => parse_water(Some(zone.to_string()), payload, default_seconds).map(MqttCommand::Water),
+        ["zones", zone, "pump", "set"] => match parse_switch(payload)? {
+            true => parse_water(Some(zone.to_string()), "", default_seconds).map(MqttCommand::Water),
+            false => Some(MqttCommand::StopZone(zone.to_string())),
+        },
+        ["stop"] => Some(MqttCommand::Stop),
+        ["pause"] => parse_switch(payload).map(MqttCommand::Pause),
+        ["tasks", task_type, "set"] => parse_task(task_type, payload),
+        _ => None,
+    }
+}
+
+/// Everything an event changes: pump state, the latest moisture and the raw event.
+pub fn event_messages(prefix: &str, event: &GardenEvent) -> Vec<MqttMessage> {
+    let mut messages = match event {
+        GardenEvent::WateringStarted(run) => vec![
+            MqttMessage::state(format!("{}/pump", prefix), on_off(true)),
+            MqttMessage::state(format!("{}/zones/{}/pump", prefix, run.zone), on_off(true)),
+        ],
+        GardenEvent::WateringFinished(run) | GardenEvent::WateringStopped(run) | GardenEvent::WateringFailed(run, _) => vec![
+            MqttMessage::state(format!("{}/pump", prefix), on_off(false)),
+            MqttMessage::state(format!("{}/zones/{}/pump", prefix, run.zone), on_off(false)),
+        ],
+        GardenEvent::MoistureRead(reading) => vec![
+            MqttMessage::state(format!("{}/zones/{}/moisture", prefix, reading.zone), format!("{:.1}", reading.moisture)),
+        ],
+        GardenEvent::WateringSkipped(_) | GardenEvent::RainSensorChanged(_) | GardenEvent::SensorFailed(_, _) | GardenEvent::TaskRan(_, _) | GardenEvent::TasksChanged | GardenEvent::Failure(_) => vec![],
+    };
+
+    messages.push(MqttMessage { topic: format!("{}/events", prefix), payload: event_json(event).to_string(), retain: false });
+    messages
+}
+
+/// Current schedules, so dashboards can show and edit them.
+pub fn task_messages(prefix: &str, tasks: &[TaskMetaData]) -> Vec<MqttMessage> {
+    tasks.iter()
+        .map(|meta| MqttMessage::state(format!("{}/tasks/{}/cron", prefix, meta.task.task_type), meta.task.cron.clone()))
+        .collect()
+}
+
+/// Failed commands are reported on `<prefix>/errors`, not retained since they are one-off.
+pub fn error_message(prefix: &str, topic: &str, error: &GardenError) -> MqttMessage {
+    let payload = json!({ "topic": topic, "error": error.to_string(), "temporary": error.is_temporary() });
+    MqttMessage { topic: format!("{}/errors", prefix), payload: payload.to_string(), retain: false }
+}
+
+async fn execute(controller: &Controller, command: MqttCommand) -> GardenResult<()> {
+    match command {
+        MqttCommand::Water(request) => controller.water(request),
+        MqttCommand::Stop => controller.stop(),
+        MqttCommand::StopZone(zone) => controller.stop_zone(&zone),
+        MqttCommand::Pause(paused) => controller.set_paused(paused),
+        MqttCommand::UpdateTask { task_type, cron, disabled } => controller.update_task_schedule(task_type, cron, disabled).await,
+    }
+}
+
+async fn handle_command(controller: Controller, client: AsyncClient, prefix: String, topic: String, command: MqttCommand) {
+    // task and pause changes are published when the scheduler announces them
+    if let Err(error) = execute(&controller, command).await {
+        warn!(%topic, %error, "MQTT command failed");
+        publish(&client, error_message(&prefix, &topic, &error));
+    }
+}
+
+fn publish(client: &AsyncClient, message: MqttMessage) {
+    // the event loop buffers while reconnecting, drop what does not fit
+    if let Err(error) = client.try_publish(message.topic.clone(), QoS::AtLeastOnce, message.retain, message.payload) {
+        warn!(topic = %message.topic, %error, "Dropping MQTT message");
+    }
+}
+
+async fn publish_tasks(controller: Controller, client: AsyncClient) {
+    match controller.list_tasks().await {
+        Ok(tasks) => task_messages(&controller.config().mqtt_topic_prefix, &tasks).into_iter().for_each(|message| publish(&client, message)),
+        Err(error) => warn!(%error, "Could not publish tasks over MQTT"),
+    }
+}
+
+/// Whether schedules are paused, so the switch isn't unknown until someone toggles it.
+async fn publish_paused(controller: Controller, client: AsyncClient) {
+    match controller.is_paused().await {
+        Ok(paused) => publish(&client, MqttMessage::state(format!("{}/paused", controller.config().mqtt_topic_prefix), on_off(paused))),
+        Err(error) => warn!(%error, "Could not publish whether schedules are paused over MQTT"),
+    }
+}
+
+async fn publish_events(controller: Controller, client: AsyncClient, prefix: String, mut event_receiver: broadcast::Receiver<GardenEvent>) {
+    loop {
+        match event_receiver.recv().await {
+            // schedules change through every control surface, not only MQTT
+            Ok(GardenEvent::TasksChanged) => {
+                tokio::spawn(publish_tasks(controller.clone(), client.clone()));
+                tokio::spawn(publish_paused(controller.clone(), client.clone()));
+            },
+            Ok(event) => event_messages(&prefix, &event).into_iter().for_each(|message| publish(&client, message)),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => warn!(skipped, "MQTT publisher fell behind, events were dropped"),
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+pub struct Mqtt {} impl Mqtt {
+    /// Connects to the broker in `MQTT_BROKER` (host:port), disabled when unset.
+    pub async fn run_async(controller: Controller) {
+        let broker = match env::var("MQTT_BROKER") {
+            Ok(broker) if !broker.is_empty() => broker,
+            _ => {
+                info!("MQTT_BROKER is not set, MQTT is disabled");
+                return;
+            }
+        };
+
+        let (host, port) = match broker.rsplit_once(':').map(|(host, port)| (host, port.parse::<u16>())) {
+            Some((host, Ok(port))) => (host.to_string(), port),
+            None => (broker, 1883),
+            Some((_, Err(error))) => {
+                error!(%broker, %error, "Invalid MQTT broker");
+                return;
+            }
+        };
+
+        let config = controller.config();
+        let mut options = MqttOptions::new(config.mqtt_client_id.clone(), host, port);
+        if let (Ok(username), Ok(password)) = (env::var("MQTT_USERNAME"), env::var("MQTT_PASSWORD")) {
+            options.set_credentials(username, password);
+        }
+
+        Mqtt::run_with_options(controller, options).await;
+    }
+
+    pub async fn run_with_options(controller: Controller, mut options: MqttOptions) {
+        let config = controller.config();
+        let prefix = config.mqtt_topic_prefix.clone();
+
+        options.set_keep_alive(Duration::from_secs(config.mqtt_keep_alive_seconds));
+        options.set_last_will(LastWill::new(availability_topic(&prefix), OFFLINE, QoS::AtLeastOnce, true));
+
+        let (client, event_loop) = AsyncClient::new(options, config.events_in_flight_limit);
+        tokio::spawn(publish_events(controller.clone(), client.clone(), prefix.clone(), controller.subscribe_events()));
+
+        Mqtt::handle_connection(controller, client, event_loop, prefix).await;
+    }
+
+    /// Polling the event loop after an error reconnects, subscriptions are renewed on every connect.
+    async fn handle_connection(controller: Controller, client: AsyncClient, mut event_loop: EventLoop, prefix: String) {
+        let reconnect_delay = Duration::from_secs(controller.config().mqtt_reconnect_delay_seconds);
+
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to the MQTT broker");
+                    publish(&client, MqttMessage::state(availability_topic(&prefix), ONLINE));
+                    discovery_messages(controller.config()).into_iter().for_each(|message| publish(&client, message));
+                    tokio::spawn(publish_tasks(controller.clone(), client.clone()));
+                    tokio::spawn(publish_paused(controller.clone(), client.clone()));
+
+                    for topic in command_topics(&prefix).into_iter().chain([birth_topic(controller.config())]) {
+                        if let Err(error) = client.try_subscribe(topic.clone(), QoS::AtLeastOnce) {
+                            warn!(%topic, %error, "Could not subscribe");
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) if message.topic == birth_topic(controller.config()) => {
+                    if message.payload.as_ref() == ONLINE.as_bytes() {
+                        discovery_messages(controller.config()).into_iter().for_each(|message| publish(&client, message));
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) => {
+                    let payload = String::from_utf8_lossy(&message.payload);
+                    let default_seconds = controller.config().water_pump_working_cycle_seconds;
+
+                    match parse_command(&prefix, &message.topic, &payload, default_seconds) {
+                        Some(command) => {
+                            let correlation_id = CorrelationId::new();
+                            let span = info_span!("mqtt", %correlation_id, topic = %message.topic);
+                            span.in_scope(|| info!(?command, "Handling an MQTT command"));
+
+                            // task updates wait for the scheduler, the event loop must keep polling meanwhile
+                            let task = handle_command(controller.traced(correlation_id), client.clone(), prefix.clone(), message.topic.clone(), command);
+                            tokio::spawn(task.instrument(span));
+                        }
+                        None => warn!(topic = %message.topic, %payload, "Ignoring MQTT message"),
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    warn!(%error, "MQTT connection lost, reconnecting in {} seconds", reconnect_delay.as_secs());
+                    tokio::time::sleep(reconnect_delay).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// standard
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::time::Duration;
+
+// internal
+use crate::clock::{SharedClock};
+use crate::config::{Config};
+use crate::definitions::{ChatId, EventSeverity, GardenEvent, NotifierChannelMessage, QuietHours};
+use crate::error::{GardenError};
+use crate::history::{History};
+use crate::metrics::{METRICS};
+
+// external
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+use crossbeam_channel::{Receiver};
+use teloxide::{prelude::*};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+
+/// Delivery preferences and batched routine events of a single chat.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    pub chat_id: ChatId,
+    pub quiet_hours: Option<QuietHours>,
+    pub digest_at: Option<NaiveTime>,
+    pending: BTreeMap<String, usize>,
+    last_digest: Option<NaiveDate>
+}
+
+impl Subscriber {
+    pub fn new(chat_id: ChatId) -> Self {
+        Subscriber {
+            chat_id,
+            quiet_hours: None,
+            digest_at: None,
+            pending: BTreeMap::new(),
+            last_digest: None
+        }
+    }
+
+    pub fn is_quiet(&self, time: NaiveTime) -> bool {
+        self.quiet_hours.map(|q| q.contains(time)).unwrap_or(false)
+    }
+
+    /// Returns the text to deliver right away, or keeps a routine event
+    /// for the next digest / the end of quiet hours.
+    pub fn route(&mut self, event: &GardenEvent, now: NaiveDateTime) -> Option<String> {
+        match event.severity() {
+            EventSeverity::Telemetry => return None,
+            EventSeverity::Critical => return Some(format!("Alert! {}", event)),
+            EventSeverity::Routine => {}
+        }
+
+        if self.digest_at.is_some() || self.is_quiet(now.time()) {
+            *self.pending.entry(event.to_string()).or_insert(0) += 1;
+            return None;
+        }
+
+        Some(event.to_string())
+    }
+
+    /// Returns a summary of the batched events once it is due.
+    pub fn flush(&mut self, now: NaiveDateTime) -> Option<String> {
+        if self.is_quiet(now.time()) {
+            return None;
+        }
+
+        let header = match self.digest_at {
+            Some(digest_at) => {
+                if now.time() < digest_at || self.last_digest == Some(now.date()) {
+                    return None;
+                }
+                self.last_digest = Some(now.date());
+                "Daily summary"
+            },
+            None => "While you were away"
+        };
+
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let lines: Vec<_> = self.pending.iter()
+            .map(|(text, count)| format!("{} (x{})", text, count))
+            .collect();
+        self.pending.clear();
+
+        Some(format!("{}:\n{}", header, lines.join("\n")))
+    }
+}
+
+pub struct Notifier {
+    notifier_tick_ms: u64,
+    timezone: Tz,
+    clock: SharedClock,
+    notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+    event_receiver: broadcast::Receiver<GardenEvent>,
+    subscribers: HashMap<ChatId, Subscriber>,
+    history: Option<History>,
+    bot_notifier: AutoSend<Bot>
+}
+
+impl Notifier {
+    /// Tests point `bot` at a stand-in for the Telegram Bot API.
+    pub fn new(
+        config: &Config,
+        clock: SharedClock,
+        bot: Bot,
+        notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+        event_receiver: broadcast::Receiver<GardenEvent>
+    ) -> Self {
+        Notifier {
+            notifier_tick_ms: config.notifier_tick_ms,
+            timezone: config.timezone,
+            clock,
+            notifier_channel_receiver,
+            event_receiver,
+            subscribers: HashMap::new(),
+            history: None,
+            bot_notifier: bot.auto_send()
+        }
+    }
+
+    /// Picks up the subscriptions saved in `history` and saves every change from then on.
+    pub fn with_history(mut self, history: History) -> Self {
+        match history.subscribers() {
+            Ok(subscribers) => {
+                for (chat_id, quiet_hours, digest_at) in subscribers {
+                    let subscriber = self.subscribers.entry(chat_id).or_insert_with(|| Subscriber::new(chat_id));
+                    subscriber.quiet_hours = quiet_hours;
+                    subscriber.digest_at = digest_at;
+                }
+            },
+            Err(error) => warn!(error = %GardenError::from(error), "Failed to load the saved subscriptions")
+        }
+        self.history = Some(history);
+        self
+    }
+
+    pub fn run_threaded(
+        config: &'static Config,
+        clock: SharedClock,
+        notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+        event_receiver: broadcast::Receiver<GardenEvent>
+    ) {
+        thread::spawn(move || {
+            let runtime = Runtime::new().unwrap();
+            let mut notifier = Notifier::new(config, clock, Bot::from_env(), notifier_channel_receiver, event_receiver);
+            match History::open(&config.history_db_path) {
+                Ok(history) => notifier = notifier.with_history(history),
+                Err(error) => warn!(error = %GardenError::from(error), "Subscriptions are not kept across restarts without the history database")
+            }
+
+            runtime.block_on(async move {
+                loop {
+                    notifier.tick().await;
+                }
+            });
+        });
+    }
+
+    /// The `tick` method applies subscription changes, routes new events
+    /// to every subscriber and delivers batches that became due.
+    pub async fn tick(&mut self) {
+        if let Ok(message) = self.notifier_channel_receiver.recv_timeout(Duration::from_millis(self.notifier_tick_ms)) {
+            self.process_feedback_channel(message);
+        }
+
+        // quiet hours and digests follow the garden's clock, not the host's
+        let now = self.clock.now().with_timezone(&self.timezone).naive_local();
+        let mut outbox = Vec::new();
+
+        loop {
+            match self.event_receiver.try_recv() {
+                Ok(event) => {
+                    for subscriber in self.subscribers.values_mut() {
+                        if let Some(text) = subscriber.route(&event, now) {
+                            outbox.push((subscriber.chat_id, text));
+                        }
+                    }
+                },
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Notifier fell behind, events were dropped");
+                },
+                Err(_) => break
+            }
+        }
+
+        for subscriber in self.subscribers.values_mut() {
+            if let Some(text) = subscriber.flush(now) {
+                outbox.push((subscriber.chat_id, text));
+            }
+        }
+
+        for (chat_id, text) in outbox {
+            if let Err(error) = self.bot_notifier.send_message(teloxide::types::ChatId(chat_id.0), text).await {
+                let error = GardenError::from(error);
+                METRICS.telegram_send_failed();
+                warn!(%chat_id, %error, "Failed to send a notification");
+            }
+        }
+    }
+
+    fn process_feedback_channel(&mut self, message: NotifierChannelMessage) {
+        let chat_id = match message {
+            NotifierChannelMessage::Subscribe(chat_id) => {
+                self.subscribers.entry(chat_id).or_insert_with(|| Subscriber::new(chat_id));
+                chat_id
+            },
+            NotifierChannelMessage::Unsubscribe(chat_id) => {
+                self.subscribers.remove(&chat_id);
+                chat_id
+            },
+            NotifierChannelMessage::SetQuietHours(chat_id, quiet_hours) => {
+                self.subscribers.entry(chat_id).or_insert_with(|| Subscriber::new(chat_id)).quiet_hours = quiet_hours;
+                chat_id
+            },
+            NotifierChannelMessage::SetDigest(chat_id, digest_at) => {
+                self.subscribers.entry(chat_id).or_insert_with(|| Subscriber::new(chat_id)).digest_at = digest_at;
+                chat_id
+            }
+        };
+
+        self.save_subscriber(chat_id);
+    }
+
+    fn save_subscriber(&self, chat_id: ChatId) {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return
+        };
+        let saved = match self.subscribers.get(&chat_id) {
+            Some(subscriber) => history.save_subscriber(chat_id, subscriber.quiet_hours, subscriber.digest_at),
+            None => history.remove_subscriber(chat_id)
+        };
+        if let Err(error) = saved {
+            let error = GardenError::from(error);
+            error!(%chat_id, %error, "Failed to save a subscription");
+        }
+    }
+}
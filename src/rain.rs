@@ -0,0 +1,96 @@
+// standard
+use std::sync::{Arc, RwLock};
+
+// internal
+use crate::clock::{SharedClock};
+use crate::config::{Config};
+use crate::definitions::{GardenEvent, local_time};
+use crate::hardware::{DebouncedInput, DigitalInput};
+
+// external
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::broadcast::Sender;
+use tracing::{info, warn};
+
+
+/// What the rain sensor last settled on, shared with the worker.
+#[derive(Debug, Clone, Default)]
+pub struct RainState {
+    pub wet: bool,
+    /// When the sensor last turned dry after being wet.
+    pub dried_at: Option<DateTime<Utc>>
+}
+
+pub type SharedRain = Arc<RwLock<RainState>>;
+
+impl RainState {
+    /// Why scheduled watering has to wait, while the sensor is wet and for `rain_sensor_dry_delay_minutes` after.
+    pub fn suppression(&self, now: DateTime<Utc>, config: &Config) -> Option<String> {
+        if self.wet {
+            return Some("the rain sensor is wet".to_string());
+        }
+
+        let dried_at = self.dried_at?;
+        let until = dried_at + Duration::minutes(config.rain_sensor_dry_delay_minutes as i64);
+        (now < until).then(|| format!("the rain sensor was wet until {}", local_time(dried_at, config.timezone)))
+    }
+}
+
+/// Polls the rain switch on `rain_sensor_pin` and keeps the shared state up to date.
+pub struct RainSensor {
+    config: &'static Config,
+    clock: SharedClock,
+    input: DebouncedInput,
+    state: SharedRain,
+    event_sender: Sender<GardenEvent>,
+    /// Only the first of a run of failed reads is reported.
+    failing: bool
+}
+
+impl RainSensor {
+    pub fn new(config: &'static Config, clock: SharedClock, input: Arc<dyn DigitalInput>, pin: u64, state: SharedRain, event_sender: Sender<GardenEvent>) -> Self {
+        let input = DebouncedInput::new(input, pin, Duration::milliseconds(config.rain_sensor_debounce_ms as i64));
+        RainSensor { config, clock, input, state, event_sender, failing: false }
+    }
+
+    pub async fn run(&mut self) {
+        loop {
+            self.poll();
+            let deadline = self.clock.now() + Duration::milliseconds(self.config.rain_sensor_poll_ms as i64);
+            self.clock.sleep_until(deadline).await;
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let now = self.clock.now();
+        match self.input.poll(now) {
+            Ok(level) => {
+                self.failing = false;
+                if let Some(high) = level {
+                    self.update(high == self.config.rain_sensor_wet_when_high, now);
+                }
+            },
+            Err(error) => {
+                if !self.failing {
+                    warn!(%error, "Failed to read the rain sensor");
+                    self.event_sender.send(GardenEvent::Failure(format!("the rain sensor failed: {}", error))).unwrap_or_default();
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn update(&self, wet: bool, now: DateTime<Utc>) {
+        {
+            let mut state = self.state.write().unwrap();
+            if state.wet && !wet {
+                state.dried_at = Some(now);
+            }
+            state.wet = wet;
+        }
+
+        info!(wet, "Rain sensor changed");
+        // nobody listening is not an error
+        self.event_sender.send(GardenEvent::RainSensorChanged(wet)).unwrap_or_default();
+    }
+}
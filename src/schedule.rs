@@ -0,0 +1,331 @@
+// standard
+use std::str::FromStr;
+
+// internal
+use crate::config::{Config};
+use crate::definitions::{PeriodicTask, PeriodicTaskType, local_time};
+use crate::error::{GardenError, GardenResult, MIN_WATERING_SECONDS, MAX_WATERING_SECONDS};
+use crate::sun::{sun_event, SunEvent};
+
+// external
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use job_scheduler::{Schedule};
+
+
+/// Offsets stay within half a day of the sunrise or sunset they are relative to.
+const MAX_SUN_OFFSET_HOURS: i64 = 12;
+
+/// When a periodic task runs: a cron expression, a time relative to sunrise or sunset like `sunrise - 30min`,
+/// an interval like `every 36h` or a duty cycle like `2min on 28min off between 10:00 and 16:00`.
+#[derive(Clone)]
+pub enum TaskSchedule {
+    Cron(Schedule),
+    Sun { event: SunEvent, offset: Duration },
+    /// Counted from midnight on 1 January 1970 in the configured timezone, so it doesn't drift across restarts.
+    Interval { every: Duration },
+    /// Starts a cycle every `on + off` from the start of the window, or from midnight without one,
+    /// as long as the `on` part ends within it. Watering runs for `on`.
+    DutyCycle { on: Duration, off: Duration, window: Option<(NaiveTime, NaiveTime)> }
+}
+
+impl TaskSchedule {
+    /// The first run after `after`. Cron expressions are evaluated in the configured timezone,
+    /// sunrise and sunset are computed for the configured latitude and longitude.
+    pub fn after(&self, after: &DateTime<Utc>, config: &Config) -> Option<DateTime<Utc>> {
+        match self {
+            TaskSchedule::Cron(schedule) => {
+                // cron can't tell local times the clocks skip or repeat, so it only sees wall-clock times
+                let local = Utc.from_utc_datetime(&after.with_timezone(&config.timezone).naive_local());
+                schedule.after(&local)
+                    .filter_map(|wall_clock| resolve(wall_clock.naive_utc(), config.timezone))
+                    .find(|at| at > after)
+            },
+            TaskSchedule::Sun { event, offset } => {
+                let today = after.naive_utc().date();
+                // close to the poles the sun may not rise or set for months
+                (-1..=366)
+                    .filter_map(|day| sun_event(today + Duration::days(day), *event, config.latitude, config.longitude))
+                    .map(|at| at + *offset)
+                    .find(|at| at > after)
+            },
+            TaskSchedule::Interval { every } => {
+                let local = after.with_timezone(&config.timezone).naive_local();
+                let since_epoch = local - NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0);
+                // a step back, so the clocks going back can't skip a run
+                let first = since_epoch.num_seconds().div_euclid(every.num_seconds()) - 1;
+                (first..)
+                    .map(|count| NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0) + Duration::seconds(every.num_seconds() * count))
+                    .filter_map(|wall_clock| resolve(wall_clock, config.timezone))
+                    .find(|at| at > after)
+            },
+            TaskSchedule::DutyCycle { on, off, window } => {
+                let today = after.with_timezone(&config.timezone).naive_local().date();
+                (-1..=2)
+                    .flat_map(|day| duty_cycles(today + Duration::days(day), *on, *off, *window))
+                    .filter_map(|wall_clock| resolve(wall_clock, config.timezone))
+                    .find(|at| at > after)
+            }
+        }
+    }
+
+    /// The next `count` runs after `after`, for previews.
+    pub fn upcoming(&self, after: &DateTime<Utc>, config: &Config, count: usize) -> Vec<DateTime<Utc>> {
+        std::iter::successors(self.after(after, config), |at| self.after(at, config)).take(count).collect()
+    }
+
+    /// How long a run lasts when the schedule says so, otherwise the task decides.
+    pub fn run_seconds(&self) -> Option<u64> {
+        match self {
+            TaskSchedule::DutyCycle { on, .. } => Some(on.num_seconds() as u64),
+            _ => None
+        }
+    }
+
+    /// Parses the task's schedule and checks it fits the task, e.g. a duty cycle waters for a valid number of seconds.
+    pub fn for_task(task: &PeriodicTask) -> GardenResult<TaskSchedule> {
+        let schedule = task.cron.parse::<TaskSchedule>()?;
+        if let (PeriodicTaskType::WaterPlants, Some(seconds)) = (&task.task_type, schedule.run_seconds()) {
+            if !(MIN_WATERING_SECONDS..=MAX_WATERING_SECONDS).contains(&seconds) {
+                return Err(GardenError::InvalidDuration(seconds));
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+/// Wall-clock starts of the duty cycles on `day`.
+fn duty_cycles(day: NaiveDate, on: Duration, off: Duration, window: Option<(NaiveTime, NaiveTime)>) -> Vec<NaiveDateTime> {
+    let (start, end) = match window {
+        // a window like 22:00 to 02:00 ends the next day
+        Some((start, end)) if end <= start => (day.and_time(start), (day + Duration::days(1)).and_time(end)),
+        Some((start, end)) => (day.and_time(start), day.and_time(end)),
+        None => (day.and_hms(0, 0, 0), (day + Duration::days(1)).and_hms(0, 0, 0))
+    };
+    (0..)
+        .map(|count| start + (on + off) * count)
+        .take_while(|cycle| *cycle + on <= end)
+        .collect()
+}
+
+impl FromStr for TaskSchedule {
+    type Err = GardenError;
+
+    fn from_str(input: &str) -> GardenResult<TaskSchedule> {
+        let invalid = |reason: String| GardenError::InvalidSchedule { cron: input.to_string(), reason };
+        let expression = input.trim().to_lowercase();
+
+        if let Some(every) = expression.strip_prefix("every ") {
+            let every = parse_duration(every).ok_or_else(|| invalid("expected an interval like every 36h or every 90min".to_string()))?;
+            if every < Duration::seconds(1) {
+                return Err(invalid("the interval must be at least a second".to_string()));
+            }
+            return Ok(TaskSchedule::Interval { every });
+        }
+
+        if expression.ends_with("off") || expression.contains(" off between ") {
+            return parse_duty_cycle(&expression).map_err(invalid);
+        }
+
+        let (event, offset) = if let Some(offset) = expression.strip_prefix("sunrise") {
+            (SunEvent::Sunrise, offset)
+        } else if let Some(offset) = expression.strip_prefix("sunset") {
+            (SunEvent::Sunset, offset)
+        } else {
+            return input.parse::<Schedule>().map(TaskSchedule::Cron).map_err(|error| invalid(error.to_string()));
+        };
+
+        let offset = parse_offset(offset).ok_or_else(|| invalid(format!("expected an offset like {} - 30min or {} + 1h", event, event)))?;
+        if offset.num_hours().abs() >= MAX_SUN_OFFSET_HOURS {
+            return Err(invalid(format!("the offset must be less than {} hours", MAX_SUN_OFFSET_HOURS)));
+        }
+
+        Ok(TaskSchedule::Sun { event, offset })
+    }
+}
+
+/// Plain English for the schedules people usually write, e.g. `every day at 06:00` or
+/// `30 minutes before sunrise`. Anything else is shown as it was written.
+pub fn describe(expression: &str) -> String {
+    match expression.parse::<TaskSchedule>() {
+        Ok(TaskSchedule::Sun { event, offset }) => describe_sun(event, offset),
+        Ok(TaskSchedule::Interval { every }) => format!("every {}", describe_duration(every)),
+        Ok(TaskSchedule::DutyCycle { on, off, window }) => {
+            let window = match window {
+                Some((start, end)) => format!("between {} and {}", start.format("%H:%M"), end.format("%H:%M")),
+                None => "all day".to_string()
+            };
+            format!("{} on, {} off {}", describe_duration(on), describe_duration(off), window)
+        },
+        _ => describe_cron(expression).unwrap_or_else(|| expression.trim().to_string())
+    }
+}
+
+/// `describe` followed by the given upcoming runs, one per line in `timezone`.
+pub fn describe_preview(expression: &str, runs: &[DateTime<Utc>], timezone: Tz) -> String {
+    if runs.is_empty() {
+        return format!("{}\nNever runs", describe(expression));
+    }
+    let runs: Vec<_> = runs.iter().map(|at| local_time(*at, timezone)).collect();
+    format!("{}\nNext runs:\n{}", describe(expression), runs.join("\n"))
+}
+
+fn describe_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().abs();
+    match seconds {
+        _ if seconds % (24 * 3600) == 0 => plural(seconds / (24 * 3600), "day"),
+        _ if seconds % 3600 == 0 => plural(seconds / 3600, "hour"),
+        _ if seconds % 60 == 0 => plural(seconds / 60, "minute"),
+        _ => plural(seconds, "second")
+    }
+}
+
+fn describe_sun(event: SunEvent, offset: Duration) -> String {
+    if offset.is_zero() {
+        return format!("at {}", event);
+    }
+    let relation = if offset < Duration::zero() { "before" } else { "after" };
+    format!("{} {} {}", describe_duration(offset), relation, event)
+}
+
+fn describe_cron(expression: &str) -> Option<String> {
+    let fields: Vec<_> = expression.split_whitespace().collect();
+    let (second, minute, hour, day_of_month, month, day_of_week) = match fields[..] {
+        [second, minute, hour, day_of_month, month, day_of_week] => (second, minute, hour, day_of_month, month, day_of_week),
+        [second, minute, hour, day_of_month, month, day_of_week, "*"] => (second, minute, hour, day_of_month, month, day_of_week),
+        _ => return None
+    };
+    if !matches!(day_of_month, "*" | "?") || month != "*" {
+        return None;
+    }
+    let days = describe_days(day_of_week)?;
+
+    let number = |field: &str, max: u32| field.parse::<u32>().ok().filter(|value| *value < max);
+    let every = |field: &str| field.strip_prefix("*/").and_then(|step| step.parse::<u32>().ok()).filter(|step| *step > 1);
+
+    match (second, minute, hour) {
+        ("0", minute, hour) if number(minute, 60).is_some() && hour.split(',').all(|hour| number(hour, 24).is_some()) => {
+            let times: Vec<_> = hour.split(',').map(|hour| format!("{:02}:{:02}", number(hour, 24).unwrap(), number(minute, 60).unwrap())).collect();
+            Some(format!("{} at {}", days, join(&times)))
+        },
+        // more than once a day only reads well every day
+        _ if days != "every day" => None,
+        ("*", "*", "*") => Some("every second".to_string()),
+        ("0", "*", "*") => Some("every minute".to_string()),
+        ("0", minute, "*") if every(minute).is_some() => Some(format!("every {} minutes", every(minute)?)),
+        ("0", minute, "*") => number(minute, 60).map(|minute| format!("every hour at :{:02}", minute)),
+        ("0", minute, hour) => Some(format!("every {} hours at :{:02}", every(hour)?, number(minute, 60)?)),
+        (second, "*", "*") => Some(format!("every {} seconds", every(second)?)),
+        _ => None
+    }
+}
+
+/// `*`, single days, lists like `Mon,Wed,Fri` and `Mon-Fri`. Days are named or numbered from Sunday = 1.
+fn describe_days(day_of_week: &str) -> Option<String> {
+    const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+    let day = |input: &str| match input.parse::<usize>() {
+        Ok(number) => DAYS.get(number.checked_sub(1)?).copied(),
+        Err(_) => DAYS.iter().find(|day| input.len() >= 3 && day.to_lowercase().starts_with(&input.to_lowercase())).copied()
+    };
+
+    match day_of_week {
+        "*" | "?" => Some("every day".to_string()),
+        "Mon-Fri" | "MON-FRI" | "mon-fri" | "2-6" => Some("every weekday".to_string()),
+        "Sat,Sun" | "SAT,SUN" | "sat,sun" | "1,7" | "7,1" => Some("every weekend".to_string()),
+        days => {
+            let days = days.split(',').map(day).collect::<Option<Vec<_>>>()?;
+            Some(format!("every {}", join(&days)))
+        }
+    }
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.as_ref().to_string(),
+        [init @ .., last] => format!("{} and {}", init.iter().map(|item| item.as_ref()).collect::<Vec<_>>().join(", "), last.as_ref())
+    }
+}
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 { format!("1 {}", unit) } else { format!("{} {}s", count, unit) }
+}
+
+/// Local times the clocks skip when DST starts run when the clocks jump,
+/// times they repeat when DST ends only run the first time.
+fn resolve(local: NaiveDateTime, timezone: Tz) -> Option<DateTime<Utc>> {
+    let at = match timezone.from_local_datetime(&local) {
+        LocalResult::Single(at) => Some(at),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => {
+            let minute = local.date().and_hms(local.hour(), local.minute(), 0);
+            (1..=24 * 60).find_map(|minutes| timezone.from_local_datetime(&(minute + Duration::minutes(minutes))).earliest())
+        }
+    };
+    at.map(|at| at.with_timezone(&Utc))
+}
+
+/// Parses `2min on 28min off`, optionally followed by `between 10:00 and 16:00`.
+fn parse_duty_cycle(expression: &str) -> Result<TaskSchedule, String> {
+    let expected = "expected a duty cycle like 2min on 28min off between 10:00 and 16:00";
+    let (cycle, window) = match expression.split_once(" between ") {
+        Some((cycle, window)) => (cycle, Some(window)),
+        None => (expression, None)
+    };
+
+    let (on, off) = cycle.strip_suffix("off").and_then(|cycle| cycle.split_once(" on")).ok_or(expected)?;
+    let on = parse_duration(on).ok_or(expected)?;
+    let off = parse_duration(off).ok_or(expected)?;
+    if on < Duration::seconds(1) || off < Duration::seconds(1) {
+        return Err("both the on and the off part must be at least a second".to_string());
+    }
+
+    let window = match window {
+        Some(window) => {
+            let (start, end) = window.split_once(" and ").ok_or(expected)?;
+            let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| expected)?;
+            let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| expected)?;
+            let length = if end > start { end - start } else { end - start + Duration::days(1) };
+            if on > length {
+                return Err("the on part must fit in the window".to_string());
+            }
+            Some((start, end))
+        },
+        None if on > Duration::days(1) => return Err("the on part must fit in a day".to_string()),
+        None => None
+    };
+
+    Ok(TaskSchedule::DutyCycle { on, off, window })
+}
+
+/// Parses `+ 1h`, `-30min` or `+ 90 s`, nothing at all is no offset.
+fn parse_offset(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Some(Duration::zero());
+    }
+
+    let sign = match input.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None
+    };
+
+    Some(parse_duration(&input[1..])? * sign)
+}
+
+/// Parses `90s`, `30 min`, `36h` or `2 days`.
+fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let digits = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    // small enough for chrono not to overflow, too large durations are rejected by the callers
+    let value = input[..digits].parse::<u16>().ok()? as i64;
+
+    match input[digits..].trim() {
+        "s" | "sec" | "secs" => Some(Duration::seconds(value)),
+        "m" | "min" | "mins" => Some(Duration::minutes(value)),
+        "h" | "hour" | "hours" => Some(Duration::hours(value)),
+        "d" | "day" | "days" => Some(Duration::days(value)),
+        _ => None
+    }
+}
@@ -1,168 +1,363 @@
 // standard
-use std::time::Duration;
-use std::thread;
 use std::collections::HashMap;
+use std::time::Duration;
 
 // internal
+use crate::clock::{SharedClock};
 use crate::config::{Config};
-use crate::definitions::{SchedulerChannelMessage, WorkerChannelMessage, PeriodicTask, PeriodicTaskType};
+use crate::definitions::{SchedulerChannelMessage, WorkerChannelMessage, GardenEvent, CatchUp, PeriodicTask, PeriodicTaskType, RunOutcome, TaskMetaData, TaskRun, Trigger, WateringRequest, CorrelationId, Traced};
+use crate::history::{History};
+use crate::error::{GardenError, GardenResult, send_error};
+use crate::metrics::{METRICS};
+use crate::schedule::{TaskSchedule};
 
 // external
-extern crate job_scheduler;
-use job_scheduler::{JobScheduler, Job, Uuid};
-use crossbeam_channel::{Sender, Receiver};
-use teloxide::{prelude::*};
-use futures::future::join_all;
-use tokio::runtime::Runtime;
-
-
-#[derive(Debug, Clone)]
-pub struct TaskMetaData {
-    pub job_id: Uuid,
-    pub task: PeriodicTask
-}
+use chrono::{DateTime, Utc};
+use job_scheduler::{Uuid};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, info, info_span, trace, warn};
+
+
+/// Upper bound for one sleep, a Pi without a real-time clock may only get
+/// the right time from NTP after the next run was planned.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
 
+/// How many upcoming runs a task listing shows.
+const UPCOMING_RUNS: usize = 3;
 
-pub struct Scheduler<'a> {
-    config: &'a Config,
-    scheduler_tick_ms: u64,
-    cron: JobScheduler<'a>,
-    scheduler_channel_receiver: &'a Receiver<SchedulerChannelMessage>,
-    worker_channel_pub: &'a Sender<WorkerChannelMessage>,
-    tasks: HashMap<String, TaskMetaData>,
-    bot_notifier: AutoSend<Bot>
+struct ScheduledTask {
+    meta: TaskMetaData,
+    schedule: TaskSchedule,
+    next_run: Option<DateTime<Utc>>
+}
+
+pub struct Scheduler {
+    config: &'static Config,
+    clock: SharedClock,
+    scheduler_channel_receiver: mpsc::Receiver<Traced<SchedulerChannelMessage>>,
+    worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+    event_sender: broadcast::Sender<GardenEvent>,
+    /// The worker reports how periodic tasks went here.
+    event_receiver: broadcast::Receiver<GardenEvent>,
+    tasks: HashMap<String, ScheduledTask>,
+    /// The last run of every task as saved before the garden started, by task type.
+    saved_runs: HashMap<String, TaskRun>,
+    paused: bool
 }
 
 
-impl<'a> Scheduler<'a> {
+impl Scheduler {
 
     pub fn new(
-        config: &'a Config,
-        scheduler_channel_receiver: &'a Receiver<SchedulerChannelMessage>,
-        worker_channel_pub: &'a Sender<WorkerChannelMessage>,
+        config: &'static Config,
+        clock: SharedClock,
+        scheduler_channel_receiver: mpsc::Receiver<Traced<SchedulerChannelMessage>>,
+        worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+        event_sender: broadcast::Sender<GardenEvent>
     ) -> Self {
         Scheduler {
-            config: config,
-            scheduler_tick_ms: config.scheduler_tick_ms,
-            cron: JobScheduler::new(),
-            scheduler_channel_receiver: scheduler_channel_receiver,
-            worker_channel_pub: worker_channel_pub,
+            config,
+            clock,
+            scheduler_channel_receiver,
+            worker_channel_sender,
+            event_receiver: event_sender.subscribe(),
+            event_sender,
             tasks: HashMap::new(),
-            bot_notifier: Bot::from_env().auto_send()
+            saved_runs: HashMap::new(),
+            paused: false
         }
      }
 
-    pub fn run_threaded(
-        config: &'a Config,
-        scheduler_channel_receiver: Receiver<SchedulerChannelMessage>,
-        worker_channel_pub: &'a Sender<WorkerChannelMessage>
+    pub fn with_saved_runs(mut self, saved_runs: HashMap<String, TaskRun>) -> Self {
+        self.saved_runs = saved_runs;
+        self
+    }
+
+    pub async fn run_async(
+        config: &'static Config,
+        clock: SharedClock,
+        scheduler_channel_receiver: mpsc::Receiver<Traced<SchedulerChannelMessage>>,
+        worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+        event_sender: broadcast::Sender<GardenEvent>
     ) {
+        let mut scheduler = Scheduler::new(config, clock.clone(), scheduler_channel_receiver, worker_channel_sender, event_sender);
+        // the only read, the history thread saves the runs from then on
+        let saved_runs = tokio::task::spawn_blocking(move || History::open(&config.history_db_path)?.task_runs()).await;
+        match saved_runs {
+            Ok(Ok(saved_runs)) => scheduler = scheduler.with_saved_runs(saved_runs),
+            Ok(Err(error)) => warn!(error = %GardenError::from(error), "Runs missed while the garden was off can't be caught up without the history database"),
+            Err(error) => warn!(%error, "Runs missed while the garden was off can't be caught up without the history database")
+        }
+        scheduler.add_tasks(&config.periodic_tasks);
+        scheduler.catch_up(clock.now());
+        scheduler.run().await;
+    }
 
-        let worker_channel_pub = worker_channel_pub.clone();
+    /// Sleeps until the next task is due or a command arrives, whichever comes first.
+    pub async fn run(&mut self) {
+        loop {
+            let wake_up_at = self.wake_up_at();
+            trace!(%wake_up_at, "Scheduler is waiting");
 
-        let config = config.clone();
+            tokio::select! {
+                _ = self.clock.sleep_until(wake_up_at) => self.run_due_tasks(self.clock.now()),
+                message = self.scheduler_channel_receiver.recv() => match message {
+                    Some(message) => self.process_message(message),
+                    None => break
+                },
+                // outcomes missed while lagging behind stay unknown
+                event = self.event_receiver.recv() => if let Ok(event) = event {
+                    self.record_outcome(&event);
+                }
+            }
+        }
+    }
 
-        thread::spawn(move || {
-            let runtime = Runtime::new().unwrap();
-            let mut scheduler = Scheduler::new(&config, &scheduler_channel_receiver, &worker_channel_pub);
-            scheduler.add_tasks(&config.periodic_tasks);
+    fn wake_up_at(&self) -> DateTime<Utc> {
+        let latest = self.clock.now() + chrono::Duration::from_std(MAX_SLEEP).unwrap();
 
-            runtime.block_on(async move {
-                loop {
-                    println!("Scheduler tick");
-                    scheduler.tick().await;
-                }
-            });
+        self.tasks.values()
+            .filter(|scheduled| !scheduled.meta.task.disabled)
+            .filter_map(|scheduled| scheduled.next_run)
+            .min()
+            .map_or(latest, |next_run| next_run.min(latest))
+    }
+
+    /// Runs every task that became due at `now` once, runs missed while the Pi was busy are not repeated.
+    pub fn run_due_tasks(&mut self, now: DateTime<Utc>) {
+        let mut due = Vec::new();
 
-        });
+        for scheduled in self.tasks.values_mut() {
+            match scheduled.next_run {
+                Some(next_run) if next_run <= now => {
+                    // a busy Pi shows up as tasks running later than planned
+                    METRICS.set_scheduler_tick_lag((now - next_run).to_std().unwrap_or(Duration::ZERO));
+                    scheduled.next_run = scheduled.schedule.after(&now, self.config);
+                    due.push(scheduled.meta.task.clone());
+                },
+                _ => {}
+            }
+        }
+
+        for task in due {
+            if task.disabled {
+                continue;
+            }
+            if self.paused {
+                debug!(task = %task.task_type, "Scheduler is paused, skipping periodic task");
+                continue;
+            }
+            self.run_task(task, now);
+        }
     }
 
-    /// The `tick` method increments time for the JobScheduler, executes
-    /// any pending jobs and listens for the data on the feedback channel.
-    /// Feedback channel can be used to adjust scheduler settings.
-    pub async fn tick(&mut self) {
-        // tick
-        self.cron.tick();
-        std::thread::sleep(Duration::from_millis(self.scheduler_tick_ms));
+    /// Dispatches runs that were due while the garden was off, as far as each task's catch-up policy allows.
+    /// Only tasks that ran before are caught up, the history knows when that was.
+    pub fn catch_up(&mut self, now: DateTime<Utc>) {
+        let mut missed = Vec::new();
 
-        // process feedback channel and adjust settings if needed
-        self.process_feedback_channel().await;
+        for scheduled in self.tasks.values() {
+            let task = &scheduled.meta.task;
+            let last_run = match &scheduled.meta.last_run {
+                Some(last_run) if !task.disabled => last_run.at,
+                _ => continue
+            };
+
+            let runs = match task.catch_up {
+                CatchUp::Skip => 0,
+                CatchUp::Once { within_hours } => {
+                    let since = last_run.max(now - chrono::Duration::hours(within_hours as i64));
+                    scheduled.schedule.after(&since, self.config).filter(|at| *at <= now).map_or(0, |_| 1)
+                },
+                CatchUp::All => std::iter::successors(scheduled.schedule.after(&last_run, self.config), |at| scheduled.schedule.after(at, self.config))
+                    .take_while(|at| *at <= now)
+                    // no more than the worker can queue
+                    .take(self.config.messages_in_flight_limit)
+                    .count()
+            };
+            if runs > 0 {
+                missed.push((task.clone(), runs));
+            }
+        }
+
+        for (task, runs) in missed {
+            info!(task = %task.task_type, runs, since = %now, "Catching up on missed runs");
+            for _ in 0..runs {
+                self.run_task(task.clone(), now);
+            }
+        }
     }
 
-    pub fn add_task(&mut self, task: PeriodicTask) {
+    fn run_task(&mut self, task: PeriodicTask, now: DateTime<Utc>) {
+        let run_seconds = self.tasks.get(&task.task_type.to_string()).and_then(|scheduled| scheduled.schedule.run_seconds());
+        let outcome = self.dispatch(task.clone(), run_seconds).err().map(|error| RunOutcome::Failed(error.to_string()));
+        if let Some(scheduled) = self.tasks.get_mut(&task.task_type.to_string()) {
+            scheduled.meta.last_run = Some(TaskRun { at: now, outcome });
+        }
+        self.save_last_run(&task.task_type);
+    }
+
+    /// The history thread writes it to the database, nobody listening is not an error.
+    fn save_last_run(&mut self, task_type: &PeriodicTaskType) {
+        let last_run = self.tasks.get(&task_type.to_string()).and_then(|scheduled| scheduled.meta.last_run.clone());
+        if let Some(last_run) = last_run {
+            self.saved_runs.insert(task_type.to_string(), last_run.clone());
+            self.event_sender.send(GardenEvent::TaskRan(task_type.clone(), last_run)).unwrap_or_default();
+        }
+    }
+
+    /// Watering runs for `run_seconds` when the schedule says how long, e.g. a duty cycle.
+    fn dispatch(&self, task: PeriodicTask, run_seconds: Option<u64>) -> GardenResult<()> {
+        // every firing starts a new trace
+        let correlation_id = CorrelationId::new();
+        info!(%correlation_id, task = %task.task_type, "Dispatching periodic task");
 
-        let worker_channel_pub = self.worker_channel_pub.clone();
         let task_type = task.task_type.clone();
-        let meta = task.clone();
-        
-        if let Ok(expression) = task.cron.parse() {
-            let job_id = self.cron.add(Job::new(expression, move || {
-                if !task.disabled {
-                    worker_channel_pub.send(WorkerChannelMessage::PeriodicTask(task.clone())).unwrap_or_default();
-                }
-            }));
+        let message = match (&task.task_type, run_seconds) {
+            (PeriodicTaskType::WaterPlants, Some(seconds)) => WorkerChannelMessage::WaterPlants(WateringRequest { zone: None, seconds, trigger: Trigger::Schedule }),
+            _ => WorkerChannelMessage::PeriodicTask(task)
+        };
+        self.worker_channel_sender.try_send(Traced::new(correlation_id, message)).map_err(send_error("worker")).map_err(|error| {
+            error!(%correlation_id, task = %task_type, %error, "Could not dispatch periodic task");
+            self.event_sender.send(GardenEvent::Failure(format!("{} did not run, {}", task_type, error))).unwrap_or_default();
+            error
+        })
+    }
 
-            self.tasks.insert(task_type.to_string(), TaskMetaData { job_id: job_id, task: meta });
+    /// Fills in how the last run of a periodic task went from what the worker reported,
+    /// with several zones the worst outcome sticks.
+    fn record_outcome(&mut self, event: &GardenEvent) {
+        let (task_type, outcome) = match event {
+            GardenEvent::WateringFinished(run) if run.trigger == Trigger::Schedule => (PeriodicTaskType::WaterPlants, RunOutcome::Ok),
+            GardenEvent::WateringStopped(run) if run.trigger == Trigger::Schedule => (PeriodicTaskType::WaterPlants, RunOutcome::Stopped),
+            GardenEvent::WateringFailed(run, reason) if run.trigger == Trigger::Schedule => (PeriodicTaskType::WaterPlants, RunOutcome::Failed(reason.clone())),
+            GardenEvent::WateringSkipped(skipped) => (PeriodicTaskType::WaterPlants, RunOutcome::Skipped(skipped.reason.clone())),
+            GardenEvent::MoistureRead(_) => (PeriodicTaskType::ReadMoistureSensorsData, RunOutcome::Ok),
+            GardenEvent::SensorFailed(zone, reason) => (PeriodicTaskType::ReadMoistureSensorsData, RunOutcome::Failed(format!("{}: {}", zone, reason))),
+            _ => return
+        };
+
+        let severity = |outcome: &Option<RunOutcome>| match outcome {
+            None => 0,
+            Some(RunOutcome::Ok) | Some(RunOutcome::Skipped(_)) => 1,
+            Some(RunOutcome::Stopped) => 2,
+            Some(RunOutcome::Failed(_)) => 3
+        };
+        if let Some(last_run) = self.tasks.get_mut(&task_type.to_string()).and_then(|scheduled| scheduled.meta.last_run.as_mut()) {
+            let outcome = Some(outcome);
+            if severity(&outcome) > severity(&last_run.outcome) {
+                last_run.outcome = outcome;
+                self.save_last_run(&task_type);
+            }
         }
     }
 
+    /// Adds the task, or replaces it if a task of the same type is already scheduled.
+    pub fn add_task(&mut self, task: PeriodicTask) -> GardenResult<()> {
+        let schedule = TaskSchedule::for_task(&task)?;
+        let next_run = schedule.after(&self.clock.now(), self.config);
+        // a new schedule doesn't change when the task last ran
+        let last_run = match self.tasks.get(&task.task_type.to_string()) {
+            Some(scheduled) => scheduled.meta.last_run.clone(),
+            None => self.saved_last_run(&task.task_type)
+        };
+        let meta = TaskMetaData { job_id: Uuid::new_v4(), task, next_runs: Vec::new(), last_run, paused: false };
+        self.tasks.insert(meta.task.task_type.to_string(), ScheduledTask { meta, schedule, next_run });
+        Ok(())
+    }
+
+    /// A broken task in the config must not keep the others from running.
     pub fn add_tasks(&mut self, tasks: &Vec<PeriodicTask>) {
         for task in tasks {
-            self.add_task(task.clone());
+            if let Err(error) = self.add_task(task.clone()) {
+                warn!(task = %task.task_type, %error, "Skipping periodic task");
+                self.event_sender.send(GardenEvent::Failure(format!("{} is not scheduled, {}", task.task_type, error))).unwrap_or_default();
+            }
         }
     }
 
-    pub fn update_task(&mut self, task: PeriodicTask) {
-        let task_id = task.task_type.to_string();
+    fn saved_last_run(&self, task_type: &PeriodicTaskType) -> Option<TaskRun> {
+        self.saved_runs.get(&task_type.to_string()).cloned()
+    }
 
-        match self.tasks.get(&task_id) {
-            Some(task_metadata) => {
-                self.cron.remove(task_metadata.job_id);
-                self.add_task(task);
+    /// The old schedule stays in place when the new one does not parse, the catch-up policy always does.
+    pub fn update_task(&mut self, task: PeriodicTask) -> GardenResult<()> {
+        match self.tasks.get(&task.task_type.to_string()) {
+            Some(scheduled) => {
+                let catch_up = scheduled.meta.task.catch_up;
+                self.add_task(PeriodicTask { catch_up, ..task })
             },
-            None => {
-                println!("Could not find any tasks with the provided id {}.", task_id);
-            }
+            None => Err(GardenError::UnknownTask(task.task_type))
         }
     }
 
-    pub fn remove_task(&mut self, task_type: PeriodicTaskType) {
-        let task_id = task_type.to_string();
+    pub fn remove_task(&mut self, task_type: PeriodicTaskType) -> GardenResult<()> {
+        match self.tasks.remove(&task_type.to_string()) {
+            Some(_) => Ok(()),
+            None => Err(GardenError::UnknownTask(task_type))
+        }
+    }
 
-        match self.tasks.get(&task_id) {
-            Some(task_metadata) => {
-                self.cron.remove(task_metadata.job_id);
-                self.tasks.remove(&task_id);
+    pub fn reset_tasks(&mut self) {
+        self.tasks.clear();
+        self.add_tasks(&self.config.periodic_tasks);
+    }
+
+    /// Every task sorted by name, with its next few runs unless it won't run.
+    pub fn list_tasks(&self) -> Vec<TaskMetaData> {
+        let mut tasks: Vec<_> = self.tasks.values()
+            .map(|scheduled| {
+                let next_runs = match scheduled.meta.task.disabled || self.paused {
+                    true => Vec::new(),
+                    false => std::iter::successors(scheduled.next_run, |at| scheduled.schedule.after(at, self.config)).take(UPCOMING_RUNS).collect()
+                };
+                TaskMetaData { next_runs, paused: self.paused, ..scheduled.meta.clone() }
+            })
+            .collect();
+        tasks.sort_by_key(|meta| meta.task.task_type.to_string());
+        tasks
+    }
+
+    fn process_message(&mut self, Traced { correlation_id, message }: Traced<SchedulerChannelMessage>) {
+        let _span = info_span!("scheduler", %correlation_id).entered();
+        info!(?message, "Processing command");
+
+        // the requester may have given up waiting for the reply
+        match message {
+            SchedulerChannelMessage::AddPeriodicTask(task, reply) => {
+                let result = self.add_task(task);
+                reply.send(self.announce_changes(result)).unwrap_or_default();
+            }
+            SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task, reply) => {
+                let result = self.update_task(task);
+                reply.send(self.announce_changes(result)).unwrap_or_default();
+            }
+            SchedulerChannelMessage::ListPeriodicTasks(reply) => {
+                reply.send(self.list_tasks()).unwrap_or_default();
             },
-            None => {
-                println!("Could not find any tasks with the provided id {}.", task_id);
+            SchedulerChannelMessage::RemovePeriodicTask(task_type, reply) => {
+                let result = self.remove_task(task_type);
+                reply.send(self.announce_changes(result)).unwrap_or_default();
+            }
+            SchedulerChannelMessage::ResetTasks(reply) => {
+                self.reset_tasks();
+                reply.send(self.announce_changes(Ok(()))).unwrap_or_default();
+            }
+            SchedulerChannelMessage::IsPaused(reply) => {
+                reply.send(self.paused).unwrap_or_default();
+            }
+            SchedulerChannelMessage::SetPaused(paused) => {
+                self.paused = paused;
+                self.announce_changes(Ok(())).unwrap_or_default();
             }
         }
     }
 
-    async fn process_feedback_channel(&mut self) {
-        if let Ok(message) = self.scheduler_channel_receiver.try_recv() {
-            println!("Got feedback processing...");
-
-            match message {
-                SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task) => {
-                    self.update_task(task);
-                }
-                SchedulerChannelMessage::ListPeriodicTasks(chat_id) => {
-                    let tasks: Vec<_> = self.tasks.clone()
-                        .into_values()
-                        .map(|t| self.bot_notifier.send_message(chat_id, format!("{} \n{}", t.job_id, t.task)))
-                        .collect();
-                    join_all(tasks).await;
-                },
-                SchedulerChannelMessage::RemovePeriodicTask(task_type) => {
-                    self.remove_task(task_type);
-                }
-                SchedulerChannelMessage::ResetTasks => {
-                    self.tasks.clone().into_values().for_each(|t| self.remove_task(t.task.task_type.clone()));
-                    self.add_tasks(&self.config.periodic_tasks);
-                }
-            }
+    /// Whoever shows the schedules, e.g. Home Assistant over MQTT, learns about changes made anywhere.
+    fn announce_changes(&self, result: GardenResult<()>) -> GardenResult<()> {
+        if result.is_ok() {
+            self.event_sender.send(GardenEvent::TasksChanged).unwrap_or_default();
         }
+        result
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,95 @@
+// standard
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// internal
+use crate::clock::{SharedClock};
+use crate::config::{Config};
+use crate::definitions::{Zone};
+use crate::error::{GardenError, GardenResult};
+use crate::hardware::{Actuator, DigitalInput, Hardware, MoistureSensor};
+
+// external
+use chrono::{DateTime, Utc};
+
+
+struct Soil {
+    moisture: f64,
+    pump_on: bool,
+    updated_at: DateTime<Utc>
+}
+
+impl Soil {
+    /// The soil dries out at a steady rate and soaks up water while the pump runs.
+    fn advance(&mut self, config: &Config, now: DateTime<Utc>) {
+        let seconds = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        let change = if self.pump_on {
+            config.simulation_pump_percent_per_second * seconds
+        } else {
+            -config.simulation_evaporation_percent_per_hour * seconds / 3600.0
+        };
+
+        self.moisture = (self.moisture + change).clamp(0.0, 100.0);
+        self.updated_at = now;
+    }
+}
+
+/// Pumps and soil moisture of every configured zone, simulated on the garden's clock.
+pub struct SimulatedGarden {
+    config: &'static Config,
+    clock: SharedClock,
+    soils: Mutex<HashMap<String, Soil>>,
+    /// Input pins that were set, the others read high as if pulled up.
+    inputs: Mutex<HashMap<u64, bool>>
+}
+
+impl SimulatedGarden {
+    pub fn new(config: &'static Config, clock: SharedClock) -> Self {
+        let now = clock.now();
+        let soils = config.zones.iter()
+            .map(|zone| (zone.name.clone(), Soil { moisture: config.simulation_initial_moisture_percent, pump_on: false, updated_at: now }))
+            .collect();
+
+        SimulatedGarden { config, clock, soils: Mutex::new(soils), inputs: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn hardware(self) -> Hardware {
+        let garden = Arc::new(self);
+        Hardware { actuator: garden.clone(), moisture_sensor: garden.clone(), digital_input: garden }
+    }
+
+    /// Brings the zone's soil up to the current time before looking at it.
+    fn with_soil<T>(&self, zone: &str, closure: impl FnOnce(&mut Soil) -> T) -> GardenResult<T> {
+        let mut soils = self.soils.lock().unwrap();
+        let soil = soils.get_mut(zone).ok_or_else(|| GardenError::UnknownZone(zone.to_string()))?;
+        soil.advance(self.config, self.clock.now());
+        Ok(closure(soil))
+    }
+
+    pub fn moisture(&self, zone: &str) -> GardenResult<f64> {
+        self.with_soil(zone, |soil| soil.moisture)
+    }
+
+    /// Drives an input pin, e.g. to make it rain on the rain sensor.
+    pub fn set_input(&self, pin: u64, high: bool) {
+        self.inputs.lock().unwrap().insert(pin, high);
+    }
+}
+
+impl Actuator for SimulatedGarden {
+    fn set_pump(&self, zone: &Zone, on: bool) -> GardenResult<()> {
+        self.with_soil(&zone.name, |soil| soil.pump_on = on)
+    }
+}
+
+impl MoistureSensor for SimulatedGarden {
+    fn read_moisture(&self, zone: &Zone) -> GardenResult<f64> {
+        self.moisture(&zone.name)
+    }
+}
+
+impl DigitalInput for SimulatedGarden {
+    fn read_input(&self, pin: u64) -> GardenResult<bool> {
+        Ok(self.inputs.lock().unwrap().get(&pin).copied().unwrap_or(true))
+    }
+}
@@ -0,0 +1,120 @@
+// standard
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+// internal
+use crate::definitions::{GardenEvent, MoistureReading, RunOutcome, RunRecord, SkippedRun, WateringRun, local_time};
+
+// external
+use chrono_tz::Tz;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tracing::{warn};
+
+
+/// Snapshot of what the garden is doing right now, kept up to date from the event stream.
+#[derive(Debug, Clone, Default)]
+pub struct GardenStatus {
+    pub current_run: Option<WateringRun>,
+    pub last_runs: BTreeMap<String, RunRecord>,
+    pub moisture: BTreeMap<String, MoistureReading>,
+    pub sensor_failures: BTreeMap<String, String>,
+    /// `None` until the rain sensor settled, or without one.
+    pub rain_sensor_wet: Option<bool>,
+    pub last_skipped: Option<SkippedRun>,
+    pub last_failure: Option<String>
+}
+
+pub type SharedStatus = Arc<RwLock<GardenStatus>>;
+
+impl GardenStatus {
+    /// What the garden is doing, with times in `timezone`.
+    pub fn describe(&self, timezone: Tz) -> String {
+        let mut lines = Vec::new();
+
+        match &self.current_run {
+            Some(run) => lines.push(format!("Watering {} for {} seconds since {}", run.zone, run.seconds, run.started_at.with_timezone(&timezone).format("%H:%M:%S %Z"))),
+            None => lines.push("Pump is idle".to_string())
+        }
+
+        for (zone, reading) in &self.moisture {
+            lines.push(format!("Moisture in {}: {:.1}% at {}", zone, reading.moisture, local_time(reading.at, timezone)));
+        }
+
+        match self.rain_sensor_wet {
+            Some(true) => lines.push("Rain sensor is wet".to_string()),
+            Some(false) => lines.push("Rain sensor is dry".to_string()),
+            None => {}
+        }
+
+        for (zone, reason) in &self.sensor_failures {
+            lines.push(format!("Moisture sensor in {} failed: {}", zone, reason));
+        }
+
+        for (zone, record) in &self.last_runs {
+            lines.push(format!("Last run in {}: {} for {} seconds ({}), {}", zone, local_time(record.run.started_at, timezone), record.run.seconds, record.run.trigger, record.outcome));
+        }
+
+        if let Some(skipped) = &self.last_skipped {
+            lines.push(format!("Last skipped run: {}, {}", local_time(skipped.at, timezone), skipped.reason));
+        }
+
+        if let Some(reason) = &self.last_failure {
+            lines.push(format!("Last problem: {}", reason));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn apply(&mut self, event: &GardenEvent) {
+        match event {
+            GardenEvent::WateringStarted(run) => {
+                self.current_run = Some(run.clone());
+            },
+            GardenEvent::WateringFinished(run) => self.finish_run(run, RunOutcome::Ok),
+            GardenEvent::WateringStopped(run) => self.finish_run(run, RunOutcome::Stopped),
+            GardenEvent::WateringFailed(run, reason) => self.finish_run(run, RunOutcome::Failed(reason.clone())),
+            GardenEvent::MoistureRead(reading) => {
+                self.sensor_failures.remove(&reading.zone);
+                self.moisture.insert(reading.zone.clone(), reading.clone());
+            },
+            GardenEvent::SensorFailed(zone, reason) => {
+                self.sensor_failures.insert(zone.clone(), reason.clone());
+            },
+            GardenEvent::WateringSkipped(skipped) => {
+                self.last_skipped = Some(skipped.clone());
+            },
+            GardenEvent::RainSensorChanged(wet) => {
+                self.rain_sensor_wet = Some(*wet);
+            },
+            GardenEvent::TaskRan(_, _) | GardenEvent::TasksChanged => {},
+            GardenEvent::Failure(reason) => {
+                self.last_failure = Some(reason.clone());
+            }
+        }
+    }
+
+    fn finish_run(&mut self, run: &WateringRun, outcome: RunOutcome) {
+        self.current_run = None;
+        self.last_runs.insert(run.zone.clone(), RunRecord { run: run.clone(), outcome });
+    }
+
+    pub fn run_threaded(status: SharedStatus, mut event_receiver: broadcast::Receiver<GardenEvent>) {
+        thread::spawn(move || {
+            let runtime = Runtime::new().unwrap();
+
+            runtime.block_on(async move {
+                loop {
+                    match event_receiver.recv().await {
+                        Ok(event) => status.write().unwrap().apply(&event),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!(skipped, "Status fell behind, events were dropped");
+                        },
+                        Err(broadcast::error::RecvError::Closed) => break
+                    }
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,54 @@
+// standard
+use std::fmt;
+
+// external
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset
+}
+
+impl fmt::Display for SunEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SunEvent::Sunrise => write!(f, "sunrise"),
+            SunEvent::Sunset => write!(f, "sunset"),
+        }
+    }
+}
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+/// When the sun rises or sets on `date` at the given place, computed with the sunrise equation
+/// (good to about a minute). `None` during polar day or night. Longitude is positive east.
+pub fn sun_event(date: NaiveDate, event: SunEvent, latitude: f64, longitude: f64) -> Option<DateTime<Utc>> {
+    let days_since_j2000 = (date - NaiveDate::from_ymd(2000, 1, 1)).num_days() as f64;
+
+    // mean solar time, solar mean anomaly, equation of the center and ecliptic longitude
+    let mean_solar_time = days_since_j2000 - longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0).to_radians();
+    let center = 1.9148 * mean_anomaly.sin() + 0.0200 * (2.0 * mean_anomaly).sin() + 0.0003 * (3.0 * mean_anomaly).sin();
+    let ecliptic_longitude = (mean_anomaly.to_degrees() + center + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+
+    let solar_transit = J2000 + mean_solar_time + 0.0053 * mean_anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin()).asin();
+
+    // -0.833° accounts for refraction and the size of the sun's disc
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin()) / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    let julian_day = match event {
+        SunEvent::Sunrise => solar_transit - hour_angle / 360.0,
+        SunEvent::Sunset => solar_transit + hour_angle / 360.0
+    };
+    let unix_millis = ((julian_day - UNIX_EPOCH_JULIAN_DAY) * 86_400_000.0).round() as i64;
+    Some(Utc.timestamp_millis(unix_millis))
+}
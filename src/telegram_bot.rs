@@ -1,16 +1,19 @@
-// standard
-use std::error::Error;
-use std::process;
-
 // internal
 use crate::config::{Config};
-use crate::definitions::{WorkerChannelMessage, SchedulerChannelMessage, PeriodicTaskType, PeriodicTask};
+use crate::definitions::{ChatId, CorrelationId, NotifierChannelMessage, QuietHours, WateringRequest, Trigger, MoistureReading, RunRecord};
+use crate::control::{Controller, PREVIEW_RUNS, parse_task_type};
+use crate::error::{GardenError, GardenResult};
+use crate::history::{History, parse_period, period_start};
+use crate::chart::{render_moisture_chart, zone_colors};
+use crate::export::{export, ExportFormat};
+use crate::metrics::{METRICS};
 use crate::reply_text::{get_confirmation_phrase, get_fact};
+use crate::schedule::{describe_preview};
 
 // external
-use teloxide::{prelude::*, utils::command::BotCommands};
-use job_scheduler::{Schedule};
-use crossbeam_channel::{Sender};
+use teloxide::{prelude::*, types::InputFile, utils::command::BotCommands};
+use chrono::{DateTime, NaiveTime, Utc};
+use tracing::{error, info, info_span, warn, Instrument};
 
 
 #[derive(BotCommands, Clone)]
@@ -18,16 +21,36 @@ use crossbeam_channel::{Sender};
 enum Command {
     #[command(description = "Help")]
     Help,
-    #[command(description = "Water Plants")]
+    #[command(description = "Water Plants, optionally only one zone and for how many seconds, e.g. tomatoes 30")]
     WaterPlants(String),
+    #[command(description = "Stop watering and drop the queued watering cycles")]
+    Stop,
+    #[command(description = "What the garden is doing right now")]
+    Status,
     #[command(description = "List Periodic Tasks")]
     ListTasks,
     #[command(description = "Removes Periodic Task")]
     RemoveTask(String),
     #[command(description = "Update Periodic Task Schedule")]
     UpdateTask(String),
+    #[command(description = "Preview the next runs of a schedule without changing any task, e.g. every 36h")]
+    PreviewSchedule(String),
     #[command(description = "Reset all tasks to default config")]
     ResetTasks,
+    #[command(description = "Moisture and watering history, e.g. tomatoes 7d")]
+    History(String),
+    #[command(description = "Moisture chart with watering runs, e.g. tomatoes 7d")]
+    Chart(String),
+    #[command(description = "Export readings and watering runs as csv or json, e.g. 30d csv tomatoes")]
+    Export(String),
+    #[command(description = "Subscribe this chat to garden notifications")]
+    Subscribe,
+    #[command(description = "Unsubscribe this chat from garden notifications")]
+    Unsubscribe,
+    #[command(description = "Hold back routine notifications, e.g. 22:00-07:00 or off")]
+    QuietHours(String),
+    #[command(description = "Batch routine notifications into a daily summary, e.g. 20:00 or off")]
+    Digest(String),
     #[command(description = "Shutdowns the system. Please note that you will need to restart it manually.")]
     Shutdown
 }
@@ -35,113 +58,266 @@ enum Command {
 async fn reply_helper(bot: AutoSend<Bot>, message: Message) {
     let phrase = get_confirmation_phrase();
     let fact = get_fact();
-    match bot.send_message(message.chat.id, format!("Beep Boop Bop... {phrase} Also did you know that {fact}.")).await {
-        Err(_) => { 
-            println!("Failed to send a reply."); 
+    if bot.send_message(message.chat.id, format!("Beep Boop Bop... {phrase} Also did you know that {fact}.")).await.is_err() {
+        METRICS.telegram_send_failed();
+        warn!("Failed to send a reply");
+    }
+}
+
+async fn reply_result(bot: AutoSend<Bot>, message: Message, result: GardenResult<()>) -> GardenResult<()> {
+    match result {
+        Ok(_) => reply_helper(bot, message).await,
+        Err(error) => {
+            bot.send_message(message.chat.id, error.reply()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses optional `[zone] [period]` arguments, the period defaults to 24h.
+fn parse_zone_and_period(input: &str, config: &Config) -> Result<(Option<String>, chrono::Duration), String> {
+    let mut zone = None;
+    let mut period = chrono::Duration::hours(24);
+
+    for arg in input.split_whitespace() {
+        match parse_period(arg) {
+            Some(parsed) => period = parsed,
+            None if config.zone(arg).is_some() => zone = Some(arg.to_string()),
+            None => return Err(format!("Wrong input... {arg} is neither a known zone nor a period like 24h or 7d"))
         }
-        _ => {}
     }
+
+    Ok((zone, period))
 }
 
-async fn handle_commands(bot: AutoSend<Bot>, message: Message, command: Command, worker_channel_sender: Sender<WorkerChannelMessage>, scheduler_channel_sender: Sender<SchedulerChannelMessage>, config: &Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+fn read_history(history: &History, zone: Option<&str>, since: DateTime<Utc>) -> GardenResult<(Vec<MoistureReading>, Vec<RunRecord>)> {
+    Ok((history.readings(zone, since)?, history.runs(zone, since)?))
+}
+
+fn chat(message: &Message) -> ChatId {
+    ChatId(message.chat.id.0)
+}
+
+fn input_period(input: &str) -> &str {
+    input.split_whitespace().find(|arg| parse_period(arg).is_some()).unwrap_or("24h")
+}
+
+/// Errors that are not about talking to Telegram are replied to the chat by the caller.
+async fn handle_commands(bot: AutoSend<Bot>, message: Message, command: Command, controller: Controller) -> GardenResult<()> {
+    let config = controller.config();
+
     match command {
         Command::Help => {
             bot.send_message(message.chat.id, Command::descriptions().to_string()).await?;
         }
-        Command::WaterPlants(watering_cycle_seconds) => {
-            let max_time_sec = 60 * 5;
-            let min_time_sec = 10;
-            let water_pump_working_cycle_seconds: u64 = watering_cycle_seconds.trim().to_string().parse().unwrap_or(config.water_pump_working_cycle_seconds);
-
-            if water_pump_working_cycle_seconds < min_time_sec || water_pump_working_cycle_seconds > max_time_sec {
-                bot.send_message(message.chat.id, format!("Wrong input... cycle to water plants is {water_pump_working_cycle_seconds} but should be a numder between {max_time_sec} and {min_time_sec} seconds")).await?;
-                return Ok(());
-            }
+        Command::WaterPlants(input) => {
+            let mut args = input.split_whitespace().peekable();
+            let zone = match args.peek() {
+                Some(arg) if arg.parse::<u64>().is_err() => args.next().map(|zone| zone.to_string()),
+                _ => None
+            };
+            let water_pump_working_cycle_seconds: u64 = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(config.water_pump_working_cycle_seconds);
 
-            worker_channel_sender.send(WorkerChannelMessage::WaterPlants(water_pump_working_cycle_seconds)).unwrap();
-            reply_helper(bot, message).await;
+            let request = WateringRequest { zone, seconds: water_pump_working_cycle_seconds, trigger: Trigger::Manual };
+            reply_result(bot, message, controller.water(request)).await?;
+        },
+        Command::Stop => {
+            reply_result(bot, message, controller.stop()).await?;
+        },
+        Command::Status => {
+            bot.send_message(message.chat.id, controller.status().describe(config.timezone)).await?;
         },
         Command::ListTasks => {
-            scheduler_channel_sender.send(SchedulerChannelMessage::ListPeriodicTasks(message.chat.id)).unwrap();
+            match controller.list_tasks().await {
+                Ok(tasks) if tasks.is_empty() => {
+                    bot.send_message(message.chat.id, "No periodic tasks are scheduled").await?;
+                },
+                Ok(tasks) => {
+                    let tasks: Vec<_> = tasks.iter().map(|meta| meta.describe(config.timezone)).collect();
+                    bot.send_message(message.chat.id, tasks.join("\n\n")).await?;
+                },
+                Err(error) => {
+                    bot.send_message(message.chat.id, error.reply()).await?;
+                }
+            }
         }
-        Command::RemoveTask(task_type) => {
-
-            let task = match task_type.as_str() {
-                "WaterPlants" => Ok(PeriodicTaskType::WaterPlants),
-                "ReadMoistureSensorsData" => Ok(PeriodicTaskType::ReadMoistureSensorsData),
-                _ => Err(())
+        Command::PreviewSchedule(schedule) => {
+            let reply = match controller.preview_schedule(&schedule, PREVIEW_RUNS) {
+                Ok(runs) => describe_preview(&schedule, &runs, config.timezone),
+                Err(error) => error.reply()
             };
-
-            match task {
-                Ok(t) => {
-                    scheduler_channel_sender.send(SchedulerChannelMessage::RemovePeriodicTask(t)).unwrap();
-                    reply_helper(bot, message).await
-                },
+            bot.send_message(message.chat.id, reply).await?;
+        },
+        Command::RemoveTask(task_type) => {
+            match parse_task_type(&task_type) {
+                Ok(t) => reply_result(bot, message, controller.remove_task(t).await).await?,
                 Err(_) => {
-                    bot.send_message(message.chat.id, format!("Wrong input... {task_type} is not a valid task. Use /listperiodictasks command to see the valid list of tasks")).await?;
+                    bot.send_message(message.chat.id, format!("Wrong input... {task_type} is not a valid task. Use /listtasks command to see the valid list of tasks")).await?;
                 }
             }
         },
         Command::UpdateTask(input) => {
             let inputs: Vec<_> = input.split(" | ").collect();
             if inputs.len() != 2 {
-                bot.send_message(message.chat.id, format!("Wrong input... Couldn't parse task_type or/and cron expression")).await?;
+                bot.send_message(message.chat.id, "Wrong input... Couldn't parse task_type or/and cron expression").await?;
                 return Ok(())
             }
             let task_type = inputs[0];
             let schedule = inputs[1];
 
-            let schedule_: Result<Schedule, _> = schedule.parse();
-            let task_type_ = match task_type {
-                "WaterPlants" => Ok(PeriodicTaskType::WaterPlants),
-                "ReadMoistureSensorsData" => Ok(PeriodicTaskType::ReadMoistureSensorsData),
-                _ => Err(())
-            };
-  
-
-            if schedule_.is_err() || task_type_.is_err() {
-                bot.send_message(message.chat.id, format!("Wrong input... {task_type} or {schedule} is not valid. Please double check the syntax and try again.")).await?;
-            } else {
-                let task = PeriodicTask {
-                    task_type: task_type_.unwrap(),
-                    cron: schedule.to_string(),
-                    disabled: false
-                };
-                scheduler_channel_sender.send(SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task)).unwrap();
-                reply_helper(bot, message).await
+            match parse_task_type(task_type) {
+                Ok(task_type_) => {
+                    reply_result(bot, message, controller.update_task_schedule(task_type_, schedule.to_string(), None).await).await?;
+                },
+                Err(_) => {
+                    bot.send_message(message.chat.id, format!("Wrong input... {task_type} or {schedule} is not valid. Please double check the syntax and try again.")).await?;
+                }
             }
         },
         Command::ResetTasks => {
-            scheduler_channel_sender.send(SchedulerChannelMessage::ResetTasks).unwrap();
-            reply_helper(bot, message).await
+            reply_result(bot, message, controller.reset_tasks().await).await?;
+        },
+        Command::History(input) => {
+            let (zone, period) = match parse_zone_and_period(&input, config) {
+                Ok(args) => args,
+                Err(error) => {
+                    bot.send_message(message.chat.id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let since = period_start(controller.clock().now(), period)?;
+            let summary = controller.read_history(move |history| Ok(history.summary(zone.as_deref(), since, config.timezone)?)).await?;
+            bot.send_message(message.chat.id, summary).await?;
+        },
+        Command::Chart(input) => {
+            let (zone, period) = match parse_zone_and_period(&input, config) {
+                Ok(args) => args,
+                Err(error) => {
+                    bot.send_message(message.chat.id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let to = controller.clock().now();
+            let from = period_start(to, period)?;
+            let zone_ = zone.clone();
+            let (png, readings, runs) = controller.read_history(move |history| {
+                let (readings, runs) = read_history(&history, zone_.as_deref(), from)?;
+                let png = render_moisture_chart(&readings, &runs, from, to).map_err(|error| GardenError::Render(error.to_string()))?;
+                Ok((png, readings, runs))
+            }).await?;
+            let mut caption = format!(
+                "Moisture in {} over the last {} (0-100%, gridlines every 25%)\n{} readings, {} watering runs (green ok, grey stopped or skipped, red failed)",
+                zone.as_deref().unwrap_or("all zones"), input_period(&input), readings.len(), runs.len()
+            );
+            let colors = zone_colors(&readings);
+            if colors.len() > 1 {
+                let lines: Vec<_> = colors.iter().map(|(zone, color)| format!("{} {}", zone, color)).collect();
+                caption.push_str(&format!("\nLines: {}", lines.join(", ")));
+            }
+            bot.send_photo(message.chat.id, InputFile::memory(png).file_name("chart.png")).caption(caption).await?;
+        },
+        Command::Export(input) => {
+            let format = input.split_whitespace().find_map(|arg| arg.parse::<ExportFormat>().ok()).unwrap_or(ExportFormat::Csv);
+            let rest: Vec<_> = input.split_whitespace().filter(|arg| arg.parse::<ExportFormat>().is_err()).collect();
+
+            let (zone, period) = match parse_zone_and_period(&rest.join(" "), config) {
+                Ok(args) => args,
+                Err(error) => {
+                    bot.send_message(message.chat.id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let since = period_start(controller.clock().now(), period)?;
+            let file_name = format!("{}_{}.{}", zone.as_deref().unwrap_or("garden"), input_period(&input), format.extension());
+            let exported = controller.read_history(move |history| {
+                let (readings, runs) = read_history(&history, zone.as_deref(), since)?;
+                Ok(export(&readings, &runs, format))
+            }).await?;
+            let document = InputFile::memory(exported).file_name(file_name);
+            bot.send_document(message.chat.id, document).await?;
+        },
+        Command::Subscribe => {
+            reply_result(bot, message.clone(), controller.notify(NotifierChannelMessage::Subscribe(chat(&message)))).await?;
+        },
+        Command::Unsubscribe => {
+            reply_result(bot, message.clone(), controller.notify(NotifierChannelMessage::Unsubscribe(chat(&message)))).await?;
+        },
+        Command::QuietHours(input) => {
+            let quiet_hours = match input.trim() {
+                "off" => Ok(None),
+                window => window.parse::<QuietHours>().map(Some)
+            };
+
+            match quiet_hours {
+                Ok(quiet_hours) => {
+                    reply_result(bot, message.clone(), controller.notify(NotifierChannelMessage::SetQuietHours(chat(&message), quiet_hours))).await?;
+                },
+                Err(_) => {
+                    bot.send_message(message.chat.id, format!("Wrong input... {input} is not valid. Use a window like 22:00-07:00 or off")).await?;
+                }
+            }
+        },
+        Command::Digest(input) => {
+            let digest_at = match input.trim() {
+                "off" => Ok(None),
+                time => NaiveTime::parse_from_str(time, "%H:%M").map(Some)
+            };
+
+            match digest_at {
+                Ok(digest_at) => {
+                    reply_result(bot, message.clone(), controller.notify(NotifierChannelMessage::SetDigest(chat(&message), digest_at))).await?;
+                },
+                Err(_) => {
+                    bot.send_message(message.chat.id, format!("Wrong input... {input} is not valid. Use a time like 20:00 or off")).await?;
+                }
+            }
         },
         Command::Shutdown => {
-            bot.send_message(message.chat.id, format!("Shutting down... I won't be able to process any commands until you restart me.")).await?;
-            process::exit(1);
+            bot.send_message(message.chat.id, "Shutting down... I won't be able to process any commands until you restart me.").await?;
+            controller.shutdown();
         }
     }
     Ok(())
 }
 
 pub struct TelegramBot {} impl TelegramBot {
-     pub async fn run_async(
-        config: &'static Config,
-        worker_channel_sender: Sender<WorkerChannelMessage>,
-        scheduler_channel_sender: Sender<SchedulerChannelMessage>
-    ) {
-        let teloxide_bot = Bot::from_env().auto_send();
+     pub async fn run_async(controller: Controller) {
+        TelegramBot::run_with_bot(Bot::from_env(), controller).await;
+    }
+
+    /// Tests point `bot` at a stand-in for the Telegram Bot API.
+    pub async fn run_with_bot(bot: Bot, controller: Controller) {
+        let teloxide_bot = bot.auto_send();
 
         let handler = move |bot: AutoSend<Bot>, message: Message, command: Command| {  
-            let worker_channel_sender_ = worker_channel_sender.clone();
-            let scheduler_channel_sender_ = scheduler_channel_sender.clone();
+            let controller_ = controller.clone();
 
             async move {
-                match handle_commands(bot, message, command, worker_channel_sender_, scheduler_channel_sender_, config).await {
-                    Err(error) => {
-                        println!("Failed to handle a command {:?}", error);
-                    },
-                    _ => {},
-                };
+                // follows the command through the scheduler and the worker
+                let correlation_id = CorrelationId::new();
+                let span = info_span!("command", %correlation_id, chat = %message.chat.id);
+
+                async move {
+                    info!("Handling a Telegram command");
+                    let chat_id = message.chat.id;
+
+                    match handle_commands(bot.clone(), message, command, controller_.traced(correlation_id)).await {
+                        Ok(_) => {},
+                        Err(error @ GardenError::Telegram(_)) => {
+                            METRICS.telegram_send_failed();
+                            error!(%error, "Failed to reply to a command");
+                        },
+                        Err(error) => {
+                            error!(%error, "Failed to handle a command");
+                            if bot.send_message(chat_id, error.reply()).await.is_err() {
+                                METRICS.telegram_send_failed();
+                            }
+                        }
+                    }
+                }.instrument(span).await;
                 respond(())
             }
         };
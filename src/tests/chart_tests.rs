@@ -0,0 +1,66 @@
+use chrono::{Duration, Utc};
+use crate::chart::{render_moisture_chart, zone_colors};
+use crate::definitions::{MoistureReading, RunOutcome, RunRecord, Trigger, WateringRun};
+
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+#[test]
+pub fn test_render_moisture_chart() {
+    let to = Utc::now();
+    let from = to - Duration::days(7);
+    let readings: Vec<_> = (0..7 * 24)
+        .map(|hour| MoistureReading { at: from + Duration::hours(hour), zone: "tomatoes".to_string(), moisture: (hour % 100) as f64 })
+        .collect();
+    let runs = vec![
+        RunRecord { run: WateringRun { started_at: from + Duration::days(1), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule }, outcome: RunOutcome::Ok },
+        RunRecord { run: WateringRun { started_at: from + Duration::days(2), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Manual }, outcome: RunOutcome::Failed("pump".to_string()) }
+    ];
+
+    let png = render_moisture_chart(&readings, &runs, from, to).unwrap();
+    assert!(png.starts_with(PNG_SIGNATURE));
+}
+
+#[test]
+pub fn test_render_empty_moisture_chart() {
+    let to = Utc::now();
+    let png = render_moisture_chart(&[], &[], to - Duration::hours(24), to).unwrap();
+    assert!(png.starts_with(PNG_SIGNATURE));
+}
+
+#[test]
+pub fn test_each_zone_gets_its_own_line() {
+    let to = Utc::now();
+    let from = to - Duration::hours(24);
+    // readings of two zones taken in turns, 20% and 80% apart
+    let readings: Vec<_> = (0..24)
+        .map(|hour| {
+            let (zone, moisture) = if hour % 2 == 0 { ("tomatoes", 80.0) } else { ("lawn", 20.0) };
+            MoistureReading { at: from + Duration::hours(hour), zone: zone.to_string(), moisture }
+        })
+        .collect();
+
+    assert_eq!(zone_colors(&readings), vec![("lawn".to_string(), "blue"), ("tomatoes".to_string(), "orange")]);
+
+    let png = render_moisture_chart(&readings, &[], from, to).unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgb8();
+    // halfway between two gridlines, at 37.5%, a single line zig-zagging between the zones would show up
+    let y = 16 + (image.height() - 32) * 5 / 8;
+    assert!((17..image.width() - 17).all(|x| image.get_pixel(x, y).0 == [255, 255, 255]));
+}
+
+#[test]
+pub fn test_stopped_runs_are_not_drawn_as_failures() {
+    let to = Utc::now();
+    let from = to - Duration::hours(24);
+    let runs = vec![
+        RunRecord { run: WateringRun { started_at: from + Duration::hours(6), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule }, outcome: RunOutcome::Stopped },
+        RunRecord { run: WateringRun { started_at: from + Duration::hours(18), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule }, outcome: RunOutcome::Skipped("rain".to_string()) }
+    ];
+
+    let png = render_moisture_chart(&[], &runs, from, to).unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgb8();
+    let y = image.height() * 5 / 8;
+    let row: Vec<_> = (0..image.width()).map(|x| image.get_pixel(x, y).0).collect();
+    assert!(row.iter().all(|[r, g, _]| r.saturating_sub(*g) <= 10), "a run is drawn in red");
+    assert!(row.iter().any(|[r, g, b]| r == g && g == b && *r < 200), "the runs are not drawn");
+}
@@ -0,0 +1,116 @@
+use crate::config::{Config};
+use crate::ctl::{parse_args, execute, run_cli, send, CtlRequest, CtlResponse, CtlServer};
+use crate::definitions::{CatchUp, PeriodicTask, PeriodicTaskType, SchedulerChannelMessage, Trigger, WorkerChannelMessage};
+use crate::scheduler::{Scheduler};
+use crate::tests::{ControllerHarness};
+
+use job_scheduler::Uuid;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+fn args(input: &str) -> Vec<String> {
+    input.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+pub fn test_parse_args() {
+    assert_eq!(parse_args(&args("water")), Some(CtlRequest::Water { zone: None, seconds: None }));
+    assert_eq!(parse_args(&args("water 30")), Some(CtlRequest::Water { zone: None, seconds: Some(30) }));
+    assert_eq!(parse_args(&args("water tomatoes")), Some(CtlRequest::Water { zone: Some("tomatoes".to_string()), seconds: None }));
+    assert_eq!(parse_args(&args("water tomatoes 60")), Some(CtlRequest::Water { zone: Some("tomatoes".to_string()), seconds: Some(60) }));
+    assert_eq!(parse_args(&args("water tomatoes lots")), None);
+    assert_eq!(parse_args(&args("stop")), Some(CtlRequest::Stop));
+    assert_eq!(parse_args(&args("status")), Some(CtlRequest::Status));
+    assert_eq!(parse_args(&args("tasks list")), Some(CtlRequest::ListTasks));
+    assert_eq!(parse_args(&args("tasks update WaterPlants 0 0 7 * * *")), Some(CtlRequest::UpdateTask { task_type: "WaterPlants".to_string(), cron: "0 0 7 * * *".to_string() }));
+    assert_eq!(parse_args(&args("tasks update WaterPlants")), None);
+    assert_eq!(parse_args(&args("tasks preview every 36h")), Some(CtlRequest::PreviewSchedule { schedule: "every 36h".to_string() }));
+    assert_eq!(parse_args(&args("dance")), None);
+    assert_eq!(parse_args(&[]), None);
+}
+
+#[tokio::test]
+pub async fn test_execute_maps_onto_controller() {
+    let ControllerHarness { controller, mut worker_channel_receiver, mut scheduler_channel_receiver, .. } = ControllerHarness::new(Config::new());
+
+    let response = execute(&controller, CtlRequest::Water { zone: Some("garden".to_string()), seconds: None }).await;
+    assert!(response.ok);
+    match worker_channel_receiver.try_recv().map(|traced| traced.message) {
+        Ok(WorkerChannelMessage::WaterPlants(request)) => {
+            assert_eq!(request.seconds, controller.config().water_pump_working_cycle_seconds);
+            assert_eq!(request.trigger, Trigger::Cli);
+        },
+        other => panic!("unexpected message {:?}", other)
+    }
+
+    let response = execute(&controller, CtlRequest::Water { zone: Some("roses".to_string()), seconds: None }).await;
+    assert!(!response.ok);
+    assert_eq!(response.output, "roses is not a known zone");
+
+    let response = execute(&controller, CtlRequest::UpdateTask { task_type: "WaterPlants".to_string(), cron: "not cron".to_string() }).await;
+    assert!(!response.ok);
+
+    assert!(execute(&controller, CtlRequest::Pause).await.ok);
+    assert!(matches!(scheduler_channel_receiver.try_recv().map(|traced| traced.message), Ok(SchedulerChannelMessage::SetPaused(true))));
+}
+
+#[tokio::test]
+pub async fn test_updating_a_schedule_leaves_the_rest_of_the_task_alone() {
+    let ControllerHarness { config, controller, worker_channel_sender, scheduler_channel_receiver, event_sender, .. } = ControllerHarness::new(Config::new());
+    let mut scheduler = Scheduler::new(config, controller.clock().clone(), scheduler_channel_receiver, worker_channel_sender, event_sender);
+    scheduler.add_task(PeriodicTask { task_type: PeriodicTaskType::WaterPlants, cron: "0 0 6 * * *".to_string(), disabled: true, catch_up: CatchUp::All }).unwrap();
+    tokio::spawn(async move { scheduler.run().await });
+
+    let response = execute(&controller, CtlRequest::UpdateTask { task_type: "WaterPlants".to_string(), cron: "0 0 7 * * *".to_string() }).await;
+    assert!(response.ok);
+
+    let task = controller.list_tasks().await.unwrap().remove(0).task;
+    assert_eq!(task.cron, "0 0 7 * * *");
+    assert!(task.disabled);
+    assert!(matches!(task.catch_up, CatchUp::All));
+}
+
+#[tokio::test]
+pub async fn test_round_trip_over_unix_socket() {
+    let mut config = Config::new();
+    config.ctl_socket_path = std::env::temp_dir().join(format!("smart_garden_test_{}.sock", Uuid::new_v4().to_simple())).to_string_lossy().to_string();
+    config.ctl_request_timeout_seconds = 1;
+    let socket_path = config.ctl_socket_path.clone();
+    let ControllerHarness { controller, mut worker_channel_receiver, .. } = ControllerHarness::new(config);
+
+    tokio::spawn(CtlServer::run_async(controller));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(std::fs::metadata(&socket_path).unwrap().permissions().mode() & 0o777, 0o660);
+
+    let path = socket_path.clone();
+    let response = tokio::task::spawn_blocking(move || send(&path, &CtlRequest::Stop)).await.unwrap().unwrap();
+    assert!(response.ok);
+    assert!(matches!(worker_channel_receiver.try_recv().map(|traced| traced.message), Ok(WorkerChannelMessage::Stop)));
+
+    // the CLI finds a daemon that isn't at the default path
+    let cli_args = args(&format!("--socket {} stop", socket_path));
+    assert_eq!(tokio::task::spawn_blocking(move || run_cli(&Config::new(), &cli_args)).await.unwrap(), 0);
+    assert!(matches!(worker_channel_receiver.try_recv().map(|traced| traced.message), Ok(WorkerChannelMessage::Stop)));
+
+    // a request that never ends is cut off instead of read into memory
+    let path = socket_path.clone();
+    let response = tokio::time::timeout(Duration::from_secs(5), tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(&path).unwrap();
+        // the server stops reading long before the end
+        stream.write_all(&vec![b'x'; 1024 * 1024]).unwrap_or_default();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line
+    })).await.unwrap().unwrap();
+    let response: CtlResponse = serde_json::from_str(&response).unwrap();
+    assert!(!response.ok && response.output.starts_with("Invalid request"));
+
+    // and one that never comes is given up on
+    let mut stream = UnixStream::connect(&socket_path).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let closed = tokio::task::spawn_blocking(move || stream.read(&mut [0; 1]).unwrap()).await.unwrap();
+    assert_eq!(closed, 0);
+}
@@ -0,0 +1,10 @@
+use crate::definitions::{PeriodicTaskType};
+use crate::error::{GardenError};
+
+#[test]
+pub fn test_error_replies_tell_input_from_temporary_failures() {
+    assert_eq!(GardenError::Busy("worker").reply(), "Sorry... the worker is not responding. Please try again later.");
+    assert_eq!(GardenError::UnknownZone("roses".to_string()).reply(), "Wrong input... roses is not a known zone");
+    assert_eq!(GardenError::Render("out of memory".to_string()).reply(), "Sorry... failed to render the chart: out of memory");
+    assert!(matches!("Watering".parse::<PeriodicTaskType>(), Err(GardenError::InvalidTask(task)) if task == "Watering"));
+}
@@ -0,0 +1,43 @@
+use chrono::{TimeZone, Utc};
+use crate::definitions::{MoistureReading, RunOutcome, RunRecord, Trigger, WateringRun};
+use crate::export::{export, ExportFormat};
+
+fn history() -> (Vec<MoistureReading>, Vec<RunRecord>) {
+    let readings = vec![
+        MoistureReading { at: Utc.ymd(2022, 6, 1).and_hms(6, 0, 0), zone: "tomatoes".to_string(), moisture: 42.5 }
+    ];
+    let runs = vec![
+        RunRecord {
+            run: WateringRun { started_at: Utc.ymd(2022, 6, 1).and_hms(5, 0, 0), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule },
+            outcome: RunOutcome::Failed("Pin, \"4\" busy".to_string())
+        }
+    ];
+    (readings, runs)
+}
+
+#[test]
+pub fn test_export_format_from_str() {
+    assert_eq!("csv".parse::<ExportFormat>(), Ok(ExportFormat::Csv));
+    assert_eq!("json".parse::<ExportFormat>(), Ok(ExportFormat::Json));
+    assert!("xml".parse::<ExportFormat>().is_err());
+}
+
+#[test]
+pub fn test_export_csv_sorts_rows_and_escapes_fields() {
+    let (readings, runs) = history();
+    let csv = String::from_utf8(export(&readings, &runs, ExportFormat::Csv)).unwrap();
+
+    assert_eq!(csv, "kind,timestamp,zone,moisture,seconds,trigger,outcome\n\
+        run,2022-06-01T05:00:00+00:00,tomatoes,,60,schedule,\"Pin, \"\"4\"\" busy\"\n\
+        reading,2022-06-01T06:00:00+00:00,tomatoes,42.5,,,\n");
+}
+
+#[test]
+pub fn test_export_json() {
+    let (readings, runs) = history();
+    let json: serde_json::Value = serde_json::from_slice(&export(&readings, &runs, ExportFormat::Json)).unwrap();
+
+    assert_eq!(json["readings"][0]["moisture"], 42.5);
+    assert_eq!(json["runs"][0]["trigger"], "schedule");
+    assert_eq!(json["runs"][0]["outcome"], "Pin, \"4\" busy");
+}
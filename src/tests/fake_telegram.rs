@@ -0,0 +1,129 @@
+//! A local stand-in for the Telegram Bot API, the bot polls it for scripted updates
+//! and everything it sends back ends up in `FakeTelegram::sent`.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use teloxide::Bot;
+use tokio::sync::{mpsc, Mutex};
+
+pub const CHAT_ID: i64 = 42;
+
+/// A request the bot made that is not part of polling, e.g. `sendMessage`.
+#[derive(Debug)]
+pub struct Sent {
+    pub method: String,
+    pub body: String
+}
+
+impl Sent {
+    /// The `text` of a `sendMessage`.
+    pub fn text(&self) -> String {
+        serde_json::from_str::<Value>(&self.body).ok()
+            .and_then(|body| body["text"].as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+struct Api {
+    updates: Mutex<mpsc::UnboundedReceiver<Value>>,
+    sent: mpsc::UnboundedSender<Sent>
+}
+
+pub struct FakeTelegram {
+    address: SocketAddr,
+    updates: mpsc::UnboundedSender<Value>,
+    sent: mpsc::UnboundedReceiver<Sent>,
+    next_update_id: i64
+}
+
+impl FakeTelegram {
+    pub async fn start() -> Self {
+        let (updates, updates_receiver) = mpsc::unbounded_channel();
+        let (sent_sender, sent) = mpsc::unbounded_channel();
+        let api = Arc::new(Api { updates: Mutex::new(updates_receiver), sent: sent_sender });
+
+        let app = Router::new().route("/:bot/:method", post(handle)).with_state(api);
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        FakeTelegram { address, updates, sent, next_update_id: 1 }
+    }
+
+    pub fn bot(&self) -> Bot {
+        Bot::new("1234:TEST").set_api_url(format!("http://{}", self.address).parse().unwrap())
+    }
+
+    /// Queues a message from the test chat for the bot's next `getUpdates`.
+    pub fn send(&mut self, text: &str) {
+        let command_length = text.split_whitespace().next().unwrap_or_default().len();
+        self.updates.send(json!({
+            "update_id": self.next_update_id,
+            "message": {
+                "message_id": self.next_update_id,
+                "date": 0,
+                "chat": { "id": CHAT_ID, "type": "private", "first_name": "Gardener" },
+                "from": { "id": CHAT_ID, "is_bot": false, "first_name": "Gardener" },
+                "text": text,
+                "entities": [{ "type": "bot_command", "offset": 0, "length": command_length }]
+            }
+        })).unwrap();
+        self.next_update_id += 1;
+    }
+
+    pub async fn next_sent(&mut self) -> Sent {
+        tokio::time::timeout(Duration::from_secs(5), self.sent.recv()).await
+            .expect("the bot did not send anything")
+            .unwrap()
+    }
+
+    /// Sends `text` and returns the text of the bot's reply.
+    pub async fn ask(&mut self, text: &str) -> String {
+        self.send(text);
+        self.next_sent().await.text()
+    }
+}
+
+async fn handle(State(api): State<Arc<Api>>, Path((_bot, method)): Path<(String, String)>, body: Bytes) -> Json<Value> {
+    let result = match method.to_lowercase().as_str() {
+        "getme" => json!({
+            "id": 1234, "is_bot": true, "first_name": "Smart Garden", "username": "smart_garden_bot",
+            "can_join_groups": false, "can_read_all_group_messages": false, "supports_inline_queries": false
+        }),
+        "getwebhookinfo" => json!({ "url": "", "has_custom_certificate": false, "pending_update_count": 0 }),
+        "deletewebhook" | "setmycommands" => json!(true),
+        "getupdates" => json!(poll(&api).await),
+        _ => {
+            api.sent.send(Sent { method, body: String::from_utf8_lossy(&body).to_string() }).unwrap_or_default();
+            json!({
+                "message_id": 1,
+                "date": 0,
+                "chat": { "id": CHAT_ID, "type": "private", "first_name": "Gardener" },
+                "text": "ok"
+            })
+        }
+    };
+
+    Json(json!({ "ok": true, "result": result }))
+}
+
+/// Waits a little for an update, so the bot does not spin while nothing is scripted.
+async fn poll(api: &Api) -> Vec<Value> {
+    let mut updates = api.updates.lock().await;
+    let mut polled = Vec::new();
+
+    if let Ok(Some(update)) = tokio::time::timeout(Duration::from_millis(100), updates.recv()).await {
+        polled.push(update);
+        while let Ok(update) = updates.try_recv() {
+            polled.push(update);
+        }
+    }
+    polled
+}
@@ -0,0 +1,52 @@
+use crate::clock::{ManualClock};
+use crate::config::{Config};
+use crate::definitions::{GardenEvent, Trigger, WateringRequest};
+use crate::garden::{Garden};
+use crate::simulation::{SimulatedGarden};
+
+use chrono::{TimeZone, Utc};
+use job_scheduler::Uuid;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// The next event about watering, the scheduler also reports its tasks.
+async fn next_event(event_receiver: &mut broadcast::Receiver<GardenEvent>) -> GardenEvent {
+    tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            match event_receiver.recv().await.unwrap() {
+                GardenEvent::TaskRan(_, _) => {},
+                event => return event
+            }
+        }
+    }).await.unwrap()
+}
+
+#[tokio::test]
+pub async fn test_garden_runs_with_the_given_clock_and_hardware() {
+    let mut config = Config::new();
+    config.history_db_path = std::env::temp_dir().join(format!("smart_garden_test_{}.db", Uuid::new_v4().to_simple())).to_string_lossy().to_string();
+    let config: &'static Config = Box::leak(Box::new(config));
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(0, 0, 0)));
+
+    let mut garden = Garden::builder(config)
+        .clock(clock.clone())
+        .hardware(SimulatedGarden::new(config, clock.clone()).hardware())
+        .start();
+
+    assert_eq!(garden.controller.list_tasks().await.unwrap().len(), config.periodic_tasks.len());
+    // previews run on the garden's clock, 06:00 in London is 05:00 UTC in summer
+    assert_eq!(garden.controller.preview_schedule("0 0 6 * * *", 1).unwrap(), vec![Utc.ymd(2022, 6, 1).and_hms(5, 0, 0)]);
+
+    garden.controller.water(WateringRequest { zone: None, seconds: 30, trigger: Trigger::Api }).unwrap();
+    let started = next_event(&mut garden.event_receiver).await;
+    assert!(matches!(started, GardenEvent::WateringStarted(_)));
+
+    // let the worker turn the pump on before time moves
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    clock.advance(chrono::Duration::seconds(30));
+    let finished = next_event(&mut garden.event_receiver).await;
+    assert!(matches!(finished, GardenEvent::WateringFinished(_)));
+
+    std::fs::remove_file(&config.history_db_path).unwrap_or_default();
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use crate::config::{Config};
+use crate::definitions::{ChatId, GardenEvent, MoistureReading, RunOutcome, Trigger, WateringRun};
+use crate::history::{History, parse_period, period_start};
+
+fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.ymd(2022, 6, day).and_hms(hour, minute, 0)
+}
+
+fn reading(at: DateTime<Utc>, moisture: f64) -> GardenEvent {
+    GardenEvent::MoistureRead(MoistureReading { at, zone: "tomatoes".to_string(), moisture })
+}
+
+#[test]
+pub fn test_parse_period() {
+    assert_eq!(parse_period("24h"), Some(Duration::hours(24)));
+    assert_eq!(parse_period("7d"), Some(Duration::days(7)));
+    assert_eq!(parse_period("2w"), Some(Duration::weeks(2)));
+    assert_eq!(parse_period("0d"), None);
+    assert_eq!(parse_period("d"), None);
+    assert_eq!(parse_period("7m"), None);
+}
+
+#[test]
+pub fn test_odd_periods_are_rejected_without_panicking() {
+    assert_eq!(parse_period("7é"), None);
+    assert_eq!(parse_period("é"), None);
+    assert_eq!(parse_period("garden🌱"), None);
+    assert_eq!(parse_period("99999999999w"), None);
+    assert_eq!(parse_period("9223372036854775807h"), None);
+    assert_eq!(parse_period("5200w"), Some(Duration::weeks(5200)));
+
+    assert!(period_start(at(1, 0, 0), Duration::weeks(2)).is_ok());
+    assert!(period_start(chrono::MIN_DATETIME, Duration::hours(1)).is_err());
+}
+
+#[test]
+pub fn test_record_and_query_runs() {
+    let history = History::open_in_memory().unwrap();
+    let run = WateringRun { started_at: at(1, 6, 0), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule };
+    history.record(&GardenEvent::WateringFinished(run.clone())).unwrap();
+    history.record(&GardenEvent::WateringFailed(WateringRun { started_at: at(1, 7, 0), ..run.clone() }, "pump".to_string())).unwrap();
+    history.record(&GardenEvent::WateringStopped(WateringRun { started_at: at(1, 8, 0), ..run })).unwrap();
+
+    let runs = history.runs(Some("tomatoes"), at(1, 0, 0)).unwrap();
+    assert_eq!(runs.len(), 3);
+    assert_eq!(runs[0].run.trigger, Trigger::Schedule);
+    assert_eq!(runs[0].outcome, RunOutcome::Ok);
+    assert_eq!(runs[1].outcome, RunOutcome::Failed("pump".to_string()));
+    assert_eq!(runs[2].outcome, RunOutcome::Stopped);
+    assert!(history.runs(Some("roses"), at(1, 0, 0)).unwrap().is_empty());
+}
+
+#[test]
+pub fn test_retention_downsamples_old_readings() {
+    let mut config = Config::new();
+    config.history_raw_retention_days = 1;
+    config.history_hourly_retention_days = 7;
+
+    let mut history = History::open_in_memory().unwrap();
+    history.record(&reading(at(1, 10, 0), 20.0)).unwrap();
+    history.record(&reading(at(1, 10, 30), 40.0)).unwrap();
+    history.record(&reading(at(4, 10, 0), 50.0)).unwrap();
+    history.record(&reading(at(4, 10, 30), 70.0)).unwrap();
+    history.record(&reading(at(10, 10, 0), 90.0)).unwrap();
+
+    history.apply_retention(&config, at(10, 12, 0)).unwrap();
+
+    let readings: Vec<_> = history.readings(None, at(1, 0, 0)).unwrap()
+        .into_iter()
+        .map(|r| (r.at, r.moisture))
+        .collect();
+
+    assert_eq!(readings, vec![
+        (at(1, 0, 0), 30.0),
+        (at(4, 10, 0), 60.0),
+        (at(10, 10, 0), 90.0)
+    ]);
+}
+
+#[test]
+pub fn test_subscribers_are_saved_and_removed() {
+    let history = History::open_in_memory().unwrap();
+    let quiet_hours = "22:00-07:00".parse().unwrap();
+
+    history.save_subscriber(ChatId(1), None, None).unwrap();
+    history.save_subscriber(ChatId(2), Some(quiet_hours), Some(NaiveTime::from_hms(19, 30, 0))).unwrap();
+    history.save_subscriber(ChatId(1), Some(quiet_hours), None).unwrap();
+    assert_eq!(history.subscribers().unwrap(), vec![
+        (ChatId(1), Some(quiet_hours), None),
+        (ChatId(2), Some(quiet_hours), Some(NaiveTime::from_hms(19, 30, 0)))
+    ]);
+
+    history.remove_subscriber(ChatId(1)).unwrap();
+    assert_eq!(history.subscribers().unwrap().len(), 1);
+}
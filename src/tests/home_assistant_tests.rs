@@ -0,0 +1,41 @@
+use crate::config::{Config};
+use crate::definitions::{Zone};
+use crate::home_assistant::{discovery_messages};
+
+use serde_json::Value;
+
+#[test]
+pub fn test_discovery_messages() {
+    let mut config = Config::new();
+    config.zones = vec![
+        Zone { name: "Tomatoes".to_string(), pump_pin: 4, moisture_sensor_pin: Some(17) },
+        Zone { name: "herbs".to_string(), pump_pin: 5, moisture_sensor_pin: None }
+    ];
+
+    let messages = discovery_messages(&config);
+    let topics: Vec<&str> = messages.iter().map(|message| message.topic.as_str()).collect();
+    assert_eq!(topics, vec![
+        "homeassistant/switch/smart_garden/tomatoes_pump/config",
+        "homeassistant/sensor/smart_garden/tomatoes_moisture/config",
+        "homeassistant/switch/smart_garden/herbs_pump/config",
+        "homeassistant/text/smart_garden/waterplants_schedule/config",
+        "homeassistant/text/smart_garden/readmoisturesensorsdata_schedule/config",
+        "homeassistant/switch/smart_garden/paused/config"
+    ]);
+    assert!(messages.iter().all(|message| message.retain));
+
+    let switch: Value = serde_json::from_str(&messages[0].payload).unwrap();
+    assert_eq!(switch["command_topic"], "smart_garden/zones/Tomatoes/pump/set");
+    assert_eq!(switch["state_topic"], "smart_garden/zones/Tomatoes/pump");
+    assert_eq!(switch["availability_topic"], "smart_garden/availability");
+    assert_eq!(switch["unique_id"], "smart_garden_tomatoes_pump");
+
+    let sensor: Value = serde_json::from_str(&messages[1].payload).unwrap();
+    assert_eq!(sensor["device_class"], "moisture");
+    assert_eq!(sensor["unit_of_measurement"], "%");
+    assert_eq!(sensor["device"], switch["device"]);
+
+    let schedule: Value = serde_json::from_str(&messages[3].payload).unwrap();
+    assert_eq!(schedule["command_topic"], "smart_garden/tasks/WaterPlants/set");
+    assert_eq!(schedule["state_topic"], "smart_garden/tasks/WaterPlants/cron");
+}
@@ -0,0 +1,244 @@
+use crate::config::{Config};
+use crate::definitions::{CatchUp, WorkerChannelMessage, SchedulerChannelMessage, PeriodicTask, PeriodicTaskType, TaskMetaData, Trigger, CorrelationId};
+use crate::error::{GardenError};
+use crate::http_api::{router};
+use crate::tests::{ControllerHarness};
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use job_scheduler::Uuid;
+use tower::ServiceExt;
+
+const TOKEN: &str = "secret";
+
+fn request(method: Method, uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn json_body(response: axum::response::Response) -> serde_json::Value {
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[tokio::test]
+pub async fn test_requests_without_token_are_rejected() {
+    let harness = ControllerHarness::new(Config::new());
+    let app = router(harness.controller, TOKEN.to_string());
+
+    let response = app.oneshot(Request::builder().uri("/status").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+pub async fn test_water_zone_sends_watering_request() {
+    let mut harness = ControllerHarness::new(Config::new());
+    let app = router(harness.controller, TOKEN.to_string());
+
+    let response = app.oneshot(request(Method::POST, "/zones/garden/water", r#"{"seconds": 30}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    match harness.worker_channel_receiver.try_recv().map(|traced| traced.message) {
+        Ok(WorkerChannelMessage::WaterPlants(request)) => {
+            assert_eq!(request.zone.as_deref(), Some("garden"));
+            assert_eq!(request.seconds, 30);
+            assert_eq!(request.trigger, Trigger::Api);
+        },
+        other => panic!("unexpected message {:?}", other)
+    }
+}
+
+#[tokio::test]
+pub async fn test_water_validates_zone_and_duration() {
+    let mut harness = ControllerHarness::new(Config::new());
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(request(Method::POST, "/zones/roses/water", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::POST, "/water", r#"{"seconds": 5}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(harness.worker_channel_receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn test_stop_sends_stop() {
+    let mut harness = ControllerHarness::new(Config::new());
+    let app = router(harness.controller, TOKEN.to_string());
+
+    let response = app.oneshot(request(Method::POST, "/stop", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert!(matches!(harness.worker_channel_receiver.try_recv().map(|traced| traced.message), Ok(WorkerChannelMessage::Stop)));
+}
+
+#[tokio::test]
+pub async fn test_stop_zone_only_stops_that_zone() {
+    let mut harness = ControllerHarness::new(Config::new());
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(request(Method::POST, "/zones/garden/stop", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert!(matches!(harness.worker_channel_receiver.try_recv().map(|traced| traced.message), Ok(WorkerChannelMessage::StopZone(zone)) if zone == "garden"));
+
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::POST, "/zones/roses/stop", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(harness.worker_channel_receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn test_list_tasks_asks_the_scheduler() {
+    let harness = ControllerHarness::new(Config::new());
+    let app = router(harness.controller, TOKEN.to_string());
+    let mut scheduler_channel_receiver = harness.scheduler_channel_receiver;
+
+    std::thread::spawn(move || {
+        if let Some(SchedulerChannelMessage::ListPeriodicTasks(reply)) = scheduler_channel_receiver.blocking_recv().map(|traced| traced.message) {
+            let task = PeriodicTask { task_type: PeriodicTaskType::WaterPlants, cron: "0 0 6 * * *".to_string(), disabled: false, catch_up: CatchUp::default() };
+            reply.send(vec![TaskMetaData { job_id: Uuid::nil(), task, next_runs: Vec::new(), last_run: None, paused: false }]).unwrap();
+        }
+    });
+
+    let response = app.oneshot(request(Method::GET, "/tasks", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let tasks = json_body(response).await;
+    assert_eq!(tasks[0]["task_type"], "WaterPlants");
+    assert_eq!(tasks[0]["cron"], "0 0 6 * * *");
+}
+
+#[tokio::test]
+pub async fn test_update_task_validates_schedule() {
+    let harness = ControllerHarness::new(Config::new());
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(request(Method::PUT, "/tasks/WaterPlants", r#"{"cron": "not a cron"}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let mut scheduler_channel_receiver = harness.scheduler_channel_receiver;
+    std::thread::spawn(move || {
+        if let Some(SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task, reply)) = scheduler_channel_receiver.blocking_recv().map(|traced| traced.message) {
+            assert_eq!(task.cron, "0 0 6 * * *");
+            reply.send(Ok(())).unwrap();
+        }
+    });
+
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::PUT, "/tasks/WaterPlants", r#"{"cron": "0 0 6 * * *"}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+pub async fn test_preview_schedule_shows_upcoming_runs() {
+    let harness = ControllerHarness::new(Config::new());
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(request(Method::GET, "/schedules/preview?schedule=every%2036h", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_body(response).await;
+    assert_eq!(body["description"], "every 36 hours");
+    assert_eq!(body["next_runs"].as_array().unwrap().len(), 5);
+
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::GET, "/schedules/preview?schedule=every%20now%20and%20then", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+pub async fn test_odd_periods_are_bad_requests() {
+    for period in ["7%C3%A9", "99999999999w"] {
+        let response = router(ControllerHarness::new(Config::new()).controller, TOKEN.to_string())
+            .oneshot(request(Method::GET, &format!("/history?period={}", period), "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+pub async fn test_scheduler_errors_map_to_status_codes() {
+    let harness = ControllerHarness::new(Config::new());
+    let mut scheduler_channel_receiver = harness.scheduler_channel_receiver;
+
+    let responder = std::thread::spawn(move || {
+        if let Some(SchedulerChannelMessage::RemovePeriodicTask(task_type, reply)) = scheduler_channel_receiver.blocking_recv().map(|traced| traced.message) {
+            reply.send(Err(GardenError::UnknownTask(task_type))).unwrap();
+        }
+        // dropping the receiver looks like a stopped scheduler
+    });
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(request(Method::DELETE, "/tasks/ReadMoistureSensorsData", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(response).await["error"], "ReadMoistureSensorsData is not scheduled");
+
+    responder.join().unwrap();
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::POST, "/tasks/reset", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+pub async fn test_dashboard_is_served_without_token() {
+    let harness = ControllerHarness::new(Config::new());
+    let app = router(harness.controller, TOKEN.to_string());
+
+    let response = app.oneshot(Request::builder().uri("/").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+pub async fn test_event_stream_accepts_token_in_query() {
+    let harness = ControllerHarness::new(Config::new());
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(Request::builder().uri("/events").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = router(harness.controller.clone(), TOKEN.to_string())
+        .oneshot(Request::builder().uri(format!("/events?access_token={}", TOKEN)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+    // the dashboard encodes the token with encodeURIComponent
+    let response = router(harness.controller.clone(), "a+b/c= d%".to_string())
+        .oneshot(Request::builder().uri("/events?access_token=a%2Bb%2Fc%3D%20d%25").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // every other route needs the header
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(Request::builder().uri(format!("/status?access_token={}", TOKEN)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+pub async fn test_metrics_reports_queue_depth() {
+    let harness = ControllerHarness::new(Config::new());
+    harness.controller.stop().unwrap();
+    harness.controller.stop().unwrap();
+
+    let response = router(harness.controller, TOKEN.to_string())
+        .oneshot(request(Method::GET, "/metrics", "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains(r#"smart_garden_channel_queue_depth{channel="worker"} 2"#));
+    assert!(text.contains(r#"smart_garden_channel_queue_depth{channel="scheduler"} 0"#));
+}
+
+#[test]
+pub fn test_traced_controller_tags_messages() {
+    let mut harness = ControllerHarness::new(Config::new());
+    let correlation_id = CorrelationId::new();
+
+    harness.controller.traced(correlation_id).stop().unwrap();
+    harness.controller.stop().unwrap();
+
+    assert_eq!(harness.worker_channel_receiver.try_recv().unwrap().correlation_id, correlation_id);
+    assert_ne!(harness.worker_channel_receiver.try_recv().unwrap().correlation_id, correlation_id);
+}
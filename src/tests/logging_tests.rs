@@ -0,0 +1,24 @@
+use crate::definitions::{CorrelationId};
+use crate::logging::{LogFormat, LogOutput};
+
+#[test]
+pub fn test_parse_log_settings() {
+    assert_eq!("json".parse(), Ok(LogFormat::Json));
+    assert_eq!("text".parse(), Ok(LogFormat::Text));
+    assert_eq!("yaml".parse::<LogFormat>(), Err(()));
+
+    assert_eq!("stdout".parse(), Ok(LogOutput::Stdout));
+    assert_eq!("journald".parse(), Ok(LogOutput::Journald));
+    assert_eq!("file:/var/log/smart_garden".parse(), Ok(LogOutput::RotatingFile { directory: "/var/log/smart_garden".to_string() }));
+    assert_eq!("file:".parse::<LogOutput>(), Err(()));
+    assert_eq!("syslog".parse::<LogOutput>(), Err(()));
+}
+
+#[test]
+pub fn test_correlation_ids_are_short_and_unique() {
+    let first = CorrelationId::new();
+    let second = CorrelationId::new();
+
+    assert_eq!(first.to_string().len(), 8);
+    assert_ne!(first, second);
+}
@@ -0,0 +1,34 @@
+use crate::definitions::{GardenEvent, MoistureReading, Trigger, WateringRun};
+use crate::metrics::{Metrics};
+
+use chrono::{Duration, Utc};
+
+#[test]
+pub fn test_metrics_from_events() {
+    let metrics = Metrics::new();
+    let run = |trigger| WateringRun { started_at: Utc::now() - Duration::seconds(10), zone: "tomatoes".to_string(), seconds: 30, trigger };
+
+    metrics.apply(&GardenEvent::WateringStarted(run(Trigger::Schedule)), Utc::now());
+    metrics.apply(&GardenEvent::WateringFinished(run(Trigger::Schedule)), Utc::now());
+    metrics.apply(&GardenEvent::WateringFinished(run(Trigger::Schedule)), Utc::now());
+    metrics.apply(&GardenEvent::WateringFailed(run(Trigger::Manual), "no water".to_string()), Utc::now());
+    metrics.apply(&GardenEvent::MoistureRead(MoistureReading { at: Utc::now(), zone: "tomatoes".to_string(), moisture: 42.5 }), Utc::now());
+    metrics.telegram_send_failed();
+    metrics.set_queue_depth("worker", 3);
+
+    let text = metrics.render();
+    assert!(text.contains(r#"smart_garden_runs_total{outcome="ok",trigger="schedule",zone="tomatoes"} 2"#));
+    assert!(text.contains(r#"smart_garden_runs_total{outcome="failed",trigger="manual",zone="tomatoes"} 1"#));
+    assert!(text.contains(r#"smart_garden_moisture_percent{zone="tomatoes"} 42.5"#));
+    assert!(text.contains(r#"smart_garden_channel_queue_depth{channel="worker"} 3"#));
+    assert!(text.contains("smart_garden_telegram_send_failures_total 1"));
+    assert!(text.contains("# TYPE smart_garden_pump_on_seconds_total counter"));
+
+    // two full runs plus the ten seconds the failed run was on
+    let pump_on_seconds: f64 = text.lines()
+        .find_map(|line| line.strip_prefix(r#"smart_garden_pump_on_seconds_total{zone="tomatoes"} "#))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((70.0..71.0).contains(&pump_on_seconds));
+}
@@ -1 +1,101 @@
-mod reply_text_tests;
\ No newline at end of file
+use crate::clock::{ManualClock, SharedClock, SystemClock};
+use crate::config::{Config};
+use crate::control::{Controller};
+use crate::definitions::{GardenEvent, NotifierChannelMessage, SchedulerChannelMessage, Traced, WorkerChannelMessage};
+use crate::simulation::{SimulatedGarden};
+use crate::status::{SharedStatus};
+use crate::worker::{Worker};
+
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{bounded, Receiver};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+
+#[cfg(feature = "telegram")]
+#[allow(clippy::redundant_static_lifetimes, clippy::redundant_slicing, clippy::bool_assert_comparison)]
+mod reply_text_tests;
+#[cfg(feature = "telegram")]
+mod notifier_tests;
+mod history_tests;
+mod chart_tests;
+mod export_tests;
+#[cfg(feature = "http")]
+mod http_api_tests;
+mod status_tests;
+#[cfg(feature = "mqtt")]
+mod mqtt_tests;
+#[cfg(feature = "mqtt")]
+mod home_assistant_tests;
+mod metrics_tests;
+#[cfg(feature = "ctl")]
+mod ctl_tests;
+mod logging_tests;
+mod scheduler_tests;
+mod error_tests;
+mod simulation_tests;
+mod garden_tests;
+mod schedule_tests;
+mod weather_tests;
+mod rain_tests;
+#[cfg(feature = "telegram")]
+mod fake_telegram;
+#[cfg(feature = "telegram")]
+mod telegram_bot_tests;
+
+/// A worker running on a simulated garden whose clock only moves when told to.
+pub struct WorkerHarness {
+    pub clock: Arc<ManualClock>,
+    pub worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+    pub event_receiver: broadcast::Receiver<GardenEvent>
+}
+
+impl WorkerHarness {
+    /// `with` adds what the test needs, e.g. a weather provider.
+    pub fn spawn(config: &'static Config, now: DateTime<Utc>, with: impl FnOnce(Worker) -> Worker) -> Self {
+        let clock = Arc::new(ManualClock::new(now));
+        let hardware = SimulatedGarden::new(config, clock.clone()).hardware();
+        let (worker_channel_sender, worker_channel_receiver) = mpsc::channel(8);
+        let (event_sender, event_receiver) = broadcast::channel(64);
+        let mut worker = with(Worker::new(config, clock.clone(), hardware, worker_channel_receiver, event_sender));
+        tokio::spawn(async move { worker.run().await });
+
+        WorkerHarness { clock, worker_channel_sender, event_receiver }
+    }
+
+    pub async fn send(&self, message: WorkerChannelMessage) {
+        self.worker_channel_sender.send(Traced::new(Default::default(), message)).await.unwrap();
+    }
+}
+
+/// A controller whose channels end in the test, nothing runs behind it unless the test starts it.
+// every surface's tests use a different part of it, and surfaces are features
+#[allow(dead_code)]
+pub struct ControllerHarness {
+    pub config: &'static Config,
+    pub controller: Controller,
+    pub status: SharedStatus,
+    pub worker_channel_sender: mpsc::Sender<Traced<WorkerChannelMessage>>,
+    pub worker_channel_receiver: mpsc::Receiver<Traced<WorkerChannelMessage>>,
+    pub scheduler_channel_receiver: mpsc::Receiver<Traced<SchedulerChannelMessage>>,
+    pub notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+    pub event_sender: broadcast::Sender<GardenEvent>
+}
+
+#[allow(dead_code)]
+impl ControllerHarness {
+    pub fn new(config: Config) -> Self {
+        ControllerHarness::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: Config, clock: SharedClock) -> Self {
+        let config: &'static Config = Box::leak(Box::new(config));
+        let (worker_channel_sender, worker_channel_receiver) = mpsc::channel(8);
+        let (scheduler_channel_sender, scheduler_channel_receiver) = mpsc::channel(8);
+        let (notifier_channel_sender, notifier_channel_receiver) = bounded(8);
+        let event_sender = broadcast::channel(8).0;
+        let status = SharedStatus::default();
+        let controller = Controller::new(config, clock, worker_channel_sender.clone(), scheduler_channel_sender, notifier_channel_sender, event_sender.clone(), status.clone());
+
+        ControllerHarness { config, controller, status, worker_channel_sender, worker_channel_receiver, scheduler_channel_receiver, notifier_channel_receiver, event_sender }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::config::{Config};
+use crate::definitions::{GardenEvent, MoistureReading, PeriodicTaskType, SchedulerChannelMessage, Trigger, WateringRun, WorkerChannelMessage};
+use crate::error::{GardenError};
+use crate::mqtt::{error_message, event_messages, parse_command, Mqtt, MqttCommand, MqttMessage};
+use crate::tests::{ControllerHarness};
+
+use chrono::{TimeZone, Utc};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::time::Duration;
+
+#[test]
+pub fn test_parse_water_commands() {
+    match parse_command("garden", "garden/water", "", 60) {
+        Some(MqttCommand::Water(request)) => {
+            assert_eq!(request.zone, None);
+            assert_eq!(request.seconds, 60);
+            assert_eq!(request.trigger, Trigger::Mqtt);
+        },
+        _ => panic!("expected a watering command"),
+    }
+
+    match parse_command("garden", "garden/zones/tomatoes/water", "30", 60) {
+        Some(MqttCommand::Water(request)) => {
+            assert_eq!(request.zone.as_deref(), Some("tomatoes"));
+            assert_eq!(request.seconds, 30);
+        },
+        _ => panic!("expected a watering command"),
+    }
+
+    match parse_command("garden", "garden/water", r#"{"zone": "herbs", "seconds": 20}"#, 60) {
+        Some(MqttCommand::Water(request)) => {
+            assert_eq!(request.zone.as_deref(), Some("herbs"));
+            assert_eq!(request.seconds, 20);
+        },
+        _ => panic!("expected a watering command"),
+    }
+
+    assert!(parse_command("garden", "garden/water", "a lot", 60).is_none());
+}
+
+#[test]
+pub fn test_parse_control_commands() {
+    assert!(matches!(parse_command("garden", "garden/stop", "", 60), Some(MqttCommand::Stop)));
+    assert!(matches!(parse_command("garden", "garden/zones/tomatoes/pump/set", "OFF", 60), Some(MqttCommand::StopZone(zone)) if zone == "tomatoes"));
+    match parse_command("garden", "garden/zones/tomatoes/pump/set", "ON", 60) {
+        Some(MqttCommand::Water(request)) => assert_eq!(request.zone.as_deref(), Some("tomatoes")),
+        _ => panic!("expected a watering command"),
+    }
+    assert!(matches!(parse_command("garden", "garden/pause", "ON", 60), Some(MqttCommand::Pause(true))));
+    assert!(matches!(parse_command("garden", "garden/pause", "off", 60), Some(MqttCommand::Pause(false))));
+    assert!(parse_command("garden", "garden/pause", "maybe", 60).is_none());
+
+    match parse_command("garden", "garden/tasks/WaterPlants/set", "0 0 7 * * *", 60) {
+        Some(MqttCommand::UpdateTask { task_type, cron, disabled }) => {
+            assert!(matches!(task_type, PeriodicTaskType::WaterPlants));
+            assert_eq!(cron, "0 0 7 * * *");
+            assert_eq!(disabled, None);
+        },
+        _ => panic!("expected a task update"),
+    }
+
+    match parse_command("garden", "garden/tasks/WaterPlants/set", r#"{"cron": "0 0 7 * * *", "disabled": true}"#, 60) {
+        Some(MqttCommand::UpdateTask { disabled, .. }) => assert_eq!(disabled, Some(true)),
+        _ => panic!("expected a task update"),
+    }
+
+    // an empty payload is not a switch or a schedule
+    assert!(parse_command("garden", "garden/pause", "", 60).is_none());
+    assert!(parse_command("garden", "garden/zones/tomatoes/pump/set", "", 60).is_none());
+    assert!(parse_command("garden", "garden/tasks/WaterPlants/set", "", 60).is_none());
+
+    assert!(parse_command("garden", "garden/tasks/Dance/set", "0 0 7 * * *", 60).is_none());
+    assert!(parse_command("garden", "other/stop", "", 60).is_none());
+    assert!(parse_command("garden", "garden/unknown", "", 60).is_none());
+}
+
+#[test]
+pub fn test_zone_switch_only_stops_its_own_zone() {
+    let ControllerHarness { controller, mut worker_channel_receiver, .. } = ControllerHarness::new(Config::new());
+
+    assert!(matches!(controller.stop_zone("roses"), Err(GardenError::UnknownZone(_))));
+    assert!(worker_channel_receiver.try_recv().is_err());
+
+    // the worker knows which zone it waters, the controller doesn't guess
+    controller.stop_zone("garden").unwrap();
+    assert!(matches!(worker_channel_receiver.try_recv().map(|traced| traced.message), Ok(WorkerChannelMessage::StopZone(zone)) if zone == "garden"));
+}
+
+#[test]
+pub fn test_event_messages() {
+    let run = WateringRun { started_at: Utc.ymd(2022, 6, 1).and_hms(7, 0, 0), zone: "tomatoes".to_string(), seconds: 30, trigger: Trigger::Schedule };
+
+    let started = event_messages("garden", &GardenEvent::WateringStarted(run.clone()));
+    assert_eq!(started[0], MqttMessage { topic: "garden/pump".to_string(), payload: "ON".to_string(), retain: true });
+    assert_eq!(started[1], MqttMessage { topic: "garden/zones/tomatoes/pump".to_string(), payload: "ON".to_string(), retain: true });
+    assert_eq!(started[2].topic, "garden/events");
+    assert!(!started[2].retain);
+    assert!(started[2].payload.contains("watering_started"));
+
+    let failed = event_messages("garden", &GardenEvent::WateringFailed(run, "no water".to_string()));
+    assert_eq!(failed[0].payload, "OFF");
+
+    let reading = MoistureReading { at: Utc.ymd(2022, 6, 1).and_hms(7, 0, 0), zone: "tomatoes".to_string(), moisture: 42.0 };
+    let read = event_messages("garden", &GardenEvent::MoistureRead(reading));
+    assert_eq!(read[0], MqttMessage { topic: "garden/zones/tomatoes/moisture".to_string(), payload: "42.0".to_string(), retain: true });
+
+    let error = error_message("garden", "garden/zones/roses/water", &GardenError::UnknownZone("roses".to_string()));
+    assert_eq!(error.topic, "garden/errors");
+    assert!(!error.retain);
+    assert_eq!(error.payload, r#"{"error":"roses is not a known zone","temporary":false,"topic":"garden/zones/roses/water"}"#);
+}
+
+/// Needs a broker, e.g. `mosquitto -p 1883`, run with `cargo test -- --ignored`.
+#[tokio::test]
+#[ignore]
+pub async fn test_round_trip_with_local_mosquitto() {
+    let ControllerHarness { config, controller, mut worker_channel_receiver, mut scheduler_channel_receiver, event_sender, .. } = ControllerHarness::new(Config::new());
+
+    tokio::spawn(Mqtt::run_with_options(controller, MqttOptions::new("smart_garden_test_garden", "localhost", 1883)));
+
+    let (client, mut event_loop) = AsyncClient::new(MqttOptions::new("smart_garden_test_client", "localhost", 1883), 8);
+    let (payload_sender, mut payload_receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(Event::Incoming(Packet::Publish(message))) = event_loop.poll().await {
+                payload_sender.send(String::from_utf8_lossy(&message.payload).to_string()).unwrap_or_default();
+            }
+        }
+    });
+
+    client.subscribe(format!("{}/pump", config.mqtt_topic_prefix), QoS::AtLeastOnce).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    client.publish(format!("{}/stop", config.mqtt_topic_prefix), QoS::AtLeastOnce, false, "").await.unwrap();
+    client.publish(format!("{}/pause", config.mqtt_topic_prefix), QoS::AtLeastOnce, false, "ON").await.unwrap();
+
+    let run = WateringRun { started_at: Utc::now(), zone: "garden".to_string(), seconds: 30, trigger: Trigger::Schedule };
+    event_sender.send(GardenEvent::WateringStarted(run)).unwrap();
+
+    // a retained state from an earlier run may arrive first
+    let pump_on = tokio::time::timeout(Duration::from_secs(5), async {
+        while payload_receiver.recv().await.unwrap() != "ON" {}
+    }).await;
+    assert!(pump_on.is_ok());
+
+    let stop = tokio::time::timeout(Duration::from_secs(5), worker_channel_receiver.recv()).await;
+    assert!(matches!(stop.map(|traced| traced.map(|traced| traced.message)), Ok(Some(WorkerChannelMessage::Stop))));
+    // connecting asks the scheduler for the tasks and whether it is paused first
+    let pause = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match scheduler_channel_receiver.recv().await.map(|traced| traced.message) {
+                Some(SchedulerChannelMessage::IsPaused(reply)) => reply.send(false).unwrap_or_default(),
+                Some(SchedulerChannelMessage::SetPaused(paused)) => return paused,
+                Some(_) => {},
+                None => panic!("the scheduler channel closed")
+            }
+        }
+    }).await;
+    assert_eq!(pause.ok(), Some(true));
+}
@@ -0,0 +1,129 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use crossbeam_channel::{bounded};
+use std::sync::Arc;
+use tokio::sync::{broadcast};
+use crate::clock::{ManualClock};
+use crate::config::{Config};
+use crate::definitions::{ChatId, GardenEvent, NotifierChannelMessage, QuietHours, Trigger, WateringRun, MoistureReading};
+use crate::history::{History};
+use crate::notifier::{Notifier, Subscriber};
+use crate::tests::fake_telegram::{FakeTelegram, CHAT_ID};
+
+fn at(hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd(2022, 6, 1).and_hms(hour, minute, 0)
+}
+
+fn run() -> WateringRun {
+    WateringRun { started_at: Utc::now(), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Schedule }
+}
+
+#[test]
+pub fn test_quiet_hours_wrapping_over_midnight() {
+    let quiet_hours: QuietHours = "22:00-07:00".parse().unwrap();
+    assert!(quiet_hours.contains(NaiveTime::from_hms(23, 30, 0)));
+    assert!(quiet_hours.contains(NaiveTime::from_hms(6, 59, 0)));
+    assert!(!quiet_hours.contains(NaiveTime::from_hms(7, 0, 0)));
+    assert!(!quiet_hours.contains(NaiveTime::from_hms(12, 0, 0)));
+}
+
+#[test]
+pub fn test_quiet_hours_rejects_invalid_input() {
+    assert!("22:00".parse::<QuietHours>().is_err());
+    assert!("22:00-22:00".parse::<QuietHours>().is_err());
+    assert!("25:00-07:00".parse::<QuietHours>().is_err());
+}
+
+#[test]
+pub fn test_routine_events_are_held_back_during_quiet_hours() {
+    let mut subscriber = Subscriber::new(ChatId(1));
+    subscriber.quiet_hours = Some("22:00-07:00".parse().unwrap());
+
+    assert_eq!(subscriber.route(&GardenEvent::WateringFinished(run()), at(23, 0)), None);
+    assert_eq!(subscriber.route(&GardenEvent::WateringFinished(run()), at(23, 10)), None);
+    assert_eq!(subscriber.flush(at(23, 20)), None);
+    assert_eq!(subscriber.flush(at(7, 0)), Some("While you were away:\nWatered tomatoes for 60 seconds (x2)".to_string()));
+    assert_eq!(subscriber.flush(at(7, 1)), None);
+}
+
+#[test]
+pub fn test_critical_events_bypass_quiet_hours_and_digest() {
+    let mut subscriber = Subscriber::new(ChatId(1));
+    subscriber.quiet_hours = Some("22:00-07:00".parse().unwrap());
+    subscriber.digest_at = Some(NaiveTime::from_hms(20, 0, 0));
+
+    let text = subscriber.route(&GardenEvent::WateringFailed(run(), "pump".to_string()), at(23, 0));
+    assert_eq!(text, Some("Alert! Watering tomatoes failed: pump".to_string()));
+}
+
+#[test]
+pub fn test_digest_is_sent_once_a_day() {
+    let mut subscriber = Subscriber::new(ChatId(1));
+    subscriber.digest_at = Some(NaiveTime::from_hms(20, 0, 0));
+
+    assert_eq!(subscriber.route(&GardenEvent::WateringFinished(run()), at(10, 0)), None);
+    assert_eq!(subscriber.flush(at(19, 59)), None);
+    assert!(subscriber.flush(at(20, 0)).unwrap().starts_with("Daily summary:"));
+
+    assert_eq!(subscriber.route(&GardenEvent::WateringFinished(run()), at(21, 0)), None);
+    assert_eq!(subscriber.flush(at(21, 1)), None);
+}
+
+#[test]
+pub fn test_telemetry_is_never_notified() {
+    let mut subscriber = Subscriber::new(ChatId(1));
+    let reading = MoistureReading { at: Utc::now(), zone: "tomatoes".to_string(), moisture: 42.0 };
+
+    assert_eq!(subscriber.route(&GardenEvent::MoistureRead(reading), at(12, 0)), None);
+    assert_eq!(subscriber.flush(at(12, 1)), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn test_quiet_hours_follow_the_garden_clock() {
+    let config = Config { timezone: chrono_tz::UTC, notifier_tick_ms: 10, ..Config::new() };
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(23, 0, 0)));
+    let (notifier_channel_sender, notifier_channel_receiver) = bounded(8);
+    let (event_sender, event_receiver) = broadcast::channel(8);
+    let mut telegram = FakeTelegram::start().await;
+    let mut notifier = Notifier::new(&config, clock.clone(), telegram.bot(), notifier_channel_receiver, event_receiver);
+
+    notifier_channel_sender.send(NotifierChannelMessage::SetQuietHours(ChatId(CHAT_ID), Some("22:00-07:00".parse().unwrap()))).unwrap();
+    notifier.tick().await;
+
+    // it is the middle of the night in the garden, whatever the host thinks
+    event_sender.send(GardenEvent::WateringFinished(run())).unwrap();
+    notifier.tick().await;
+
+    clock.set(Utc.ymd(2022, 6, 2).and_hms(7, 0, 0));
+    notifier.tick().await;
+    assert_eq!(telegram.next_sent().await.text(), "While you were away:\nWatered tomatoes for 60 seconds (x1)");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn test_subscriptions_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("smart_garden_test_{}.db", job_scheduler::Uuid::new_v4().to_simple())).to_string_lossy().to_string();
+    let config = Config { timezone: chrono_tz::UTC, notifier_tick_ms: 10, ..Config::new() };
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(23, 0, 0)));
+    let mut telegram = FakeTelegram::start().await;
+
+    let (notifier_channel_sender, notifier_channel_receiver) = bounded(8);
+    let mut notifier = Notifier::new(&config, clock.clone(), telegram.bot(), notifier_channel_receiver, broadcast::channel(8).1)
+        .with_history(History::open(&path).unwrap());
+    notifier_channel_sender.send(NotifierChannelMessage::Subscribe(ChatId(CHAT_ID))).unwrap();
+    notifier.tick().await;
+    notifier_channel_sender.send(NotifierChannelMessage::SetQuietHours(ChatId(CHAT_ID), Some("22:00-07:00".parse().unwrap()))).unwrap();
+    notifier.tick().await;
+    drop(notifier);
+
+    let (event_sender, event_receiver) = broadcast::channel(8);
+    let mut restarted = Notifier::new(&config, clock.clone(), telegram.bot(), bounded(8).1, event_receiver)
+        .with_history(History::open(&path).unwrap());
+
+    // still subscribed, and still quiet at night
+    event_sender.send(GardenEvent::WateringFinished(run())).unwrap();
+    restarted.tick().await;
+    clock.set(Utc.ymd(2022, 6, 2).and_hms(7, 0, 0));
+    restarted.tick().await;
+    assert_eq!(telegram.next_sent().await.text(), "While you were away:\nWatered tomatoes for 60 seconds (x1)");
+
+    std::fs::remove_file(&path).unwrap_or_default();
+}
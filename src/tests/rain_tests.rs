@@ -0,0 +1,110 @@
+use crate::clock::{ManualClock};
+use crate::config::{Config};
+use crate::definitions::{GardenEvent, Trigger, WateringRequest, WorkerChannelMessage};
+use crate::hardware::{DebouncedInput};
+use crate::rain::{RainSensor, RainState, SharedRain};
+use crate::simulation::{SimulatedGarden};
+use crate::status::{GardenStatus};
+use crate::tests::{WorkerHarness};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::Arc;
+use tokio::sync::{broadcast};
+
+const PIN: u64 = 27;
+
+fn now() -> DateTime<Utc> {
+    Utc.ymd(2022, 6, 1).and_hms(6, 0, 0)
+}
+
+fn config() -> &'static Config {
+    let mut config = Config::simulation();
+    config.rain_sensor_pin = Some(PIN);
+    config.timezone = chrono_tz::UTC;
+    Box::leak(Box::new(config))
+}
+
+#[test]
+pub fn test_input_changes_once_the_level_held() {
+    let config = config();
+    let clock = Arc::new(ManualClock::new(now()));
+    let garden = Arc::new(SimulatedGarden::new(config, clock));
+    let mut input = DebouncedInput::new(garden.clone(), PIN, Duration::seconds(5));
+
+    // the first level counts once it settled too
+    assert_eq!(input.poll(now()).unwrap(), None);
+    assert_eq!(input.poll(now() + Duration::seconds(5)).unwrap(), Some(true));
+
+    // a bounce shorter than the debounce time is ignored
+    garden.set_input(PIN, false);
+    assert_eq!(input.poll(now() + Duration::seconds(6)).unwrap(), None);
+    garden.set_input(PIN, true);
+    assert_eq!(input.poll(now() + Duration::seconds(7)).unwrap(), None);
+    garden.set_input(PIN, false);
+    assert_eq!(input.poll(now() + Duration::seconds(8)).unwrap(), None);
+    assert_eq!(input.poll(now() + Duration::seconds(12)).unwrap(), None);
+    assert_eq!(input.poll(now() + Duration::seconds(13)).unwrap(), Some(false));
+    assert_eq!(input.poll(now() + Duration::seconds(60)).unwrap(), None);
+}
+
+#[test]
+pub fn test_rain_suppresses_watering_until_the_delay_passed() {
+    let config = config();
+
+    assert_eq!(RainState::default().suppression(now(), config), None);
+    assert_eq!(RainState { wet: true, dried_at: None }.suppression(now(), config).as_deref(), Some("the rain sensor is wet"));
+
+    let dried = RainState { wet: false, dried_at: Some(now()) };
+    assert_eq!(dried.suppression(now() + Duration::hours(5), config).as_deref(), Some("the rain sensor was wet until 2022-06-01 06:00 UTC"));
+    assert_eq!(dried.suppression(now() + Duration::hours(6), config), None);
+}
+
+#[test]
+pub fn test_rain_sensor_follows_the_switch() {
+    let config = config();
+    let clock = Arc::new(ManualClock::new(now()));
+    let garden = Arc::new(SimulatedGarden::new(config, clock.clone()));
+    let rain = SharedRain::default();
+    let (event_sender, mut event_receiver) = broadcast::channel(8);
+    let mut rain_sensor = RainSensor::new(config, clock.clone(), garden.clone(), PIN, rain.clone(), event_sender);
+
+    // pulled up, so dry
+    rain_sensor.poll();
+    clock.advance(Duration::seconds(5));
+    rain_sensor.poll();
+    assert!(matches!(event_receiver.try_recv(), Ok(GardenEvent::RainSensorChanged(false))));
+
+    garden.set_input(PIN, false);
+    rain_sensor.poll();
+    clock.advance(Duration::seconds(5));
+    rain_sensor.poll();
+    assert!(matches!(event_receiver.try_recv(), Ok(GardenEvent::RainSensorChanged(true))));
+    assert!(rain.read().unwrap().wet);
+
+    garden.set_input(PIN, true);
+    rain_sensor.poll();
+    clock.advance(Duration::seconds(5));
+    rain_sensor.poll();
+    assert!(matches!(event_receiver.try_recv(), Ok(GardenEvent::RainSensorChanged(false))));
+    assert_eq!(rain.read().unwrap().dried_at, Some(now() + Duration::seconds(15)));
+}
+
+#[tokio::test]
+pub async fn test_scheduled_watering_waits_for_the_rain() {
+    let config = config();
+    let rain = Arc::new(std::sync::RwLock::new(RainState { wet: true, dried_at: None }));
+    let mut harness = WorkerHarness::spawn(config, now(), |worker| worker.with_rain(rain));
+
+    harness.send(WorkerChannelMessage::WaterPlants(WateringRequest { zone: None, seconds: 60, trigger: Trigger::Schedule })).await;
+    let skipped = harness.event_receiver.recv().await.unwrap();
+    assert!(matches!(&skipped, GardenEvent::WateringSkipped(skipped) if skipped.reason == "the rain sensor is wet"));
+
+    // watering by hand still works in the rain
+    harness.send(WorkerChannelMessage::WaterPlants(WateringRequest { zone: None, seconds: 60, trigger: Trigger::Manual })).await;
+    assert!(matches!(harness.event_receiver.recv().await.unwrap(), GardenEvent::WateringStarted(_)));
+
+    let mut status = GardenStatus::default();
+    status.apply(&GardenEvent::RainSensorChanged(true));
+    status.apply(&skipped);
+    assert_eq!(status.describe(config.timezone), "Pump is idle\nRain sensor is wet\nLast skipped run: 2022-06-01 06:00 UTC, the rain sensor is wet");
+}
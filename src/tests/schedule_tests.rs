@@ -0,0 +1,182 @@
+use crate::config::{Config};
+use crate::definitions::{CatchUp, PeriodicTask, PeriodicTaskType};
+use crate::error::{GardenError};
+use crate::schedule::{describe, TaskSchedule};
+use crate::sun::{sun_event, SunEvent};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+
+fn config(latitude: f64, longitude: f64) -> Config {
+    Config { latitude, longitude, ..Config::new() }
+}
+
+fn assert_close(actual: DateTime<Utc>, expected: DateTime<Utc>) {
+    assert!((actual - expected).num_minutes().abs() <= 2, "{} is not close to {}", actual, expected);
+}
+
+#[test]
+pub fn test_sunrise_and_sunset_in_greenwich() {
+    let midsummer = NaiveDate::from_ymd(2022, 6, 21);
+    assert_close(sun_event(midsummer, SunEvent::Sunrise, 51.48, 0.0).unwrap(), Utc.ymd(2022, 6, 21).and_hms(3, 43, 0));
+    assert_close(sun_event(midsummer, SunEvent::Sunset, 51.48, 0.0).unwrap(), Utc.ymd(2022, 6, 21).and_hms(20, 21, 0));
+
+    let midwinter = NaiveDate::from_ymd(2022, 12, 21);
+    assert_close(sun_event(midwinter, SunEvent::Sunrise, 51.48, 0.0).unwrap(), Utc.ymd(2022, 12, 21).and_hms(8, 4, 0));
+    assert_close(sun_event(midwinter, SunEvent::Sunset, 51.48, 0.0).unwrap(), Utc.ymd(2022, 12, 21).and_hms(15, 53, 0));
+}
+
+#[test]
+pub fn test_no_sunrise_during_polar_day_and_night() {
+    assert_eq!(sun_event(NaiveDate::from_ymd(2022, 6, 21), SunEvent::Sunrise, 69.65, 18.96), None);
+    assert_eq!(sun_event(NaiveDate::from_ymd(2022, 12, 21), SunEvent::Sunset, 69.65, 18.96), None);
+}
+
+#[test]
+pub fn test_parse_sun_schedules() {
+    assert!(matches!("sunrise - 30min".parse(), Ok(TaskSchedule::Sun { event: SunEvent::Sunrise, offset }) if offset == Duration::minutes(-30)));
+    assert!(matches!("Sunset + 1h".parse(), Ok(TaskSchedule::Sun { event: SunEvent::Sunset, offset }) if offset == Duration::hours(1)));
+    assert!(matches!("sunset+90s".parse(), Ok(TaskSchedule::Sun { event: SunEvent::Sunset, offset }) if offset == Duration::seconds(90)));
+    assert!(matches!("sunrise".parse(), Ok(TaskSchedule::Sun { offset, .. }) if offset == Duration::zero()));
+    assert!(matches!("0 0 6 * * *".parse(), Ok(TaskSchedule::Cron(_))));
+
+    for invalid in ["sunrise 30min", "sunrise - 30 days", "sunset + 12h", "sunrise - 99999999999h", "noon"] {
+        assert!(matches!(invalid.parse::<TaskSchedule>(), Err(GardenError::InvalidSchedule { .. })), "{} should not parse", invalid);
+    }
+}
+
+#[test]
+pub fn test_next_sun_run() {
+    let config = config(51.48, 0.0);
+    let schedule: TaskSchedule = "sunrise - 30min".parse().unwrap();
+
+    let next_run = schedule.after(&Utc.ymd(2022, 6, 21).and_hms(0, 0, 0), &config).unwrap();
+    assert_close(next_run, Utc.ymd(2022, 6, 21).and_hms(3, 13, 0));
+
+    // once today's has passed, it is tomorrow's sunrise
+    let next_run = schedule.after(&next_run, &config).unwrap();
+    assert_close(next_run, Utc.ymd(2022, 6, 22).and_hms(3, 13, 0));
+}
+
+#[test]
+pub fn test_next_sunset_after_the_midnight_sun() {
+    let config = config(69.65, 18.96);
+    let schedule: TaskSchedule = "sunset".parse().unwrap();
+
+    let next_run = schedule.after(&Utc.ymd(2022, 6, 21).and_hms(0, 0, 0), &config).unwrap();
+    assert_eq!(next_run.naive_utc().date().month(), 7);
+}
+
+fn runs(schedule: &str, from: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+    let config = Config { timezone: chrono_tz::Europe::London, ..Config::new() };
+    let schedule: TaskSchedule = schedule.parse().unwrap();
+    let mut runs = vec![schedule.after(&from, &config).unwrap()];
+    while runs.len() < count {
+        runs.push(schedule.after(runs.last().unwrap(), &config).unwrap());
+    }
+    runs
+}
+
+#[test]
+pub fn test_cron_schedules_follow_local_time() {
+    assert_eq!(runs("0 0 6 * * *", Utc.ymd(2022, 3, 26).and_hms(0, 0, 0), 3), vec![
+        Utc.ymd(2022, 3, 26).and_hms(6, 0, 0),
+        Utc.ymd(2022, 3, 27).and_hms(5, 0, 0),
+        Utc.ymd(2022, 3, 28).and_hms(5, 0, 0)
+    ]);
+}
+
+#[test]
+pub fn test_skipped_local_time_runs_when_the_clocks_jump() {
+    // 01:30 doesn't exist in London on 27 March 2022
+    assert_eq!(runs("0 30 1 * * *", Utc.ymd(2022, 3, 26).and_hms(12, 0, 0), 3), vec![
+        Utc.ymd(2022, 3, 27).and_hms(1, 0, 0),
+        Utc.ymd(2022, 3, 28).and_hms(0, 30, 0),
+        Utc.ymd(2022, 3, 29).and_hms(0, 30, 0)
+    ]);
+}
+
+#[test]
+pub fn test_repeated_local_time_runs_once() {
+    // 01:30 happens twice in London on 30 October 2022
+    assert_eq!(runs("0 30 1 * * *", Utc.ymd(2022, 10, 29).and_hms(12, 0, 0), 3), vec![
+        Utc.ymd(2022, 10, 30).and_hms(0, 30, 0),
+        Utc.ymd(2022, 10, 31).and_hms(1, 30, 0),
+        Utc.ymd(2022, 11, 1).and_hms(1, 30, 0)
+    ]);
+
+    let hourly = runs("0 0 * * * *", Utc.ymd(2022, 10, 29).and_hms(23, 30, 0), 4);
+    assert_eq!(hourly, vec![
+        Utc.ymd(2022, 10, 30).and_hms(0, 0, 0),
+        Utc.ymd(2022, 10, 30).and_hms(2, 0, 0),
+        Utc.ymd(2022, 10, 30).and_hms(3, 0, 0),
+        Utc.ymd(2022, 10, 30).and_hms(4, 0, 0)
+    ]);
+}
+
+#[test]
+pub fn test_describe_schedules_in_plain_english() {
+    assert_eq!(describe("0 0 6 * * *"), "every day at 06:00");
+    assert_eq!(describe("0 30 6,18 * * *"), "every day at 06:30 and 18:30");
+    assert_eq!(describe("0 0 7 * * Mon,Wed,Fri"), "every Monday, Wednesday and Friday at 07:00");
+    assert_eq!(describe("0 0 7 * * Mon-Fri"), "every weekday at 07:00");
+    assert_eq!(describe("0 0 * * * *"), "every hour at :00");
+    assert_eq!(describe("0 */10 * * * *"), "every 10 minutes");
+    assert_eq!(describe("0 15 */2 * * *"), "every 2 hours at :15");
+    assert_eq!(describe("sunrise - 30min"), "30 minutes before sunrise");
+    assert_eq!(describe("sunset + 1h"), "1 hour after sunset");
+    assert_eq!(describe("sunset"), "at sunset");
+    assert_eq!(describe("every 36h"), "every 36 hours");
+    assert_eq!(describe("every 2d"), "every 2 days");
+    assert_eq!(describe("2min on 28min off between 10:00 and 16:00"), "2 minutes on, 28 minutes off between 10:00 and 16:00");
+    assert_eq!(describe("30s on 10min off"), "30 seconds on, 10 minutes off all day");
+    // anything unusual is shown as written
+    assert_eq!(describe("0 0 6 1 * *"), "0 0 6 1 * *");
+}
+
+#[test]
+pub fn test_parse_interval_and_duty_cycle_schedules() {
+    assert!(matches!("every 36h".parse(), Ok(TaskSchedule::Interval { every }) if every == Duration::hours(36)));
+    assert!(matches!("Every 90 min".parse(), Ok(TaskSchedule::Interval { every }) if every == Duration::minutes(90)));
+    assert!(matches!("2min on 28min off".parse(), Ok(TaskSchedule::DutyCycle { on, off, window: None }) if on == Duration::minutes(2) && off == Duration::minutes(28)));
+    assert!(matches!("2min on 28min off between 10:00 and 16:00".parse(), Ok(TaskSchedule::DutyCycle { window: Some((start, end)), .. })
+        if start == NaiveTime::from_hms(10, 0, 0) && end == NaiveTime::from_hms(16, 0, 0)));
+
+    for invalid in ["every 0h", "every day", "every 99999h", "2min on off", "0min on 28min off", "2h on 1h off between 10:00 and 11:00", "2min on 28min off between 10 and 16"] {
+        assert!(matches!(invalid.parse::<TaskSchedule>(), Err(GardenError::InvalidSchedule { .. })), "{} should not parse", invalid);
+    }
+}
+
+#[test]
+pub fn test_duty_cycles_run_within_their_window() {
+    let london = runs("2min on 28min off between 10:00 and 11:00", Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), 3);
+    assert_eq!(london, vec![
+        Utc.ymd(2022, 1, 1).and_hms(10, 0, 0),
+        Utc.ymd(2022, 1, 1).and_hms(10, 30, 0),
+        Utc.ymd(2022, 1, 2).and_hms(10, 0, 0)
+    ]);
+
+    // a window over midnight belongs to the day it starts on
+    let overnight = runs("1h on 1h off between 23:00 and 02:00", Utc.ymd(2022, 1, 1).and_hms(12, 0, 0), 2);
+    assert_eq!(overnight, vec![Utc.ymd(2022, 1, 1).and_hms(23, 0, 0), Utc.ymd(2022, 1, 2).and_hms(1, 0, 0)]);
+}
+
+#[test]
+pub fn test_intervals_do_not_drift() {
+    let every_36h = runs("every 36h", Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), 3);
+    assert_eq!(every_36h[1] - every_36h[0], Duration::hours(36));
+    assert_eq!(every_36h[2] - every_36h[1], Duration::hours(36));
+
+    // the same runs, no matter when they are looked at
+    assert_eq!(runs("every 36h", every_36h[0] - Duration::hours(1), 3), every_36h);
+    assert_eq!(runs("every 6h", Utc.ymd(2022, 1, 1).and_hms(1, 0, 0), 2), vec![Utc.ymd(2022, 1, 1).and_hms(6, 0, 0), Utc.ymd(2022, 1, 1).and_hms(12, 0, 0)]);
+}
+
+#[test]
+pub fn test_duty_cycles_water_for_a_valid_duration() {
+    let task = |task_type, cron: &str| PeriodicTask { task_type, cron: cron.to_string(), disabled: false, catch_up: CatchUp::Skip };
+
+    assert_eq!(TaskSchedule::for_task(&task(PeriodicTaskType::WaterPlants, "2min on 28min off")).unwrap().run_seconds(), Some(120));
+    assert!(matches!(TaskSchedule::for_task(&task(PeriodicTaskType::WaterPlants, "2h on 1h off")), Err(GardenError::InvalidDuration(7200))));
+    // reading the sensors has no duration to check
+    assert!(TaskSchedule::for_task(&task(PeriodicTaskType::ReadMoistureSensorsData, "2h on 1h off")).is_ok());
+}
@@ -0,0 +1,271 @@
+use crate::clock::{ManualClock, SharedClock, SystemClock};
+use crate::config::{Config};
+use crate::definitions::{CatchUp, GardenEvent, PeriodicTask, PeriodicTaskType, RunOutcome, SchedulerChannelMessage, TaskRun, Traced, Trigger, WateringRun, WorkerChannelMessage};
+use crate::error::{GardenError};
+use crate::history::{History};
+use crate::scheduler::{Scheduler};
+
+use chrono::{TimeZone, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+fn task(task_type: PeriodicTaskType, cron: &str) -> PeriodicTask {
+    PeriodicTask { task_type, cron: cron.to_string(), disabled: false, catch_up: CatchUp::Skip }
+}
+
+struct Harness {
+    scheduler: Scheduler,
+    scheduler_channel_sender: mpsc::Sender<Traced<SchedulerChannelMessage>>,
+    worker_channel_receiver: mpsc::Receiver<Traced<WorkerChannelMessage>>,
+    event_sender: broadcast::Sender<GardenEvent>,
+    event_receiver: broadcast::Receiver<GardenEvent>
+}
+
+fn harness() -> Harness {
+    harness_with_clock(Arc::new(SystemClock))
+}
+
+fn harness_with_clock(clock: SharedClock) -> Harness {
+    // schedules in UTC keep the expected times readable, timezones have their own tests
+    let config: &'static Config = Box::leak(Box::new(Config { timezone: chrono_tz::UTC, ..Config::new() }));
+    let (scheduler_channel_sender, scheduler_channel_receiver) = mpsc::channel(8);
+    let (worker_channel_sender, worker_channel_receiver) = mpsc::channel(8);
+    let (event_sender, event_receiver) = broadcast::channel(8);
+
+    Harness {
+        scheduler: Scheduler::new(config, clock, scheduler_channel_receiver, worker_channel_sender, event_sender.clone()),
+        scheduler_channel_sender,
+        worker_channel_receiver,
+        event_sender,
+        event_receiver
+    }
+}
+
+#[test]
+pub fn test_scheduler_reports_task_errors() {
+    let mut scheduler = harness().scheduler;
+
+    assert!(matches!(scheduler.add_task(task(PeriodicTaskType::WaterPlants, "not a cron")), Err(GardenError::InvalidSchedule { .. })));
+    assert!(matches!(scheduler.update_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")), Err(GardenError::UnknownTask(_))));
+
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    // a bad schedule leaves the task in place, so it can still be updated
+    assert!(matches!(scheduler.update_task(task(PeriodicTaskType::WaterPlants, "0 0 25 * * *")), Err(GardenError::InvalidSchedule { .. })));
+    assert_eq!(scheduler.list_tasks()[0].task.cron, "0 0 6 * * *");
+    scheduler.update_task(task(PeriodicTaskType::WaterPlants, "0 0 7 * * *")).unwrap();
+
+    scheduler.remove_task(PeriodicTaskType::WaterPlants).unwrap();
+    assert!(matches!(scheduler.remove_task(PeriodicTaskType::WaterPlants), Err(GardenError::UnknownTask(_))));
+}
+
+#[test]
+pub fn test_invalid_config_tasks_are_skipped_with_a_failure_event() {
+    let Harness { mut scheduler, mut event_receiver, .. } = harness();
+
+    scheduler.add_tasks(&vec![task(PeriodicTaskType::WaterPlants, "every morning"), task(PeriodicTaskType::ReadMoistureSensorsData, "0 0 * * * *")]);
+
+    match event_receiver.try_recv() {
+        Ok(GardenEvent::Failure(reason)) => assert!(reason.starts_with("WaterPlants is not scheduled")),
+        other => panic!("unexpected event {:?}", other)
+    }
+    assert!(event_receiver.try_recv().is_err());
+    assert_eq!(scheduler.list_tasks().len(), 1);
+}
+
+#[test]
+pub fn test_missed_runs_are_dispatched_once() {
+    let Harness { mut scheduler, mut worker_channel_receiver, .. } = harness();
+    scheduler.add_task(task(PeriodicTaskType::ReadMoistureSensorsData, "0 0 * * * *")).unwrap();
+    scheduler.add_task(PeriodicTask { disabled: true, ..task(PeriodicTaskType::WaterPlants, "0 0 * * * *") }).unwrap();
+
+    scheduler.run_due_tasks(Utc::now());
+    assert!(worker_channel_receiver.try_recv().is_err());
+
+    // three hours late, the hourly task still only runs once and the disabled one not at all
+    scheduler.run_due_tasks(Utc::now() + chrono::Duration::hours(3));
+    match worker_channel_receiver.try_recv().map(|traced| traced.message) {
+        Ok(WorkerChannelMessage::PeriodicTask(task)) => assert!(matches!(task.task_type, PeriodicTaskType::ReadMoistureSensorsData)),
+        other => panic!("unexpected message {:?}", other)
+    }
+    assert!(worker_channel_receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn test_commands_do_not_wait_for_the_next_run() {
+    let Harness { mut scheduler, scheduler_channel_sender, .. } = harness();
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 1 1 *")).unwrap();
+    tokio::spawn(async move { scheduler.run().await });
+
+    let (reply, response) = oneshot::channel();
+    scheduler_channel_sender.send(Traced::new(Default::default(), SchedulerChannelMessage::ListPeriodicTasks(reply))).await.unwrap();
+
+    let tasks = tokio::time::timeout(Duration::from_millis(100), response).await.unwrap().unwrap();
+    assert_eq!(tasks.len(), 1);
+}
+
+#[tokio::test]
+pub async fn test_changes_are_announced_whoever_makes_them() {
+    let Harness { mut scheduler, scheduler_channel_sender, mut event_receiver, .. } = harness();
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    tokio::spawn(async move { scheduler.run().await });
+
+    let update = |cron: &str| {
+        let (reply, response) = oneshot::channel();
+        let message = SchedulerChannelMessage::UpdatePeriodicTaskScheduleCommand(task(PeriodicTaskType::WaterPlants, cron), reply);
+        (Traced::new(Default::default(), message), response)
+    };
+
+    let (message, response) = update("0 0 7 * * *");
+    scheduler_channel_sender.send(message).await.unwrap();
+    response.await.unwrap().unwrap();
+    assert!(matches!(event_receiver.try_recv(), Ok(GardenEvent::TasksChanged)));
+
+    // nothing changed, nothing to announce
+    let (message, response) = update("not a cron");
+    scheduler_channel_sender.send(message).await.unwrap();
+    assert!(response.await.unwrap().is_err());
+    assert!(event_receiver.try_recv().is_err());
+
+    scheduler_channel_sender.send(Traced::new(Default::default(), SchedulerChannelMessage::SetPaused(true))).await.unwrap();
+    let announced = tokio::time::timeout(Duration::from_millis(100), event_receiver.recv()).await.unwrap();
+    assert!(matches!(announced, Ok(GardenEvent::TasksChanged)));
+}
+
+#[tokio::test]
+pub async fn test_a_week_of_schedules_with_a_manual_clock() {
+    let start = Utc.ymd(2022, 6, 1).and_hms(0, 0, 0);
+    let clock = Arc::new(ManualClock::new(start));
+    let Harness { mut scheduler, mut worker_channel_receiver, scheduler_channel_sender: _scheduler_channel_sender, .. } = harness_with_clock(clock.clone());
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    tokio::spawn(async move { scheduler.run().await });
+
+    // a week of mornings in a few milliseconds
+    for day in 0..7 {
+        clock.set(start + chrono::Duration::days(day) + chrono::Duration::hours(5));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(worker_channel_receiver.try_recv().is_err(), "ran before 06:00 on day {}", day);
+
+        clock.advance(chrono::Duration::hours(1));
+        let dispatched = tokio::time::timeout(Duration::from_secs(1), worker_channel_receiver.recv()).await.unwrap();
+        assert!(matches!(dispatched.map(|traced| traced.message), Some(WorkerChannelMessage::PeriodicTask(_))));
+    }
+}
+
+#[test]
+pub fn test_sun_schedules_run_at_their_computed_time() {
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 21).and_hms(0, 0, 0)));
+    let Harness { mut scheduler, mut worker_channel_receiver, .. } = harness_with_clock(clock);
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "sunrise - 30min")).unwrap();
+
+    let next_run = scheduler.list_tasks()[0].next_run().unwrap();
+    assert_eq!(next_run.naive_utc().date(), Utc.ymd(2022, 6, 21).naive_utc());
+
+    scheduler.run_due_tasks(next_run - chrono::Duration::seconds(1));
+    assert!(worker_channel_receiver.try_recv().is_err());
+    scheduler.run_due_tasks(next_run);
+    assert!(worker_channel_receiver.try_recv().is_ok());
+    assert!(scheduler.list_tasks()[0].next_run().unwrap() > next_run + chrono::Duration::hours(23));
+}
+
+#[tokio::test]
+pub async fn test_listed_tasks_show_upcoming_runs_and_how_the_last_one_went() {
+    let start = Utc.ymd(2022, 6, 1).and_hms(0, 0, 0);
+    let clock = Arc::new(ManualClock::new(start));
+    let Harness { mut scheduler, scheduler_channel_sender, mut worker_channel_receiver, event_sender, .. } = harness_with_clock(clock.clone());
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    scheduler.add_task(PeriodicTask { disabled: true, ..task(PeriodicTaskType::ReadMoistureSensorsData, "* * * * * *") }).unwrap();
+
+    let tasks = scheduler.list_tasks();
+    assert_eq!(tasks.iter().map(|meta| meta.task.task_type.to_string()).collect::<Vec<_>>(), vec!["ReadMoistureSensorsData", "WaterPlants"]);
+    assert_eq!(tasks[1].next_runs, vec![start + chrono::Duration::hours(6), start + chrono::Duration::hours(30), start + chrono::Duration::hours(54)]);
+    assert!(tasks[1].last_run.is_none());
+    // a disabled task doesn't run every second
+    assert!(tasks[0].next_runs.is_empty());
+    assert!(tasks[0].describe(chrono_tz::UTC).contains("\nNext runs: none while disabled\n"));
+    tokio::spawn(async move { scheduler.run().await });
+
+    clock.set(start + chrono::Duration::hours(6));
+    tokio::time::timeout(Duration::from_secs(1), worker_channel_receiver.recv()).await.unwrap().unwrap();
+    let run = WateringRun { started_at: start + chrono::Duration::hours(6), zone: "garden".to_string(), seconds: 60, trigger: Trigger::Schedule };
+    event_sender.send(GardenEvent::WateringFailed(run.clone(), "pump is stuck".to_string())).unwrap();
+    // a later zone going well doesn't hide the failure
+    event_sender.send(GardenEvent::WateringFinished(run)).unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let (reply, response) = oneshot::channel();
+    scheduler_channel_sender.send(Traced::new(Default::default(), SchedulerChannelMessage::ListPeriodicTasks(reply))).await.unwrap();
+    let tasks = response.await.unwrap();
+    let last_run = tasks[1].last_run.clone().unwrap();
+    assert_eq!(last_run.at, start + chrono::Duration::hours(6));
+    assert_eq!(last_run.outcome, Some(RunOutcome::Failed("pump is stuck".to_string())));
+    assert_eq!(tasks[1].next_run(), Some(start + chrono::Duration::hours(30)));
+    assert!(tasks[0].last_run.is_none());
+
+    scheduler_channel_sender.send(Traced::new(Default::default(), SchedulerChannelMessage::SetPaused(true))).await.unwrap();
+    let (reply, response) = oneshot::channel();
+    scheduler_channel_sender.send(Traced::new(Default::default(), SchedulerChannelMessage::ListPeriodicTasks(reply))).await.unwrap();
+    let tasks = response.await.unwrap();
+    assert!(tasks[1].paused && tasks[1].next_runs.is_empty());
+    assert!(tasks[1].describe(chrono_tz::UTC).contains("\nNext runs: none while the scheduler is paused\n"));
+}
+
+/// How many runs a garden that last watered at 06:00 on 1 June catches up when it comes back at 09:00 three days later.
+fn caught_up_runs(catch_up: CatchUp) -> usize {
+    let Harness { scheduler, mut worker_channel_receiver, .. } = harness();
+    let history = History::open_in_memory().unwrap();
+    history.save_task_run(&PeriodicTaskType::WaterPlants, &TaskRun { at: Utc.ymd(2022, 6, 1).and_hms(6, 0, 0), outcome: Some(RunOutcome::Ok) }).unwrap();
+    let mut scheduler = scheduler.with_saved_runs(history.task_runs().unwrap());
+
+    scheduler.add_task(PeriodicTask { catch_up, ..task(PeriodicTaskType::WaterPlants, "0 0 6 * * *") }).unwrap();
+    scheduler.catch_up(Utc.ymd(2022, 6, 4).and_hms(9, 0, 0));
+
+    std::iter::from_fn(|| worker_channel_receiver.try_recv().ok()).count()
+}
+
+#[test]
+pub fn test_missed_runs_are_caught_up_as_the_policy_says() {
+    assert_eq!(caught_up_runs(CatchUp::Skip), 0);
+    assert_eq!(caught_up_runs(CatchUp::Once { within_hours: 12 }), 1);
+    // the latest missed run was three hours ago
+    assert_eq!(caught_up_runs(CatchUp::Once { within_hours: 2 }), 0);
+    assert_eq!(caught_up_runs(CatchUp::All), 3);
+}
+
+#[test]
+pub fn test_last_runs_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("smart_garden_test_{}.db", job_scheduler::Uuid::new_v4().to_simple())).to_string_lossy().to_string();
+
+    let Harness { mut scheduler, mut event_receiver, .. } = harness();
+    let history = History::open(&path).unwrap();
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    assert!(scheduler.list_tasks()[0].last_run.is_none());
+    let next_run = scheduler.list_tasks()[0].next_run().unwrap();
+    scheduler.run_due_tasks(next_run);
+
+    // what the history thread does with the scheduler's events
+    while let Ok(event) = event_receiver.try_recv() {
+        history.record(&event).unwrap();
+    }
+
+    let mut restarted = harness().scheduler.with_saved_runs(History::open(&path).unwrap().task_runs().unwrap());
+    restarted.add_task(task(PeriodicTaskType::WaterPlants, "0 0 6 * * *")).unwrap();
+    assert_eq!(restarted.list_tasks()[0].last_run.clone().map(|last_run| last_run.at), Some(next_run));
+
+    std::fs::remove_file(&path).unwrap_or_default();
+}
+
+#[test]
+pub fn test_duty_cycles_water_for_the_on_part() {
+    let Harness { mut scheduler, mut worker_channel_receiver, .. } = harness();
+    scheduler.add_task(task(PeriodicTaskType::WaterPlants, "2min on 28min off")).unwrap();
+
+    scheduler.run_due_tasks(scheduler.list_tasks()[0].next_run().unwrap());
+    match worker_channel_receiver.try_recv().map(|traced| traced.message) {
+        Ok(WorkerChannelMessage::WaterPlants(request)) => {
+            assert_eq!(request.seconds, 120);
+            assert_eq!(request.trigger, Trigger::Schedule);
+        },
+        other => panic!("unexpected message {:?}", other)
+    }
+}
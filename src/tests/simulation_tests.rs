@@ -0,0 +1,150 @@
+use crate::clock::{AcceleratedClock, Clock, ManualClock};
+use crate::config::{Config};
+use crate::definitions::{CatchUp, GardenEvent, PeriodicTask, PeriodicTaskType, Traced, Trigger, WateringRequest, WorkerChannelMessage, Zone};
+use crate::error::{GardenError};
+use crate::hardware::{Actuator, Hardware};
+use crate::simulation::{SimulatedGarden};
+use crate::tests::{WorkerHarness};
+use crate::worker::{Worker};
+
+use chrono::{TimeZone, Utc};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+
+fn config() -> &'static Config {
+    Box::leak(Box::new(Config::simulation()))
+}
+
+#[test]
+pub fn test_soil_dries_out_and_soaks_up_water() {
+    let config = config();
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(0, 0, 0)));
+    let garden = SimulatedGarden::new(config, clock.clone());
+    let zone = &config.zones[0];
+
+    assert_eq!(garden.moisture(&zone.name).unwrap(), 60.0);
+
+    clock.advance(chrono::Duration::hours(10));
+    assert_eq!(garden.moisture(&zone.name).unwrap(), 45.0);
+
+    garden.set_pump(zone, true).unwrap();
+    clock.advance(chrono::Duration::seconds(20));
+    assert_eq!(garden.moisture(&zone.name).unwrap(), 55.0);
+
+    // the soil can't get wetter than wet
+    clock.advance(chrono::Duration::minutes(10));
+    assert_eq!(garden.moisture(&zone.name).unwrap(), 100.0);
+
+    garden.set_pump(zone, false).unwrap();
+    clock.advance(chrono::Duration::days(30));
+    assert_eq!(garden.moisture(&zone.name).unwrap(), 0.0);
+
+    assert!(matches!(garden.moisture("lawn"), Err(GardenError::UnknownZone(_))));
+}
+
+#[tokio::test]
+pub async fn test_worker_waters_the_simulated_garden() {
+    let config = config();
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(0, 0, 0)));
+    let garden = Arc::new(SimulatedGarden::new(config, clock.clone()));
+    let hardware = Hardware { actuator: garden.clone(), moisture_sensor: garden.clone(), digital_input: garden.clone() };
+    let (worker_channel_sender, worker_channel_receiver) = mpsc::channel(8);
+    let (event_sender, mut event_receiver) = broadcast::channel(8);
+    tokio::spawn(Worker::run_async(config, clock.clone(), hardware, worker_channel_receiver, event_sender));
+
+    let request = WateringRequest { zone: None, seconds: 30, trigger: Trigger::Manual };
+    worker_channel_sender.send(Traced::new(Default::default(), WorkerChannelMessage::WaterPlants(request))).await.unwrap();
+
+    let started = tokio::time::timeout(Duration::from_secs(1), event_receiver.recv()).await.unwrap().unwrap();
+    assert!(matches!(started, GardenEvent::WateringStarted(_)));
+
+    // let the worker turn the pump on before time moves
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    clock.advance(chrono::Duration::seconds(30));
+    let finished = tokio::time::timeout(Duration::from_secs(1), event_receiver.recv()).await.unwrap().unwrap();
+    assert!(matches!(finished, GardenEvent::WateringFinished(_)));
+    assert_eq!(garden.moisture(&config.zones[0].name).unwrap(), 75.0);
+}
+
+#[tokio::test]
+pub async fn test_worker_does_not_pile_up_commands_during_a_cycle() {
+    let config = config();
+    let WorkerHarness { clock, worker_channel_sender, mut event_receiver } = WorkerHarness::spawn(config, Utc.ymd(2022, 6, 1).and_hms(0, 0, 0), |worker| worker);
+
+    let water = |trigger| WorkerChannelMessage::WaterPlants(WateringRequest { zone: None, seconds: 30, trigger });
+    worker_channel_sender.send(Traced::new(Default::default(), water(Trigger::Manual))).await.unwrap();
+    let started = tokio::time::timeout(Duration::from_secs(1), event_receiver.recv()).await.unwrap().unwrap();
+    assert!(matches!(started, GardenEvent::WateringStarted(_)));
+
+    // a schedule firing every few seconds and someone pressing the button over and over
+    let task = PeriodicTask { task_type: PeriodicTaskType::WaterPlants, cron: "1/10 * * * * *".to_string(), disabled: false, catch_up: CatchUp::Skip };
+    for _ in 0..20 {
+        worker_channel_sender.send(Traced::new(Default::default(), WorkerChannelMessage::PeriodicTask(task.clone()))).await.unwrap();
+        worker_channel_sender.send(Traced::new(Default::default(), water(Trigger::Schedule))).await.unwrap();
+        worker_channel_sender.send(Traced::new(Default::default(), water(Trigger::Manual))).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let mut runs = 0;
+    loop {
+        // let the worker start the cycle before time moves past its end
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        clock.advance(chrono::Duration::minutes(10));
+        match tokio::time::timeout(Duration::from_millis(100), event_receiver.recv()).await {
+            Ok(Ok(GardenEvent::WateringStarted(_))) => runs += 1,
+            Ok(Ok(_)) => {},
+            _ => break
+        }
+    }
+    // one scheduled run, then manual runs until the backlog was full
+    assert_eq!(runs, config.messages_in_flight_limit);
+}
+
+#[tokio::test]
+pub async fn test_accelerated_clock_sleeps_in_a_fraction_of_the_time() {
+    let clock = AcceleratedClock::new(36000.0);
+    let deadline = clock.now() + chrono::Duration::hours(1);
+    let started = Instant::now();
+
+    clock.sleep_until(deadline).await;
+
+    assert!(clock.now() >= deadline);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+pub async fn test_stopping_a_zone_leaves_the_others_and_the_queue_alone() {
+    let mut config = Config::simulation();
+    config.zones = vec![
+        Zone { name: "tomatoes".to_string(), pump_pin: 4, moisture_sensor_pin: None },
+        Zone { name: "herbs".to_string(), pump_pin: 5, moisture_sensor_pin: None }
+    ];
+    let config: &'static Config = Box::leak(Box::new(config));
+    let WorkerHarness { clock, worker_channel_sender, mut event_receiver } = WorkerHarness::spawn(config, Utc.ymd(2022, 6, 1).and_hms(0, 0, 0), |worker| worker);
+    let send = |message| worker_channel_sender.send(Traced::new(Default::default(), message));
+    let water = |zone: Option<&str>| WorkerChannelMessage::WaterPlants(WateringRequest { zone: zone.map(str::to_string), seconds: 30, trigger: Trigger::Manual });
+
+    // both zones, then tomatoes once more
+    send(water(None)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    send(water(Some("tomatoes"))).await.unwrap();
+    // the herbs aren't being watered, so this stops nothing
+    send(WorkerChannelMessage::StopZone("herbs".to_string())).await.unwrap();
+    send(WorkerChannelMessage::StopZone("tomatoes".to_string())).await.unwrap();
+
+    let mut runs = Vec::new();
+    while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(100), event_receiver.recv()).await {
+        match event {
+            GardenEvent::WateringStarted(run) => runs.push(format!("{} started", run.zone)),
+            GardenEvent::WateringStopped(run) => runs.push(format!("{} stopped", run.zone)),
+            GardenEvent::WateringFinished(run) => runs.push(format!("{} finished", run.zone)),
+            _ => {}
+        }
+        // let the worker start the next cycle before time moves past its end
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        clock.advance(chrono::Duration::seconds(30));
+    }
+
+    assert_eq!(runs, vec!["tomatoes started", "tomatoes stopped", "herbs started", "herbs finished", "tomatoes started", "tomatoes finished"]);
+}
@@ -0,0 +1,16 @@
+use chrono::Utc;
+use crate::definitions::{GardenEvent, RunOutcome, Trigger, WateringRun};
+use crate::status::{GardenStatus};
+
+#[test]
+pub fn test_status_follows_watering_runs() {
+    let run = WateringRun { started_at: Utc::now(), zone: "tomatoes".to_string(), seconds: 60, trigger: Trigger::Manual };
+    let mut status = GardenStatus::default();
+
+    status.apply(&GardenEvent::WateringStarted(run.clone()));
+    assert_eq!(status.current_run.as_ref().map(|r| r.zone.as_str()), Some("tomatoes"));
+
+    status.apply(&GardenEvent::WateringStopped(run));
+    assert!(status.current_run.is_none());
+    assert_eq!(status.last_runs["tomatoes"].outcome, RunOutcome::Stopped);
+}
@@ -0,0 +1,229 @@
+use crate::clock::{ManualClock};
+use crate::config::{Config};
+use crate::definitions::{ChatId, GardenEvent, MoistureReading, NotifierChannelMessage, Trigger, WateringRun, Zone};
+use crate::error::{GardenResult};
+use crate::hardware::{Actuator, DigitalInput, Hardware, MoistureSensor};
+use crate::history::{History};
+use crate::scheduler::{Scheduler};
+use crate::tests::{ControllerHarness};
+use crate::telegram_bot::{TelegramBot};
+use crate::tests::fake_telegram::{FakeTelegram, CHAT_ID};
+use crate::worker::{Worker};
+
+use chrono::{NaiveTime, TimeZone, Utc};
+use crossbeam_channel::{Receiver};
+use job_scheduler::Uuid;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast};
+
+const CONFIRMATION: &str = "Beep Boop Bop...";
+
+/// Remembers every time a pump was turned on or off.
+#[derive(Default)]
+struct RecordingPumps {
+    switched: Mutex<Vec<(String, bool)>>
+}
+
+impl RecordingPumps {
+    fn switched(&self) -> Vec<(String, bool)> {
+        self.switched.lock().unwrap().clone()
+    }
+}
+
+impl Actuator for RecordingPumps {
+    fn set_pump(&self, zone: &Zone, on: bool) -> GardenResult<()> {
+        self.switched.lock().unwrap().push((zone.name.clone(), on));
+        Ok(())
+    }
+}
+
+impl MoistureSensor for RecordingPumps {
+    fn read_moisture(&self, _zone: &Zone) -> GardenResult<f64> {
+        Ok(50.0)
+    }
+}
+
+impl DigitalInput for RecordingPumps {
+    fn read_input(&self, _pin: u64) -> GardenResult<bool> {
+        Ok(true)
+    }
+}
+
+struct Harness {
+    config: &'static Config,
+    telegram: FakeTelegram,
+    pumps: Arc<RecordingPumps>,
+    notifier_channel_receiver: Receiver<NotifierChannelMessage>,
+    event_receiver: broadcast::Receiver<GardenEvent>,
+    shutdowns: Arc<AtomicUsize>
+}
+
+/// The bot with a real scheduler and worker behind it, only the pumps and Telegram are fake.
+async fn harness() -> Harness {
+    let mut config = Config::new();
+    config.history_db_path = std::env::temp_dir().join(format!("smart_garden_test_{}.db", Uuid::new_v4().to_simple())).to_string_lossy().to_string();
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2022, 6, 1).and_hms(0, 0, 0)));
+    let pumps = Arc::new(RecordingPumps::default());
+    let hardware = Hardware { actuator: pumps.clone(), moisture_sensor: pumps.clone(), digital_input: pumps.clone() };
+
+    let ControllerHarness { config, controller, worker_channel_sender, worker_channel_receiver, scheduler_channel_receiver, notifier_channel_receiver, event_sender, .. } =
+        ControllerHarness::with_clock(config, clock.clone());
+    let event_receiver = event_sender.subscribe();
+    tokio::spawn(Scheduler::run_async(config, clock.clone(), scheduler_channel_receiver, worker_channel_sender, event_sender.clone()));
+    tokio::spawn(Worker::run_async(config, clock.clone(), hardware, worker_channel_receiver, event_sender));
+
+    let shutdowns = Arc::new(AtomicUsize::new(0));
+    let shutdowns_ = shutdowns.clone();
+    let controller = controller.with_shutdown(move || { shutdowns_.fetch_add(1, Ordering::SeqCst); });
+
+    let telegram = FakeTelegram::start().await;
+    tokio::spawn(TelegramBot::run_with_bot(telegram.bot(), controller));
+
+    Harness { config, telegram, pumps, notifier_channel_receiver, event_receiver, shutdowns }
+}
+
+async fn next_event(event_receiver: &mut broadcast::Receiver<GardenEvent>) -> GardenEvent {
+    tokio::time::timeout(Duration::from_secs(5), event_receiver.recv()).await.unwrap().unwrap()
+}
+
+/// The worker announces a run before it switches the pump.
+async fn eventually(check: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if check() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("gave up waiting");
+}
+
+/// Tasks are listed in one message, separated by blank lines.
+async fn list_tasks(telegram: &mut FakeTelegram) -> Vec<String> {
+    telegram.ask("/listtasks").await.split("\n\n").map(str::to_string).collect()
+}
+
+#[tokio::test]
+pub async fn test_help_and_status() {
+    let mut harness = harness().await;
+
+    let help = harness.telegram.ask("/help").await;
+    assert!(help.starts_with("These commands are supported:"));
+    assert!(help.contains("/waterplants"));
+
+    assert_eq!(harness.telegram.ask("/status").await, "Pump is idle");
+}
+
+#[tokio::test]
+pub async fn test_water_plants_and_stop_switch_the_pump() {
+    let mut harness = harness().await;
+
+    assert!(harness.telegram.ask("/waterplants garden 30").await.starts_with(CONFIRMATION));
+    match next_event(&mut harness.event_receiver).await {
+        GardenEvent::WateringStarted(run) => {
+            assert_eq!(run.zone, "garden");
+            assert_eq!(run.seconds, 30);
+            assert_eq!(run.trigger, Trigger::Manual);
+        },
+        other => panic!("unexpected event {:?}", other)
+    }
+    eventually(|| harness.pumps.switched() == vec![("garden".to_string(), true)]).await;
+
+    assert!(harness.telegram.ask("/stop").await.starts_with(CONFIRMATION));
+    assert!(matches!(next_event(&mut harness.event_receiver).await, GardenEvent::WateringStopped(_)));
+    assert_eq!(harness.pumps.switched(), vec![("garden".to_string(), true), ("garden".to_string(), false)]);
+
+    assert_eq!(harness.telegram.ask("/waterplants lawn").await, "Wrong input... lawn is not a known zone");
+    assert!(harness.telegram.ask("/waterplants 5").await.starts_with("Wrong input... "));
+    assert_eq!(harness.pumps.switched().len(), 2);
+}
+
+#[tokio::test]
+pub async fn test_task_commands_go_through_the_scheduler() {
+    let mut harness = harness().await;
+
+    let tasks = list_tasks(&mut harness.telegram).await;
+    assert_eq!(tasks.len(), harness.config.periodic_tasks.len());
+    assert!(tasks[0].starts_with("ReadMoistureSensorsData: every second, disabled\n"));
+    assert!(tasks[1].starts_with("WaterPlants: 1/10 * * * * *\n"));
+    assert!(tasks[1].contains("\nLast run: not yet"));
+
+    assert!(harness.telegram.ask("/updatetask WaterPlants | 0 0 7 * * *").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/updatetask WaterPlants 0 0 7 * * *").await.starts_with("Wrong input... Couldn't parse"));
+    assert!(harness.telegram.ask("/updatetask WaterPlants | every day").await.starts_with("Wrong input... "));
+    assert!(harness.telegram.ask("/removetask ReadMoistureSensorsData").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/removetask Weeding").await.starts_with("Wrong input... Weeding is not a valid task"));
+
+    let tasks = list_tasks(&mut harness.telegram).await;
+    assert_eq!(tasks.len(), 1);
+    assert!(tasks[0].starts_with("WaterPlants: every day at 07:00"));
+    // the harness clock stands at midnight UTC on 1 June, the bot shows British Summer Time
+    assert!(tasks[0].contains("\nNext runs: 2022-06-01 07:00 BST, 2022-06-02 07:00 BST, 2022-06-03 07:00 BST\n"));
+
+    let preview = harness.telegram.ask("/previewschedule 2min on 28min off between 10:00 and 16:00").await;
+    assert!(preview.starts_with("2 minutes on, 28 minutes off between 10:00 and 16:00\nNext runs:\n"));
+    assert_eq!(preview.lines().count(), 7);
+    assert!(harness.telegram.ask("/previewschedule whenever").await.starts_with("Wrong input... "));
+
+    assert!(harness.telegram.ask("/resettasks").await.starts_with(CONFIRMATION));
+    assert_eq!(list_tasks(&mut harness.telegram).await.len(), 2);
+}
+
+#[tokio::test]
+pub async fn test_history_chart_and_export_read_the_history() {
+    let mut harness = harness().await;
+    let history = History::open(&harness.config.history_db_path).unwrap();
+    let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+    history.record(&GardenEvent::MoistureRead(MoistureReading { at: an_hour_ago, zone: "garden".to_string(), moisture: 40.0 })).unwrap();
+    history.record(&GardenEvent::WateringFinished(WateringRun { started_at: an_hour_ago, zone: "garden".to_string(), seconds: 60, trigger: Trigger::Schedule })).unwrap();
+
+    let summary = harness.telegram.ask("/history garden 7d").await;
+    assert!(summary.contains("avg 40.0%"));
+    assert!(summary.contains("Watering runs: 1 (0 failed, 0 stopped), 60 seconds in total"));
+    assert!(harness.telegram.ask("/history lawn").await.starts_with("Wrong input... lawn is neither"));
+
+    harness.telegram.send("/chart garden 7d");
+    let chart = harness.telegram.next_sent().await;
+    assert!(chart.method.eq_ignore_ascii_case("sendPhoto"));
+    assert!(chart.body.contains("chart.png"));
+    assert!(chart.body.contains("1 readings, 1 watering runs"));
+
+    harness.telegram.send("/export 7d json garden");
+    let export = harness.telegram.next_sent().await;
+    assert!(export.method.eq_ignore_ascii_case("sendDocument"));
+    assert!(export.body.contains("garden_7d.json"));
+
+    std::fs::remove_file(&harness.config.history_db_path).unwrap_or_default();
+}
+
+#[tokio::test]
+pub async fn test_notification_commands_reach_the_notifier() {
+    let mut harness = harness().await;
+    let chat = ChatId(CHAT_ID);
+
+    assert!(harness.telegram.ask("/subscribe").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/quiethours 22:00-07:00").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/quiethours tonight").await.starts_with("Wrong input... tonight is not valid"));
+    assert!(harness.telegram.ask("/digest 20:00").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/digest off").await.starts_with(CONFIRMATION));
+    assert!(harness.telegram.ask("/unsubscribe").await.starts_with(CONFIRMATION));
+
+    let messages: Vec<_> = harness.notifier_channel_receiver.try_iter().collect();
+    assert_eq!(messages.len(), 5);
+    assert!(matches!(messages[0], NotifierChannelMessage::Subscribe(id) if id == chat));
+    assert!(matches!(messages[1], NotifierChannelMessage::SetQuietHours(id, Some(_)) if id == chat));
+    assert!(matches!(messages[2], NotifierChannelMessage::SetDigest(id, Some(at)) if id == chat && at == NaiveTime::from_hms(20, 0, 0)));
+    assert!(matches!(messages[3], NotifierChannelMessage::SetDigest(id, None) if id == chat));
+    assert!(matches!(messages[4], NotifierChannelMessage::Unsubscribe(id) if id == chat));
+}
+
+#[tokio::test]
+pub async fn test_shutdown_replies_before_shutting_down() {
+    let mut harness = harness().await;
+
+    assert!(harness.telegram.ask("/shutdown").await.starts_with("Shutting down..."));
+    // the reply goes out first, give the handler a moment to get to the shutdown
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(harness.shutdowns.load(Ordering::SeqCst), 1);
+}
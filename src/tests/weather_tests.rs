@@ -0,0 +1,129 @@
+use crate::config::{Config};
+use crate::definitions::{GardenEvent, Trigger, WateringRequest, WorkerChannelMessage};
+use crate::error::{GardenError, GardenResult};
+use crate::tests::{WorkerHarness};
+use crate::weather::{FileWeather, HttpWeather, WeatherDecision, WeatherProvider, WeatherSample, decide};
+
+use axum::{Json, Router};
+use axum::routing::get;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use job_scheduler::Uuid;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn now() -> DateTime<Utc> {
+    Utc.ymd(2022, 6, 1).and_hms(6, 0, 0)
+}
+
+fn sample(hours: i64, rain_mm: f64, temperature_celsius: f64) -> WeatherSample {
+    WeatherSample { at: now() + chrono::Duration::hours(hours), rain_mm, temperature_celsius }
+}
+
+fn temp_file(extension: &str, contents: &str) -> String {
+    let path = std::env::temp_dir().join(format!("smart_garden_weather_{}.{}", Uuid::new_v4().to_simple(), extension)).to_string_lossy().to_string();
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Answers with the given samples, or fails without any.
+struct FakeWeather(Option<Vec<WeatherSample>>);
+
+impl WeatherProvider for FakeWeather {
+    fn samples(&self) -> BoxFuture<'static, GardenResult<Vec<WeatherSample>>> {
+        let samples = self.0.clone().ok_or_else(|| GardenError::Weather { source: "fake".to_string(), reason: "offline".to_string() });
+        async move { samples }.boxed()
+    }
+}
+
+#[test]
+pub fn test_rain_in_the_last_day_or_the_forecast_skips_watering() {
+    let config = Config::new();
+
+    let fell = [sample(-30, 20.0, 20.0), sample(-10, 3.0, 20.0), sample(-2, 2.5, 20.0)];
+    assert_eq!(decide(&fell, now(), &config), WeatherDecision::Skip("5.5 mm of rain fell in the last 24 hours".to_string()));
+
+    let forecast = [sample(-2, 1.0, 20.0), sample(6, 6.0, 20.0), sample(20, 30.0, 20.0)];
+    assert_eq!(decide(&forecast, now(), &config), WeatherDecision::Skip("6.0 mm of rain is forecast in the next 12 hours".to_string()));
+
+    // rain older than a day or further ahead than 12 hours doesn't count
+    let dry = [sample(-30, 20.0, 20.0), sample(-1, 4.0, 20.0), sample(13, 30.0, 20.0)];
+    assert_eq!(decide(&dry, now(), &config), WeatherDecision::Water { scale: 1.0 });
+}
+
+#[test]
+pub fn test_temperature_scales_watering_within_limits() {
+    let config = Config::new();
+
+    assert_eq!(decide(&[], now(), &config), WeatherDecision::Water { scale: 1.0 });
+
+    match decide(&[sample(-3, 0.0, 28.0), sample(3, 0.0, 32.0)], now(), &config) {
+        WeatherDecision::Water { scale } => assert!((scale - 1.3).abs() < 1e-9),
+        other => panic!("unexpected decision {:?}", other)
+    }
+
+    assert_eq!(decide(&[sample(0, 0.0, 45.0)], now(), &config), WeatherDecision::Water { scale: 1.5 });
+    assert_eq!(decide(&[sample(0, 0.0, -5.0)], now(), &config), WeatherDecision::Water { scale: 0.5 });
+}
+
+#[tokio::test]
+pub async fn test_file_weather_reads_json_and_csv() {
+    let json = temp_file("json", r#"[{"at": "2022-06-01T05:00:00Z", "rain_mm": 1.5, "temperature_celsius": 18.0}]"#);
+    assert_eq!(FileWeather::new(&json).samples().await.unwrap(), vec![sample(-1, 1.5, 18.0)]);
+
+    let csv = temp_file("csv", "at,rain_mm,temperature_celsius\n2022-06-01T05:00:00Z,1.5,18\n2022-06-01T09:00:00+02:00,0,21.5\n");
+    assert_eq!(FileWeather::new(&csv).samples().await.unwrap(), vec![sample(-1, 1.5, 18.0), sample(1, 0.0, 21.5)]);
+
+    let bad = temp_file("csv", "time,rain\n2022-06-01T05:00:00Z,1.5\n");
+    assert!(matches!(FileWeather::new(&bad).samples().await, Err(GardenError::Weather { .. })));
+    assert!(matches!(FileWeather::new("/nonexistent/weather.json").samples().await, Err(GardenError::Weather { .. })));
+
+    for path in [json, csv, bad] {
+        std::fs::remove_file(path).unwrap_or_default();
+    }
+}
+
+#[tokio::test]
+pub async fn test_http_weather_reads_a_local_endpoint() {
+    let app = Router::new()
+        .route("/weather", get(|| async { Json(json!([{ "at": "2022-06-01T07:00:00Z", "rain_mm": 0.2, "temperature_celsius": 24.0 }])) }));
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+    let address = server.local_addr();
+    tokio::spawn(server);
+
+    let samples = HttpWeather::new(&format!("http://{}/weather", address)).samples().await.unwrap();
+    assert_eq!(samples, vec![sample(1, 0.2, 24.0)]);
+
+    let missing = HttpWeather::new(&format!("http://{}/forecast", address)).samples().await;
+    assert!(matches!(missing, Err(GardenError::Weather { .. })));
+}
+
+async fn scheduled_watering(weather: FakeWeather) -> Vec<GardenEvent> {
+    let config: &'static Config = Box::leak(Box::new(Config::simulation()));
+    let mut harness = WorkerHarness::spawn(config, now(), |worker| worker.with_weather(Arc::new(weather)));
+    harness.send(WorkerChannelMessage::WaterPlants(WateringRequest { zone: None, seconds: 60, trigger: Trigger::Schedule })).await;
+
+    let mut events = Vec::new();
+    while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(100), harness.event_receiver.recv()).await {
+        let started = matches!(event, GardenEvent::WateringStarted(_) | GardenEvent::WateringSkipped(_));
+        events.push(event);
+        if started {
+            break;
+        }
+    }
+    events
+}
+
+#[tokio::test]
+pub async fn test_scheduled_watering_follows_the_weather() {
+    let events = scheduled_watering(FakeWeather(Some(vec![sample(-1, 12.0, 20.0)]))).await;
+    assert!(matches!(&events[..], [GardenEvent::WateringSkipped(skipped)] if skipped.reason == "12.0 mm of rain fell in the last 24 hours"));
+
+    let events = scheduled_watering(FakeWeather(Some(vec![sample(-1, 0.0, 30.0)]))).await;
+    assert!(matches!(&events[..], [GardenEvent::WateringStarted(run)] if run.seconds == 78));
+
+    // no weather is no reason to let the plants dry out
+    let events = scheduled_watering(FakeWeather(None)).await;
+    assert!(matches!(&events[..], [GardenEvent::Failure(_), GardenEvent::WateringStarted(run)] if run.seconds == 60));
+}
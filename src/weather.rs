@@ -0,0 +1,151 @@
+// standard
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// internal
+use crate::config::{Config};
+use crate::error::{GardenError, GardenResult};
+
+// external
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize};
+
+
+/// Rain and temperature for one hour, observed or forecast.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WeatherSample {
+    pub at: DateTime<Utc>,
+    pub rain_mm: f64,
+    pub temperature_celsius: f64
+}
+
+/// Where the garden gets recent and forecast weather from, so scheduled watering can be skipped or scaled.
+pub trait WeatherProvider: Send + Sync {
+    /// Observations and forecasts around now, in any order.
+    fn samples(&self) -> BoxFuture<'static, GardenResult<Vec<WeatherSample>>>;
+}
+
+pub type SharedWeather = Arc<dyn WeatherProvider>;
+
+/// The weather providers that work without the internet, picked in `Config::new`.
+#[derive(Debug, Clone)]
+pub enum WeatherSource {
+    /// A `.json` or `.csv` file, e.g. written by a local weather station.
+    File(String),
+    /// A local HTTP endpoint answering with the same JSON as the file.
+    Http(String)
+}
+
+impl WeatherSource {
+    pub fn provider(&self) -> SharedWeather {
+        match self {
+            WeatherSource::File(path) => Arc::new(FileWeather::new(path)),
+            WeatherSource::Http(url) => Arc::new(HttpWeather::new(url))
+        }
+    }
+}
+
+/// Reads a JSON array of samples, or a CSV file with an `at,rain_mm,temperature_celsius` header.
+pub struct FileWeather {
+    path: String
+}
+
+impl FileWeather {
+    pub fn new(path: &str) -> Self {
+        FileWeather { path: path.to_string() }
+    }
+}
+
+impl WeatherProvider for FileWeather {
+    fn samples(&self) -> BoxFuture<'static, GardenResult<Vec<WeatherSample>>> {
+        let path = self.path.clone();
+        async move {
+            let invalid = |reason: String| GardenError::Weather { source: path.clone(), reason };
+            let input = tokio::fs::read_to_string(&path).await.map_err(|error| invalid(error.to_string()))?;
+            match Path::new(&path).extension().and_then(|extension| extension.to_str()) {
+                Some("csv") => parse_csv(&input).map_err(invalid),
+                _ => serde_json::from_str(&input).map_err(|error| invalid(error.to_string()))
+            }
+        }.boxed()
+    }
+}
+
+fn parse_csv(input: &str) -> Result<Vec<WeatherSample>, String> {
+    let mut lines = input.lines().map(str::trim).filter(|line| !line.is_empty());
+    match lines.next() {
+        Some("at,rain_mm,temperature_celsius") => {},
+        _ => return Err("expected an at,rain_mm,temperature_celsius header".to_string())
+    }
+
+    lines.map(|line| {
+        let invalid = || format!("{} is not a valid sample", line);
+        match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+            [at, rain_mm, temperature_celsius] => Ok(WeatherSample {
+                at: DateTime::parse_from_rfc3339(at).map_err(|_| invalid())?.with_timezone(&Utc),
+                rain_mm: rain_mm.parse().map_err(|_| invalid())?,
+                temperature_celsius: temperature_celsius.parse().map_err(|_| invalid())?
+            }),
+            _ => Err(invalid())
+        }
+    }).collect()
+}
+
+/// Asks a local HTTP endpoint for a JSON array of samples.
+pub struct HttpWeather {
+    url: String,
+    client: reqwest::Client
+}
+
+impl HttpWeather {
+    pub fn new(url: &str) -> Self {
+        HttpWeather { url: url.to_string(), client: reqwest::Client::new() }
+    }
+}
+
+impl WeatherProvider for HttpWeather {
+    fn samples(&self) -> BoxFuture<'static, GardenResult<Vec<WeatherSample>>> {
+        let url = self.url.clone();
+        // a slow weather station must not hold up watering for long
+        let request = self.client.get(&url).timeout(Duration::from_secs(10)).send();
+        async move {
+            let invalid = |error: reqwest::Error| GardenError::Weather { source: url.clone(), reason: error.to_string() };
+            let response = request.await.and_then(|response| response.error_for_status()).map_err(invalid)?;
+            response.json::<Vec<WeatherSample>>().await.map_err(invalid)
+        }.boxed()
+    }
+}
+
+/// What to do about a scheduled watering run given the weather.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WeatherDecision {
+    /// Water for `scale` times the usual duration.
+    Water { scale: f64 },
+    Skip(String)
+}
+
+/// Skips when it rained, or is forecast to rain, more than `weather_skip_rain_mm`, otherwise scales
+/// the duration by the average temperature from 24 hours ago to 12 hours ahead.
+pub fn decide(samples: &[WeatherSample], now: DateTime<Utc>, config: &Config) -> WeatherDecision {
+    let past = now - chrono::Duration::hours(24);
+    let ahead = now + chrono::Duration::hours(12);
+
+    let rain = |from: DateTime<Utc>, to: DateTime<Utc>| samples.iter().filter(|sample| sample.at > from && sample.at <= to).map(|sample| sample.rain_mm).sum::<f64>();
+    let rain_fell = rain(past, now);
+    if rain_fell > config.weather_skip_rain_mm {
+        return WeatherDecision::Skip(format!("{:.1} mm of rain fell in the last 24 hours", rain_fell));
+    }
+    let rain_forecast = rain(now, ahead);
+    if rain_forecast > config.weather_skip_rain_mm {
+        return WeatherDecision::Skip(format!("{:.1} mm of rain is forecast in the next 12 hours", rain_forecast));
+    }
+
+    let temperatures: Vec<_> = samples.iter().filter(|sample| sample.at > past && sample.at <= ahead).map(|sample| sample.temperature_celsius).collect();
+    if temperatures.is_empty() {
+        return WeatherDecision::Water { scale: 1.0 };
+    }
+    let temperature = temperatures.iter().sum::<f64>() / temperatures.len() as f64;
+    let scale = 1.0 + (temperature - config.weather_base_temperature_celsius) * config.weather_scale_percent_per_degree / 100.0;
+    WeatherDecision::Water { scale: scale.clamp(config.weather_min_scale, config.weather_max_scale) }
+}
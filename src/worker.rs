@@ -1,60 +1,298 @@
 // standard
-use std::thread;
-use std::time::Duration;
-use std::thread::sleep;
-
+use std::collections::VecDeque;
 
 // internal
+use crate::clock::{SharedClock};
 use crate::config::{Config};
-use crate::definitions::{WorkerChannelMessage, PeriodicTaskType};
+use crate::definitions::{WorkerChannelMessage, PeriodicTaskType, GardenEvent, Zone, Trigger, WateringRequest, WateringRun, MoistureReading, SkippedRun, Traced};
+use crate::error::{GardenResult, MIN_WATERING_SECONDS, MAX_WATERING_SECONDS};
+use crate::hardware::{Hardware};
+use crate::rain::{SharedRain};
+use crate::weather::{SharedWeather, WeatherDecision, decide};
 
 // external
-use crossbeam_channel::{Receiver};
-use sysfs_gpio::{Direction, Pin};
-
-
-const WATER_PUMP_PIN: u64 = 4;
-
-fn handle_commands(cmd: WorkerChannelMessage, config: &Config) {
-    match cmd {
-        WorkerChannelMessage::PeriodicTask(task) => {
-            match task.task_type {
-                PeriodicTaskType::WaterPlants => {
-                    let water_pump = Pin::new(WATER_PUMP_PIN);
-                    let result = water_pump.with_exported(|| {
-                        water_pump.set_direction(Direction::Out).unwrap();
-                        water_pump.set_value(1).unwrap();
-                        sleep(Duration::from_secs(config.water_pump_working_cycle_seconds));
-                        water_pump.set_value(0).unwrap();
-                        Ok(())
-                    });
-
-                    match result {
-                        Err(_) => {
-                            println!("Tried to water plants but failed to connect or turn on the water pump");
-                        },
-                        _ => {}
-                    }
-                },
-                _ => {}
-            }
-        },
-        _ => {}
-    }
+use tokio::sync::{broadcast::Sender, mpsc::Receiver};
+use tracing::{error, info, info_span, warn, Instrument};
+
+
+/// How a watering cycle ended.
+#[derive(Debug, PartialEq, Eq)]
+enum Cycle {
+    Completed,
+    /// Only this zone was stopped.
+    ZoneStopped,
+    /// Everything was stopped, the remaining zones are skipped too.
+    Stopped
+}
+
+pub struct Worker {
+    config: &'static Config,
+    clock: SharedClock,
+    hardware: Hardware,
+    worker_channel_receiver: Receiver<Traced<WorkerChannelMessage>>,
+    event_sender: Sender<GardenEvent>,
+    /// Asked before every scheduled watering run, if set.
+    weather: Option<SharedWeather>,
+    /// Holds back scheduled watering while it rains, if there is a rain sensor.
+    rain: Option<SharedRain>,
+    /// Commands that arrived while the pump was running.
+    backlog: VecDeque<Traced<WorkerChannelMessage>>
 }
 
-pub struct Worker {} impl Worker {
-    pub fn run_threaded(
+impl Worker {
+    pub fn new(
         config: &'static Config,
-        worker_channel_receiver: Receiver<WorkerChannelMessage>
+        clock: SharedClock,
+        hardware: Hardware,
+        worker_channel_receiver: Receiver<Traced<WorkerChannelMessage>>,
+        event_sender: Sender<GardenEvent>
+    ) -> Self {
+        Worker {
+            config,
+            clock,
+            hardware,
+            worker_channel_receiver,
+            event_sender,
+            weather: None,
+            rain: None,
+            backlog: VecDeque::new()
+        }
+    }
+
+    pub fn with_weather(mut self, weather: SharedWeather) -> Self {
+        self.weather = Some(weather);
+        self
+    }
+
+    pub fn with_rain(mut self, rain: SharedRain) -> Self {
+        self.rain = Some(rain);
+        self
+    }
+
+    pub async fn run_async(
+        config: &'static Config,
+        clock: SharedClock,
+        hardware: Hardware,
+        worker_channel_receiver: Receiver<Traced<WorkerChannelMessage>>,
+        event_sender: Sender<GardenEvent>
     ) {
-        thread::spawn(move || {
-            loop {
-                if let Ok(cmd) = worker_channel_receiver.try_recv() {
-                    handle_commands(cmd, &config);
+        let mut worker = Worker::new(config, clock, hardware, worker_channel_receiver, event_sender);
+        if let Some(source) = &config.weather {
+            worker = worker.with_weather(source.provider());
+        }
+        worker.run().await;
+    }
+
+    /// Works through the backlog first, then waits for the next command.
+    pub async fn run(&mut self) {
+        loop {
+            let cmd = match self.backlog.pop_front() {
+                Some(cmd) => cmd,
+                None => match self.worker_channel_receiver.recv().await {
+                    Some(cmd) => cmd,
+                    None => break
                 }
-                std::thread::sleep(Duration::from_millis(1000));
+            };
+            self.handle_commands(cmd).await;
+        }
+    }
+
+    fn publish(&self, event: GardenEvent) {
+        // nobody listening is not an error
+        self.event_sender.send(event).unwrap_or_default();
+    }
+
+    async fn handle_commands(&mut self, cmd: Traced<WorkerChannelMessage>) {
+        let span = info_span!("worker", correlation_id = %cmd.correlation_id);
+        self.handle_message(cmd.message).instrument(span).await;
+    }
+
+    async fn handle_message(&mut self, message: WorkerChannelMessage) {
+        info!(?message, "Processing command");
+
+        match message {
+            WorkerChannelMessage::PeriodicTask(task) => {
+                match task.task_type {
+                    PeriodicTaskType::WaterPlants => {
+                        let request = WateringRequest { zone: None, seconds: self.config.water_pump_working_cycle_seconds, trigger: Trigger::Schedule };
+                        self.water_plants(request).await;
+                    },
+                    PeriodicTaskType::ReadMoistureSensorsData => {
+                        self.read_moisture_sensors();
+                    }
+                }
+            },
+            WorkerChannelMessage::WaterPlants(request) => {
+                self.water_plants(request).await;
+            },
+            WorkerChannelMessage::Stop => {
+                self.drop_queued_runs();
+            },
+            WorkerChannelMessage::StopZone(zone) => {
+                info!(%zone, "Nothing is watering, nothing to stop");
+            }
+        }
+    }
+
+    /// Queues a command that arrived while the pump was running. A periodic task that is queued already
+    /// isn't queued twice, and past `messages_in_flight_limit` commands are dropped, so a schedule that
+    /// fires faster than the pump runs can't pile up.
+    fn queue(&mut self, cmd: Traced<WorkerChannelMessage>) {
+        if let Some(task_type) = scheduled_task(&cmd.message) {
+            if self.backlog.iter().any(|queued| scheduled_task(&queued.message).as_ref() == Some(&task_type)) {
+                info!(correlation_id = %cmd.correlation_id, task = %task_type, "Periodic task is queued already");
+                return;
             }
+        }
+
+        if self.backlog.len() >= self.config.messages_in_flight_limit {
+            warn!(correlation_id = %cmd.correlation_id, message = ?cmd.message, "Too many commands are waiting, dropping");
+            self.publish(GardenEvent::Failure(format!("the worker is busy, {:?} was dropped", cmd.message)));
+            return;
+        }
+
+        self.backlog.push_back(cmd);
+    }
+
+    fn drop_queued_runs(&mut self) {
+        self.backlog.retain(|cmd| match &cmd.message {
+            WorkerChannelMessage::WaterPlants(_) => false,
+            WorkerChannelMessage::PeriodicTask(task) => !matches!(task.task_type, PeriodicTaskType::WaterPlants),
+            WorkerChannelMessage::Stop | WorkerChannelMessage::StopZone(_) => false
         });
     }
-}
\ No newline at end of file
+
+    async fn water_plants(&mut self, request: WateringRequest) {
+        let seconds = match request.trigger {
+            Trigger::Schedule => match self.schedule_decision().await {
+                WeatherDecision::Water { scale } => ((request.seconds as f64 * scale).round() as u64).clamp(MIN_WATERING_SECONDS, MAX_WATERING_SECONDS),
+                WeatherDecision::Skip(reason) => {
+                    info!(%reason, "Skipping the scheduled watering");
+                    self.publish(GardenEvent::WateringSkipped(SkippedRun { at: self.clock.now(), reason }));
+                    return;
+                }
+            },
+            _ => request.seconds
+        };
+
+        let zones: Vec<_> = match &request.zone {
+            Some(name) => self.config.zone(name).into_iter().cloned().collect(),
+            None => self.config.zones.clone()
+        };
+
+        for zone in zones {
+            if !self.water_zone(&zone, seconds, request.trigger).await {
+                break;
+            }
+        }
+    }
+
+    /// The rain sensor goes first, it is on site and cheap to ask.
+    async fn schedule_decision(&self) -> WeatherDecision {
+        let raining = self.rain.as_ref().and_then(|rain| rain.read().unwrap().suppression(self.clock.now(), self.config));
+        match raining {
+            Some(reason) => WeatherDecision::Skip(reason),
+            None => self.weather_decision().await
+        }
+    }
+
+    /// Without a provider, or when it fails, scheduled runs water as usual.
+    async fn weather_decision(&self) -> WeatherDecision {
+        let weather = match &self.weather {
+            Some(weather) => weather,
+            None => return WeatherDecision::Water { scale: 1.0 }
+        };
+
+        match weather.samples().await {
+            Ok(samples) => decide(&samples, self.clock.now(), self.config),
+            Err(error) => {
+                warn!(%error, "Watering as scheduled without the weather");
+                self.publish(GardenEvent::Failure(format!("watering as scheduled, {}", error)));
+                WeatherDecision::Water { scale: 1.0 }
+            }
+        }
+    }
+
+    /// Waits for the watering cycle of `zone` to finish or to be stopped.
+    async fn wait_for_cycle(&mut self, zone: &str, seconds: u64) -> Cycle {
+        let deadline = self.clock.now() + chrono::Duration::seconds(seconds as i64);
+
+        loop {
+            tokio::select! {
+                _ = self.clock.sleep_until(deadline) => return Cycle::Completed,
+                cmd = self.worker_channel_receiver.recv() => match cmd {
+                    Some(Traced { correlation_id, message: WorkerChannelMessage::Stop }) => {
+                        info!(stopped_by = %correlation_id, "Stopping the watering cycle");
+                        self.drop_queued_runs();
+                        return Cycle::Stopped;
+                    },
+                    Some(Traced { correlation_id, message: WorkerChannelMessage::StopZone(stopped) }) => {
+                        if stopped == zone {
+                            info!(stopped_by = %correlation_id, %zone, "Stopping the zone");
+                            return Cycle::ZoneStopped;
+                        }
+                        // the zone may have finished already, stopping it must not stop another one
+                        info!(stopped_by = %correlation_id, zone = %stopped, "The zone is not being watered, nothing to stop");
+                    },
+                    Some(cmd) => self.queue(cmd),
+                    None => {
+                        self.clock.sleep_until(deadline).await;
+                        return Cycle::Completed;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The pump is turned off however the cycle ends.
+    async fn run_pump(&mut self, zone: &Zone, seconds: u64) -> GardenResult<Cycle> {
+        self.hardware.actuator.set_pump(zone, true)?;
+        let cycle = self.wait_for_cycle(&zone.name, seconds).await;
+        self.hardware.actuator.set_pump(zone, false)?;
+        Ok(cycle)
+    }
+
+    /// Returns `false` if everything was stopped, so the remaining zones are skipped too.
+    async fn water_zone(&mut self, zone: &Zone, seconds: u64, trigger: Trigger) -> bool {
+        let run = WateringRun { started_at: self.clock.now(), zone: zone.name.clone(), seconds, trigger };
+        info!(zone = %zone.name, seconds, %trigger, "Watering");
+        self.publish(GardenEvent::WateringStarted(run.clone()));
+
+        let (event, carry_on) = match self.run_pump(zone, seconds).await {
+            Ok(Cycle::Completed) => (GardenEvent::WateringFinished(run), true),
+            Ok(Cycle::ZoneStopped) => (GardenEvent::WateringStopped(run), true),
+            Ok(Cycle::Stopped) => (GardenEvent::WateringStopped(run), false),
+            Err(err) => {
+                error!(zone = %zone.name, error = %err, "Failed to run the water pump");
+                (GardenEvent::WateringFailed(run, err.to_string()), true)
+            }
+        };
+        self.publish(event);
+
+        carry_on
+    }
+
+    fn read_moisture_sensors(&self) {
+        for zone in &self.config.zones {
+            if zone.moisture_sensor_pin.is_some() {
+                let event = match self.hardware.moisture_sensor.read_moisture(zone) {
+                    Ok(moisture) => GardenEvent::MoistureRead(MoistureReading { at: self.clock.now(), zone: zone.name.clone(), moisture }),
+                    Err(error) => {
+                        warn!(zone = %zone.name, %error, "Failed to read the moisture sensor");
+                        GardenEvent::SensorFailed(zone.name.clone(), error.to_string())
+                    }
+                };
+                self.publish(event);
+            }
+        }
+    }
+}
+
+/// Which periodic task a command runs, scheduled watering with a duration from the schedule included.
+fn scheduled_task(message: &WorkerChannelMessage) -> Option<PeriodicTaskType> {
+    match message {
+        WorkerChannelMessage::PeriodicTask(task) => Some(task.task_type.clone()),
+        WorkerChannelMessage::WaterPlants(request) if request.trigger == Trigger::Schedule => Some(PeriodicTaskType::WaterPlants),
+        _ => None
+    }
+}